@@ -1,3 +1,5 @@
+use std::{collections::HashMap, net::IpAddr};
+
 use log::error;
 use reedline::{
     default_emacs_keybindings, ColumnarMenu, DefaultCompleter, Emacs, FileBackedHistory, KeyCode, KeyModifiers, MenuBuilder, Reedline, ReedlineEvent, ReedlineMenu, Signal
@@ -5,17 +7,24 @@ use reedline::{
 
 use crate::{
     cli::{command_manager::CommandManager, commands::Cli, hydraad_prompt::HydraAdPrompt},
-    data::{Credential, DomainController},
+    data::{credential::AuthMethod, Credential, DomainController},
     stores::{credentials_store::CredentialsStore, domain_controller_store::DomainControllerStore},
-    utils::cli_utils::{clear_screen, list_all_commands},
+    utils::{
+        cli_utils::{clear_screen, list_all_commands},
+        ldap_session::LdapSession,
+    },
 };
 
+/// LDAP sessions are cached per domain controller, credential and authentication method
+type LdapSessionKey = (IpAddr, String, AuthMethod);
+
 pub struct App {
     prompt: HydraAdPrompt,
     pub domain_controller_storage: DomainControllerStore,
     pub credential_storage: CredentialsStore,
     pub current_used_dc: Option<DomainController>,
     pub current_used_creds: Option<Credential>,
+    ldap_sessions: HashMap<LdapSessionKey, LdapSession>,
 }
 
 impl App {
@@ -26,6 +35,7 @@ impl App {
             credential_storage: CredentialsStore::new(),
             current_used_dc: None,
             current_used_creds: None,
+            ldap_sessions: HashMap::new(),
         }
     }
 
@@ -68,6 +78,32 @@ impl App {
         Err("Please set a domain controller and a credential".to_string())
     }
 
+    pub fn get_current_dc(&self) -> Result<DomainController, String> {
+        self.current_used_dc
+            .clone()
+            .ok_or("Please set a domain controller".to_string())
+    }
+
+    /// LDAP session of the current domain controller and credential, reusing the open connection
+    pub async fn ldap_session(&mut self, auth: AuthMethod) -> Result<LdapSession, String> {
+        let (dc, creds) = self.get_current_context()?;
+        let key = (dc.ip_address, creds.id.clone(), auth);
+        if let Some(session) = self.ldap_sessions.get_mut(&key) {
+            if !session.is_closed() {
+                return Ok(session.clone());
+            }
+        }
+
+        let session = LdapSession::connect(&self.domain_controller_storage, &dc, &creds, auth).await?;
+        self.ldap_sessions.insert(key, session.clone());
+        Ok(session)
+    }
+
+    /// Drop the cached LDAP sessions of a domain controller
+    pub fn close_ldap_sessions(&mut self, dc: &DomainController) {
+        self.ldap_sessions.retain(|(ip, _, _), _| *ip != dc.ip_address);
+    }
+
     pub async fn before_run(&mut self, file_path: &str) -> Result<bool, String> {
         match std::fs::read_to_string(file_path) {
             Ok(content) => {
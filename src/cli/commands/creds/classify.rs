@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use clap::Args;
+
+use crate::{
+    app::App,
+    cli::commands::Command,
+    data::{credential::AuthMethod, AuthData, Credential, CredentialType},
+    utils::{
+        ldap_groups::{find_object, is_privileged_group, member_of, DirectoryObject},
+        ldap_operations::sam_account_name,
+        ldap_session::LdapSession,
+    },
+};
+
+#[derive(Debug, Args)]
+pub struct ClassifyArgs {
+    /// Classify every credential in the store instead of only the current one
+    #[arg(short, long)]
+    pub all: bool,
+
+    /// Authentication protocol for LDAP
+    #[arg(long, value_enum, default_value_t = AuthMethod::Auto)]
+    pub auth: AuthMethod,
+}
+
+/// Directory data of a principal needed for classification
+#[derive(Debug, Clone, Default)]
+pub struct PrincipalInfo {
+    pub principal: DirectoryObject,
+    /// Recursive group memberships, primary group included, as (name, rid)
+    pub groups: Vec<(String, Option<u32>)>,
+}
+
+impl PrincipalInfo {
+    fn is_member_of_rid(&self, rid: u32) -> bool {
+        self.groups.iter().any(|(_, group_rid)| *group_rid == Some(rid))
+    }
+
+    /// Decide the credential type of the principal
+    pub fn credential_type(&self) -> CredentialType {
+        if self.principal.has_object_class("msDS-GroupManagedServiceAccount") {
+            CredentialType::GroupManagedServiceAccount
+        } else if self.principal.has_object_class("msDS-ManagedServiceAccount") {
+            CredentialType::ManagedServiceAccount
+        } else if self.principal.has_object_class("computer") || self.principal.sam_account_name.ends_with('$') {
+            CredentialType::MachineAccount
+        } else if self.principal.sam_account_name.eq_ignore_ascii_case("krbtgt")
+            || matches!(self.principal.rid(), Some(500) | Some(502))
+        {
+            CredentialType::BuiltIn
+        } else if self.is_member_of_rid(519) {
+            CredentialType::EnterpriseAdmin
+        } else if self.is_member_of_rid(512) {
+            CredentialType::DomainAdmin
+        } else if !self.principal.service_principal_names.is_empty() {
+            CredentialType::ServiceAccount
+        } else {
+            CredentialType::DomainUser
+        }
+    }
+
+    /// Privileged group memberships of the principal
+    pub fn privileges(&self) -> Vec<String> {
+        self.groups
+            .iter()
+            .filter(|(name, rid)| is_privileged_group(name, *rid))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+}
+
+/// Hashes and keys are often dumped for machine accounts under the host name,
+/// without the trailing $ of the sAMAccountName
+fn may_be_machine_secret(creds: &Credential) -> bool {
+    !creds.username.ends_with('$')
+        && matches!(
+            creds.auth_data,
+            AuthData::NtlmHash(_) | AuthData::LmNtlm { .. } | AuthData::KerberosKey { .. }
+        )
+}
+
+/// Look a principal and its recursive group memberships up over LDAP. The
+/// computer account is only tried when no account has the exact name.
+async fn lookup_principal(ldap: &mut LdapSession, creds: &Credential) -> Result<PrincipalInfo, String> {
+    let principal = match find_object(ldap, &creds.username).await {
+        Ok(principal) => principal,
+        Err(e) if may_be_machine_secret(creds) => {
+            let machine = format!("{}$", sam_account_name(&creds.username));
+            find_object(ldap, &machine).await.map_err(|_| e)?
+        }
+        Err(e) => return Err(e),
+    };
+    let groups = member_of(ldap, &principal).await?;
+
+    Ok(PrincipalInfo {
+        principal,
+        groups: groups.iter().map(|group| (group.name.clone(), group.rid())).collect(),
+    })
+}
+
+impl Command for ClassifyArgs {
+    async fn execute(&self, app: &mut App) -> Result<bool, String> {
+        let (dc, creds) = app.get_current_context()?;
+
+        // Credentials of other domains can't be looked up in this directory
+        let targets = if self.all {
+            app.credential_storage()
+                .get_all_credentials()
+                .into_iter()
+                .filter(|c| c.target_dc.as_ref().is_none_or(|d| d.eq_ignore_ascii_case(&dc.domain_name)))
+                .collect()
+        } else {
+            vec![creds.clone()]
+        };
+
+        let mut ldap = app.ldap_session(self.auth).await?;
+
+        // Several credentials may belong to the same principal, look each one up once
+        let mut lookups: HashMap<(String, bool), Option<PrincipalInfo>> = HashMap::new();
+        let mut classified = 0;
+        for target in targets {
+            let key = (sam_account_name(&target.username).to_lowercase(), may_be_machine_secret(&target));
+            if !lookups.contains_key(&key) {
+                let info = match lookup_principal(&mut ldap, &target).await {
+                    Ok(info) => Some(info),
+                    Err(e) => {
+                        println!("Skipping {}: {}", target.username, e);
+                        None
+                    }
+                };
+                lookups.insert(key.clone(), info);
+            }
+            let Some(info) = lookups.get(&key).cloned().flatten() else {
+                continue;
+            };
+
+            let mut updated: Credential = target.clone();
+            updated.credential_type = info.credential_type();
+            updated.privileges = info.privileges();
+            updated.target_dc = Some(dc.domain_name.clone());
+
+            println!(
+                "{} -> {:?} [{}]",
+                updated.username,
+                updated.credential_type,
+                updated.privileges.join(", ")
+            );
+
+            app.credential_storage().update_credential(&target.id, updated.clone())?;
+            if app.current_used_creds.as_ref().map_or(false, |c| c.id == target.id) {
+                app.set_current_creds(Some(updated));
+            }
+            classified += 1;
+        }
+
+        println!("Classified {} credentials", classified);
+        Ok(false)
+    }
+}
@@ -0,0 +1,88 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use himmelblau_kerberos_constants::etypes::{AES128_CTS_HMAC_SHA1_96, AES256_CTS_HMAC_SHA1_96, RC4_HMAC};
+
+use crate::{
+    app::App,
+    cli::commands::Command,
+    data::{AuthData, Credential},
+    kerberos::{
+        etype_name,
+        keytab::{build_keytab, KeytabEntry},
+        ticket::long_term_key,
+    },
+    utils::ldap_operations::sam_account_name,
+};
+
+#[derive(Debug, Args)]
+pub struct ExportKeytabArgs {
+    /// Path of the keytab to write
+    pub path: PathBuf,
+
+    /// Principals to export (usernames of stored credentials), all principals with keys when omitted
+    #[arg(short, long = "principal")]
+    pub principals: Vec<String>,
+
+    /// Key version number of the entries (keytabs imported keep their own)
+    #[arg(long, default_value_t = 1)]
+    pub kvno: u32,
+}
+
+/// Keytab entries of a credential: every key a password derives to, or the stored key
+fn credential_entries(creds: &Credential, realm: &str, kvno: u32) -> Vec<KeytabEntry> {
+    let principal = sam_account_name(&creds.username);
+    let kvno = creds
+        .get_metadata("kvno")
+        .and_then(|kvno| kvno.parse().ok())
+        .unwrap_or(kvno);
+    [AES256_CTS_HMAC_SHA1_96, AES128_CTS_HMAC_SHA1_96, RC4_HMAC]
+        .into_iter()
+        .filter_map(|etype| long_term_key(creds, etype, realm).ok())
+        .map(|key| KeytabEntry::new(realm, &principal, kvno, key))
+        .collect()
+}
+
+impl Command for ExportKeytabArgs {
+    async fn execute(&self, app: &mut App) -> Result<bool, String> {
+        let default_realm = app.get_current_dc().ok().map(|dc| dc.domain_name);
+
+        let mut entries = Vec::new();
+        for creds in app.credential_storage().get_all_credentials() {
+            let principal = sam_account_name(&creds.username);
+            if !self.principals.is_empty()
+                && !self
+                    .principals
+                    .iter()
+                    .any(|p| p.eq_ignore_ascii_case(&principal) || p.eq_ignore_ascii_case(&creds.username))
+            {
+                continue;
+            }
+            if matches!(creds.auth_data, AuthData::KerberosTicket(_) | AuthData::Certificate { .. }) {
+                continue;
+            }
+            let Some(realm) = creds.target_dc.clone().or(default_realm.clone()) else {
+                println!("Skipping {}: unknown realm, set a domain controller", creds.username);
+                continue;
+            };
+
+            for entry in credential_entries(&creds, &realm, self.kvno) {
+                if entries
+                    .iter()
+                    .any(|e: &KeytabEntry| e.principal() == entry.principal() && e.realm == entry.realm && e.key == entry.key)
+                {
+                    continue;
+                }
+                println!("{}@{} kvno {} {}", entry.principal(), entry.realm, entry.kvno, etype_name(entry.key.keytype));
+                entries.push(entry);
+            }
+        }
+
+        if entries.is_empty() {
+            return Err("No stored password or key matches".to_string());
+        }
+        std::fs::write(&self.path, build_keytab(&entries)).map_err(|e| e.to_string())?;
+        println!("{} keys written to {}", entries.len(), self.path.display());
+        Ok(false)
+    }
+}
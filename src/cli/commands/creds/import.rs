@@ -0,0 +1,139 @@
+use std::{net::IpAddr, path::PathBuf};
+
+use clap::{Args, ValueEnum};
+
+use crate::{
+    app::App,
+    cli::commands::Command,
+    data::{AuthData, Credential, CredentialType, DomainController},
+    utils::nxc_workspace::{dns_domain, load_nxc_workspace, NxcDatabase, NxcUser},
+};
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum ImportFormat {
+    /// NetExec workspace directory or protocol database (smb.db, ldap.db, mssql.db)
+    Nxc,
+}
+
+#[derive(Debug, Args)]
+pub struct ImportArgs {
+    /// Import format
+    #[arg(short, long, value_enum, default_value_t = ImportFormat::Nxc)]
+    pub format: ImportFormat,
+
+    /// Path to the database file or workspace directory
+    pub path: PathBuf,
+}
+
+/// `dns_domains` are the DNS domains known from the DC store and the workspace hosts, used to
+/// map the NetBIOS domain NetExec stores for users
+fn nxc_user_to_credential(db: &NxcDatabase, user: &NxcUser, dns_domains: &[String]) -> Credential {
+    let auth_data = if user.credtype.eq_ignore_ascii_case("hash") {
+        AuthData::from_hash_string(&user.password)
+    } else {
+        AuthData::Password(user.password.clone())
+    };
+
+    let mut credential = Credential::new_password(
+        user.username.clone(),
+        String::new(),
+        format!("nxc:{}", db.protocol),
+    );
+    credential.auth_data = auth_data;
+    credential.credential_type = if user.username.ends_with('$') {
+        CredentialType::MachineAccount
+    } else {
+        CredentialType::DomainUser
+    };
+
+    if !user.domain.is_empty() {
+        credential.target_dc = dns_domain(&user.domain, dns_domains);
+        credential.set_metadata("domain".to_string(), user.domain.clone());
+    }
+    if let Some(host) = user.pillaged_from_hostid.and_then(|id| db.host(id)) {
+        credential.set_metadata("pillaged_from".to_string(), format!("{} ({})", host.hostname, host.ip));
+    }
+
+    let admin_hosts = db.admin_hosts(user.id);
+    if !admin_hosts.is_empty() {
+        let hosts = admin_hosts
+            .iter()
+            .map(|h| if h.hostname.is_empty() { h.ip.clone() } else { format!("{} ({})", h.hostname, h.ip) })
+            .collect::<Vec<_>>()
+            .join(", ");
+        credential.set_metadata("admin_on".to_string(), hosts);
+        credential.add_privilege(format!("LocalAdmin ({})", db.protocol));
+        if admin_hosts.iter().any(|h| h.is_dc) {
+            credential.credential_type = CredentialType::DomainAdmin;
+        } else if credential.credential_type == CredentialType::DomainUser {
+            credential.credential_type = CredentialType::LocalAdmin;
+        }
+    }
+
+    credential
+}
+
+impl ImportArgs {
+    fn import_nxc(&self, app: &mut App) -> Result<(), String> {
+        let databases = load_nxc_workspace(&self.path)?;
+        let mut dns_domains: Vec<String> = app
+            .dc_storage()
+            .list_domain_controllers()
+            .iter()
+            .map(|dc| dc.domain_name.clone())
+            .collect();
+        dns_domains.extend(databases.iter().flat_map(|db| &db.hosts).map(|h| h.domain.clone()));
+
+        let mut imported_creds = 0;
+        let mut skipped_creds = 0;
+        let mut imported_dcs = 0;
+        for db in &databases {
+            for user in &db.users {
+                let credential = nxc_user_to_credential(db, user, &dns_domains);
+                if app
+                    .credential_storage()
+                    .contains_auth_data(&credential.username, &credential.auth_data)
+                {
+                    skipped_creds += 1;
+                    continue;
+                }
+                app.credential_storage().add_credential(credential)?;
+                imported_creds += 1;
+            }
+
+            for host in db.hosts.iter().filter(|h| h.is_dc && !h.domain.is_empty()) {
+                let Ok(ip) = host.ip.parse::<IpAddr>() else {
+                    continue;
+                };
+                let domain = host.domain.to_lowercase();
+                if app.dc_storage().get_domain_controller(&domain).is_some() {
+                    continue;
+                }
+                app.dc_storage()
+                    .add_domain_controller(DomainController::new(ip, domain));
+                imported_dcs += 1;
+            }
+            println!(
+                "Read {} users and {} hosts from the {} database",
+                db.users.len(),
+                db.hosts.len(),
+                db.protocol
+            );
+        }
+
+        println!(
+            "Imported {} credentials ({} duplicates skipped) and {} domain controllers",
+            imported_creds, skipped_creds, imported_dcs
+        );
+        Ok(())
+    }
+}
+
+impl Command for ImportArgs {
+    async fn execute(&self, app: &mut App) -> Result<bool, String> {
+        match self.format {
+            ImportFormat::Nxc => self.import_nxc(app)?,
+        }
+        Ok(false)
+    }
+}
@@ -0,0 +1,70 @@
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+
+use crate::{
+    app::App,
+    cli::commands::Command,
+    data::{AuthData, Credential, CredentialType},
+    kerberos::{etype_name, keytab::{parse_keytab, KeytabEntry}},
+};
+
+#[derive(Debug, Args)]
+pub struct ImportKeytabArgs {
+    /// Path to the keytab file
+    pub path: PathBuf,
+}
+
+fn keytab_entry_to_credential(entry: &KeytabEntry, path: &Path) -> Credential {
+    let principal = entry.principal();
+    let mut credential = Credential::new_password(principal.clone(), String::new(), "keytab".to_string());
+    credential.auth_data = AuthData::KerberosKey {
+        etype: entry.key.keytype,
+        key: entry.key.keyvalue.iter().map(|b| format!("{:02x}", b)).collect(),
+    };
+    credential.credential_type = if principal.ends_with('$') {
+        CredentialType::MachineAccount
+    } else if principal.contains('/') {
+        CredentialType::ServicePrincipal
+    } else {
+        CredentialType::DomainUser
+    };
+    credential.target_dc = Some(entry.realm.to_lowercase());
+    credential.set_metadata("kvno".to_string(), entry.kvno.to_string());
+    credential.set_metadata("keytab".to_string(), path.display().to_string());
+    credential
+}
+
+impl Command for ImportKeytabArgs {
+    async fn execute(&self, app: &mut App) -> Result<bool, String> {
+        let data = std::fs::read(&self.path).map_err(|e| e.to_string())?;
+        let entries = parse_keytab(&data)?;
+
+        let mut imported = 0;
+        for entry in &entries {
+            let credential = keytab_entry_to_credential(entry, &self.path);
+            if app
+                .credential_storage()
+                .contains_auth_data(&credential.username, &credential.auth_data)
+            {
+                continue;
+            }
+            println!(
+                "{}@{} kvno {} {}",
+                entry.principal(),
+                entry.realm,
+                entry.kvno,
+                etype_name(entry.key.keytype)
+            );
+            app.credential_storage().add_credential(credential)?;
+            imported += 1;
+        }
+
+        println!(
+            "Imported {} keys ({} duplicates skipped)",
+            imported,
+            entries.len() - imported
+        );
+        Ok(false)
+    }
+}
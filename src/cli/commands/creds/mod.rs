@@ -4,10 +4,14 @@ mod remove;
 mod r#use;
 mod save_file;
 mod load_file;
+mod classify;
+mod import;
+mod import_keytab;
+mod export_keytab;
 
 use clap::{Parser, Subcommand};
 
-use crate::{app::App, cli::commands::{creds::{add::AddArgs, list::ListArgs, remove::RemoveArgs, r#use::UseArgs, save_file::SaveFileArgs, load_file::LoadFileArgs}, Command}};
+use crate::{app::App, cli::commands::{creds::{add::AddArgs, list::ListArgs, remove::RemoveArgs, r#use::UseArgs, save_file::SaveFileArgs, load_file::LoadFileArgs, classify::ClassifyArgs, import::ImportArgs, import_keytab::ImportKeytabArgs, export_keytab::ExportKeytabArgs}, Command}};
 
 #[derive(Debug, Parser)]
 pub struct CredsArgs {
@@ -29,7 +33,15 @@ pub enum CredsCommands {
     /// Save credentials to file
     SaveFile(SaveFileArgs),
     /// Load credentials from file
-    LoadFile(LoadFileArgs)
+    LoadFile(LoadFileArgs),
+    /// Classify credentials using directory data
+    Classify(ClassifyArgs),
+    /// Import credentials from another tool
+    Import(ImportArgs),
+    /// Import the keys of a keytab file
+    ImportKeytab(ImportKeytabArgs),
+    /// Write stored passwords and keys to a keytab file
+    ExportKeytab(ExportKeytabArgs),
 }
 
 impl Command for CredsArgs {
@@ -41,6 +53,10 @@ impl Command for CredsArgs {
             CredsCommands::Use(args) => args.execute(app).await,
             CredsCommands::SaveFile(args) => args.execute(app).await,
             CredsCommands::LoadFile(args) => args.execute(app).await,
+            CredsCommands::Classify(args) => args.execute(app).await,
+            CredsCommands::Import(args) => args.execute(app).await,
+            CredsCommands::ImportKeytab(args) => args.execute(app).await,
+            CredsCommands::ExportKeytab(args) => args.execute(app).await,
         }
     }
 }
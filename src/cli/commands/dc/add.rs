@@ -1,70 +1,121 @@
-use std::net::{IpAddr, Ipv4Addr};
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+};
 
+use chrono::Utc;
 use clap::{Args, arg};
-use ldap3::{LdapConn, LdapConnAsync, Scope, SearchEntry};
 use log::info;
 
-use crate::{app::App, cli::commands::Command, data::DomainController};
+use crate::{
+    app::App,
+    cli::commands::Command,
+    data::{domain_controller::KdcProxySettings, DomainController},
+    utils::{ldap_operations::parse_generalized_time, ldap_session::LdapSession},
+};
 
 #[derive(Debug, Args)]
 pub struct AddArgs {
-    pub ip: Ipv4Addr,
+    pub ip: IpAddr,
     #[arg(long, default_value_t = false)]
     pub ldaps: bool,
     #[arg(long, default_value_t = 389)]
     pub ldap_port: u16,
     #[arg(long)]
     pub domain: Option<String>,
+    /// Additional KDC of the domain (ip or ip:port), tried when the dc doesn't answer
+    #[arg(long = "kdc", value_parser = parse_kdc)]
+    pub kdcs: Vec<SocketAddr>,
+    /// Timeout of a single KDC request in seconds
+    #[arg(long, default_value_t = 10)]
+    pub kdc_timeout: u64,
+    /// Additional attempts per KDC before failing over
+    #[arg(long, default_value_t = 1)]
+    pub kdc_retries: u32,
+    /// Talk to the KDCs over UDP, falling back to TCP for large replies
+    #[arg(long, default_value_t = false)]
+    pub kdc_udp: bool,
+    /// MS-KKDCP proxy used when the KDCs are unreachable (e.g. https://host/KdcProxy)
+    #[arg(long, value_parser = parse_kdc_proxy)]
+    pub kdc_proxy: Option<String>,
+    /// PEM file with the CA certificates to trust for the KDC proxy
+    #[arg(long, requires = "kdc_proxy")]
+    pub kdc_proxy_ca: Option<PathBuf>,
+    /// Don't verify the certificate of the KDC proxy
+    #[arg(long, default_value_t = false, requires = "kdc_proxy")]
+    pub kdc_proxy_insecure: bool,
+}
+
+fn parse_kdc(value: &str) -> Result<SocketAddr, String> {
+    value
+        .parse::<SocketAddr>()
+        .or_else(|_| value.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 88)))
+        .map_err(|_| format!("Invalid KDC address: {}", value))
+}
+
+fn parse_kdc_proxy(value: &str) -> Result<String, String> {
+    let url = url::Url::parse(value).map_err(|e| format!("Invalid KDC proxy url: {}", e))?;
+    match url.scheme() {
+        "https" | "http" => Ok(url.to_string()),
+        scheme => Err(format!("Unsupported KDC proxy scheme: {}", scheme)),
+    }
 }
 
 impl Command for AddArgs {
     async fn execute(&self, app: &mut App) -> Result<bool, String> {
-        let mut dc = DomainController::new(IpAddr::V4(self.ip), "UNKNOWN".to_string());
+        let mut dc = DomainController::new(self.ip, "UNKNOWN".to_string());
 
         dc.ldaps_enabled = self.ldaps;
         dc.ldap_port = self.ldap_port;
+        dc.kdc.extra_kdcs = self.kdcs.clone();
+        dc.kdc.timeout_secs = self.kdc_timeout;
+        dc.kdc.retries = self.kdc_retries;
+        dc.kdc.udp = self.kdc_udp;
+        dc.kdc.proxy = self.kdc_proxy.clone().map(|url| KdcProxySettings {
+            url,
+            ca_file: self.kdc_proxy_ca.clone(),
+            insecure: self.kdc_proxy_insecure,
+        });
 
-        let ldap_url = dc.ldap_url();
+        let mut session = LdapSession::anonymous(&dc).await?;
+        let root_dse = session
+            .root_dse(vec!["defaultNamingContext", "namingContexts", "currentTime"])
+            .await?;
+        session.unbind().await?;
 
-        let (conn, mut ldap) = LdapConnAsync::new(ldap_url.as_str())
-            .await
-            .map_err(|e| e.to_string())?;
-        ldap3::drive!(conn);
-        let (rs, _res) = ldap
-            .search(
-                "",
-                Scope::Base,
-                "(objectClass=*)",
-                vec!["defaultNamingContext", "namingContexts"],
-            )
-            .await
-            .map_err(|e| e.to_string())?
-            .success()
-            .map_err(|e| e.to_string())?;
+        // Kerberos timestamps follow the DC clock
+        let current_time = root_dse
+            .attrs
+            .get("currentTime")
+            .and_then(|values| values.first())
+            .and_then(|t| parse_generalized_time(t));
+        if let Some(current_time) = current_time {
+            dc.clock_skew_secs = (current_time - Utc::now()).num_seconds();
+            if dc.clock_skew_secs.abs() > 60 {
+                println!("Clock skew with the DC: {}s, kerberos timestamps will be adjusted", dc.clock_skew_secs);
+            }
+        }
 
         if let Some(domain) = &self.domain {
             dc.domain_name = domain.clone();
         } else {
-            for entry in rs {
-                let search_entry = SearchEntry::construct(entry);
-                let default_naming_context = search_entry
-                    .attrs
-                    .get("defaultNamingContext")
-                    .or(search_entry.attrs.get("namingContexts"))
-                    .ok_or("Failed to get defaultNamingContext or namingContexts".to_string())?;
-                let default_naming_context = default_naming_context
-                    .get(0)
-                    .ok_or("Failed to get defaultNamingContext or namingContexts".to_string())?;
-                dc.domain_name = default_naming_context
-                    .split(",")
-                    .filter_map(|f| f.strip_prefix("DC="))
-                    .map(|f| f.to_ascii_lowercase())
-                    .collect::<Vec<_>>()
-                    .join(".");
-                println!("Domain name found {}", dc.domain_name.clone());
-            }
+            let default_naming_context = root_dse
+                .attrs
+                .get("defaultNamingContext")
+                .or(root_dse.attrs.get("namingContexts"))
+                .and_then(|values| values.first())
+                .ok_or("Failed to get defaultNamingContext or namingContexts".to_string())?;
+            dc.domain_name = default_naming_context
+                .split(",")
+                .filter_map(|f| f.strip_prefix("DC="))
+                .map(|f| f.to_ascii_lowercase())
+                .collect::<Vec<_>>()
+                .join(".");
+            println!("Domain name found {}", dc.domain_name.clone());
         }
-        ldap.unbind().await.map_err(|e| e.to_string())?;
+
+        // Sessions opened with the previous settings of this DC are stale
+        app.close_ldap_sessions(&dc);
 
         // Add the domain controller to storage
         app.domain_controller_storage
@@ -1,10 +1,11 @@
 mod add;
 mod list;
 mod r#use;
+mod time;
 
 use clap::{Parser, Subcommand};
 
-use crate::{app::App, cli::commands::{dc::{add::AddArgs, list::ListArgs, r#use::UseArgs}, Command}};
+use crate::{app::App, cli::commands::{dc::{add::AddArgs, list::ListArgs, r#use::UseArgs, time::TimeArgs}, Command}};
 
 #[derive(Debug, Parser)]
 pub struct DcArgs {
@@ -20,6 +21,8 @@ pub enum DcCommands {
     List(ListArgs),
     #[command(about = "Use a domain controller")]
     Use(UseArgs),
+    #[command(about = "Measure the clock skew with a domain controller and compensate it")]
+    Time(TimeArgs),
 }
 
 impl Command for DcArgs {
@@ -28,6 +31,7 @@ impl Command for DcArgs {
             DcCommands::Add(args) => args.execute(app).await,
             DcCommands::List(args) => args.execute(app).await,
             DcCommands::Use(args) => args.execute(app).await,
+            DcCommands::Time(args) => args.execute(app).await,
         }
     }
 }
\ No newline at end of file
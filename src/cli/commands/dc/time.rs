@@ -0,0 +1,64 @@
+use clap::Args;
+use chrono::Utc;
+
+use crate::{
+    app::App,
+    cli::commands::Command,
+    kerberos::clock::kdc_time,
+    utils::ldap_operations::ldap_current_time,
+};
+
+#[derive(Debug, Args)]
+pub struct TimeArgs {
+    /// Domain name of the domain controller (defaults to the current one)
+    pub domain_name: Option<String>,
+
+    /// Only show the skew, keep the stored offset
+    #[arg(long, default_value_t = false)]
+    pub no_store: bool,
+}
+
+impl Command for TimeArgs {
+    async fn execute(&self, app: &mut App) -> Result<bool, String> {
+        let dc = match &self.domain_name {
+            Some(domain_name) => app
+                .domain_controller_storage
+                .get_domain_controller(domain_name)
+                .ok_or_else(|| format!("Domain controller '{}' not found", domain_name))?
+                .clone(),
+            None => app.get_current_dc()?,
+        };
+
+        let local = Utc::now();
+        println!("Local time  : {}", local.to_rfc3339());
+
+        // The KDC time is the one kerberos checks, the rootDSE one is a fallback
+        let mut skew = None;
+        match kdc_time(&dc).await {
+            Ok(time) => {
+                println!("KDC time    : {}", time.to_rfc3339());
+                skew = Some((time - local).num_seconds());
+            }
+            Err(e) => println!("KDC time    : {}", e),
+        }
+        match ldap_current_time(&dc).await {
+            Ok(time) => {
+                println!("LDAP time   : {}", time.to_rfc3339());
+                skew = skew.or(Some((time - local).num_seconds()));
+            }
+            Err(e) => println!("LDAP time   : {}", e),
+        }
+
+        let skew = skew.ok_or("Failed to read the time of the domain controller".to_string())?;
+        println!("Clock skew  : {}s (stored {}s)", skew, dc.clock_skew_secs);
+
+        if !self.no_store {
+            app.dc_storage().get_domain_controller_mut(&dc.domain_name)?.clock_skew_secs = skew;
+            if let Some(current) = app.current_used_dc.as_mut().filter(|c| c.domain_name == dc.domain_name) {
+                current.clock_skew_secs = skew;
+            }
+            println!("Kerberos timestamps will use the DC clock");
+        }
+        Ok(false)
+    }
+}
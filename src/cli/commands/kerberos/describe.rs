@@ -0,0 +1,195 @@
+use std::path::Path;
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use chrono::DateTime;
+use clap::Args;
+use himmelblau_kerberos_asn1::{Asn1Object, EncTicketPart, Ticket};
+use himmelblau_kerberos_ccache::{CCache, Credential as CcacheCredential};
+
+use crate::{
+    app::App,
+    cli::commands::Command,
+    data::AuthData,
+    kerberos::{
+        etype_name,
+        pac::{
+            pac_buffer_name, pac_buffers, parse_client_info, parse_logon_info, parse_signature,
+            parse_upn_dns_info, ticket_pac, PAC_ATTRIBUTES_INFO, PAC_CLIENT_INFO, PAC_FULL_CHECKSUM, PAC_LOGON_INFO,
+            PAC_PRIVSVR_CHECKSUM, PAC_REQUESTOR, PAC_SERVER_CHECKSUM, PAC_TICKET_CHECKSUM, PAC_UPN_DNS_INFO,
+        },
+        ticket::{
+            decode_ccache, decrypt_ticket, hex_decode, long_term_key, principal_from_ccache, service_account_name,
+            ticket_flag_names,
+        },
+    },
+    utils::ldap_operations::{filetime_to_string, sam_account_name, sid_to_string},
+};
+
+#[derive(Debug, Args, Clone)]
+pub struct DescribeArgs {
+    /// Id of a ticket credential, or a ccache file
+    pub ticket: String,
+
+    /// Hex encoded long term key of the service (RC4 or AES) to decrypt the ticket with
+    #[arg(short, long)]
+    pub key: Option<String>,
+}
+
+fn timestamp(time: u32) -> String {
+    if time == 0 {
+        return "-".to_string();
+    }
+    DateTime::from_timestamp(time as i64, 0).map_or("-".to_string(), |t| t.to_rfc3339())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Load the tickets of a stored credential or of a ccache file
+fn load_tickets(app: &mut App, ticket: &str) -> Result<Vec<CcacheCredential>, String> {
+    if let Some(creds) = app.credential_storage().get_credential(ticket) {
+        return match &creds.auth_data {
+            AuthData::KerberosTicket(data) => Ok(vec![decode_ccache(data)?]),
+            _ => Err(format!("Credential {} is not a kerberos ticket", ticket)),
+        };
+    }
+
+    let raw = std::fs::read(Path::new(ticket)).map_err(|e| format!("No credential or file {}: {}", ticket, e))?;
+    if let Ok((_, ccache)) = CCache::parse(&raw) {
+        return Ok(ccache.credentials);
+    }
+    // Exported credential, a single base64 encoded ccache entry
+    let decoded = BASE64_STANDARD
+        .decode(String::from_utf8_lossy(&raw).trim())
+        .map_err(|_| format!("{} is not a ccache file", ticket))?;
+    let (_, credential) = CcacheCredential::parse(&decoded).map_err(|e| format!("Invalid ticket: {}", e))?;
+    Ok(vec![credential])
+}
+
+/// Try the given key, then the stored credentials of the service account, then every other stored key
+fn decrypt_with_store(app: &mut App, ticket: &Ticket, key: Option<&str>) -> Option<(String, Vec<u8>)> {
+    if let Some(key) = key {
+        let key = hex_decode(key).ok()?;
+        return decrypt_ticket(ticket, &key).ok().map(|part| ("--key".to_string(), part));
+    }
+
+    let account = service_account_name(&ticket.sname);
+    let mut candidates = app.credential_storage().get_all_credentials();
+    candidates.sort_by_key(|c| !sam_account_name(&c.username).eq_ignore_ascii_case(&account));
+    candidates.into_iter().find_map(|creds| {
+        let key = long_term_key(&creds, ticket.enc_part.etype, &ticket.realm).ok()?;
+        decrypt_ticket(ticket, &key.keyvalue)
+            .ok()
+            .map(|part| (format!("{} ({})", creds.username, creds.id), part))
+    })
+}
+
+fn print_ticket(credential: &CcacheCredential) -> Result<Ticket, String> {
+    let (client_realm, client_name) = principal_from_ccache(&credential.client);
+    let (server_realm, server_name) = principal_from_ccache(&credential.server);
+    let (_, ticket) = Ticket::parse(&credential.ticket.data).map_err(|e| format!("Invalid ticket: {}", e))?;
+
+    println!("Client           : {}@{}", client_name.name_string.join("/"), client_realm);
+    println!("Service          : {}@{}", server_name.name_string.join("/"), server_realm);
+    println!("Realm            : {}", ticket.realm);
+    println!("Flags            : {}", ticket_flag_names(credential.tktflags).join(", "));
+    println!("Session key      : {} {}", etype_name(credential.key.keytype as i32), hex(&credential.key.keyvalue));
+    println!("Ticket etype     : {}", etype_name(ticket.enc_part.etype));
+    println!("Ticket kvno      : {}", ticket.enc_part.kvno.map_or("-".to_string(), |kvno| kvno.to_string()));
+    println!("Auth time        : {}", timestamp(credential.time.authtime));
+    println!("Start time       : {}", timestamp(credential.time.starttime));
+    println!("End time         : {}", timestamp(credential.time.endtime));
+    println!("Renew until      : {}", timestamp(credential.time.renew_till));
+    Ok(ticket)
+}
+
+fn print_pac(pac: &[u8]) -> Result<(), String> {
+    for (buffer_type, data) in pac_buffers(pac)? {
+        println!("[{}]", pac_buffer_name(buffer_type));
+        match buffer_type {
+            PAC_LOGON_INFO => {
+                let info = parse_logon_info(&data)?;
+                println!("  Username           : {}", info.effective_name);
+                println!("  Full name          : {}", info.full_name);
+                println!("  Domain             : {}", info.logon_domain_name);
+                println!("  Logon server       : {}", info.logon_server);
+                println!("  Logon time         : {}", filetime_to_string(info.logon_time));
+                println!("  Password last set  : {}", filetime_to_string(info.password_last_set));
+                println!("  Password expires   : {}", filetime_to_string(info.password_must_change));
+                println!("  Logon count        : {}", info.logon_count);
+                println!("  Bad password count : {}", info.bad_password_count);
+                println!("  User SID           : {}", info.user_sid().unwrap_or(info.user_id.to_string()));
+                println!("  Primary group RID  : {}", info.primary_group_id);
+                println!("  User flags         : {:#x}", info.user_flags);
+                println!("  UAC                : {:#x}", info.user_account_control);
+                for sid in info.group_sids() {
+                    println!("  Group SID          : {}", sid);
+                }
+                for (sid, attributes) in &info.extra_sids {
+                    println!("  Extra SID          : {} ({:#x})", sid, attributes);
+                }
+                if let Some(domain) = &info.resource_group_domain_sid {
+                    for (rid, _) in &info.resource_group_ids {
+                        println!("  Resource group SID : {}-{}", domain, rid);
+                    }
+                }
+            }
+            PAC_CLIENT_INFO => {
+                let (client_id, name) = parse_client_info(&data)?;
+                println!("  Name               : {}", name);
+                println!("  Client id          : {}", filetime_to_string(client_id));
+            }
+            PAC_UPN_DNS_INFO => {
+                let info = parse_upn_dns_info(&data)?;
+                println!("  UPN                : {}", info.upn);
+                println!("  DNS domain         : {}", info.dns_domain_name);
+                println!("  Flags              : {:#x}", info.flags);
+                if let Some(sam_name) = &info.sam_name {
+                    println!("  sAMAccountName     : {}", sam_name);
+                }
+                if let Some(sid) = &info.sid {
+                    println!("  SID                : {}", sid);
+                }
+            }
+            PAC_SERVER_CHECKSUM | PAC_PRIVSVR_CHECKSUM | PAC_TICKET_CHECKSUM | PAC_FULL_CHECKSUM => {
+                let (signature_type, signature) = parse_signature(&data)?;
+                println!("  Type               : {}", signature_type);
+                println!("  Signature          : {}", hex(&signature));
+            }
+            PAC_ATTRIBUTES_INFO => {
+                let flags = data.get(4..8).map_or(0, |b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+                println!("  Flags              : {:#x}", flags);
+            }
+            PAC_REQUESTOR => {
+                println!("  SID                : {}", sid_to_string(&data).unwrap_or_default());
+            }
+            _ => println!("  {} bytes", data.len()),
+        }
+    }
+    Ok(())
+}
+
+impl Command for DescribeArgs {
+    async fn execute(&self, app: &mut App) -> Result<bool, String> {
+        for credential in load_tickets(app, &self.ticket)? {
+            let ticket = print_ticket(&credential)?;
+
+            let Some((source, enc_ticket_part)) = decrypt_with_store(app, &ticket, self.key.as_deref()) else {
+                println!("No key of {} found to decrypt the ticket\n", service_account_name(&ticket.sname));
+                continue;
+            };
+            println!("Decrypted with   : {}", source);
+            if let Ok((_, part)) = EncTicketPart::parse(&enc_ticket_part) {
+                println!("Ticket flags     : {}", ticket_flag_names(part.flags.flags).join(", "));
+                println!("Client (ticket)  : {}@{}", part.cname.name_string.join("/"), part.crealm);
+            }
+            match ticket_pac(&enc_ticket_part) {
+                Ok(pac) => print_pac(&pac)?,
+                Err(e) => println!("{}", e),
+            }
+            println!();
+        }
+        Ok(false)
+    }
+}
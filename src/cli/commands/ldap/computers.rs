@@ -0,0 +1,254 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use comfy_table::{Attribute, Cell, Color, ContentArrangement, Table};
+use ldap3::{Scope, SearchEntry};
+
+use crate::{
+    app::App,
+    cli::commands::Command,
+    data::credential::AuthMethod,
+    utils::{
+        dns_operations::resolve_hosts,
+        ldap_operations::{filetime_to_string, first_attr},
+    },
+};
+
+const COMPUTER_ATTRIBUTES: [&str; 14] = [
+    "sAMAccountName",
+    "dNSHostName",
+    "operatingSystem",
+    "operatingSystemVersion",
+    "lastLogonTimestamp",
+    "servicePrincipalName",
+    "userAccountControl",
+    "msDS-AllowedToDelegateTo",
+    "msDS-AllowedToActOnBehalfOfOtherIdentity",
+    "ms-Mcs-AdmPwd",
+    "ms-Mcs-AdmPwdExpirationTime",
+    "msLAPS-Password",
+    "msLAPS-EncryptedPassword",
+    "msLAPS-PasswordExpirationTime",
+];
+
+const TRUSTED_FOR_DELEGATION: u32 = 0x0008_0000;
+const TRUSTED_TO_AUTH_FOR_DELEGATION: u32 = 0x0100_0000;
+const SERVER_TRUST_ACCOUNT: u32 = 0x0000_2000;
+
+#[derive(Debug, Args)]
+pub struct ComputersArgs {
+    /// Only enabled computer accounts
+    #[arg(long)]
+    pub enabled: bool,
+
+    /// File to write the host names of the listed computers to, one per line
+    #[arg(short, long)]
+    pub targets: Option<PathBuf>,
+
+    /// Resolve the host names with the DNS of the DC and write IP addresses to the target list
+    #[arg(long)]
+    pub ip: bool,
+
+    /// Authentication protocol for LDAP
+    #[arg(long, value_enum, default_value_t = AuthMethod::Auto)]
+    pub auth: AuthMethod,
+}
+
+fn has_attr(entry: &SearchEntry, name: &str) -> bool {
+    entry.attrs.contains_key(name) || entry.bin_attrs.contains_key(name)
+}
+
+/// Host name of a computer, from the sAMAccountName in `domain` when dNSHostName isn't set
+fn hostname(entry: &SearchEntry, domain: &str) -> String {
+    first_attr(entry, "dNSHostName").map(str::to_string).unwrap_or_else(|| {
+        let name = first_attr(entry, "sAMAccountName").unwrap_or_default().trim_end_matches('$');
+        format!("{}.{}", name.to_lowercase(), domain.to_lowercase())
+    })
+}
+
+/// LAPS state: the password is readable with the current credential (encrypted for Windows LAPS
+/// with password encryption, it needs the DPAPI-NG keys), or only its expiration is
+fn laps(entry: &SearchEntry) -> &'static str {
+    if has_attr(entry, "ms-Mcs-AdmPwd") || has_attr(entry, "msLAPS-Password") {
+        "readable"
+    } else if has_attr(entry, "msLAPS-EncryptedPassword") {
+        "encrypted"
+    } else if has_attr(entry, "ms-Mcs-AdmPwdExpirationTime") || has_attr(entry, "msLAPS-PasswordExpirationTime") {
+        "managed"
+    } else {
+        ""
+    }
+}
+
+fn uac(entry: &SearchEntry) -> u32 {
+    first_attr(entry, "userAccountControl").and_then(|v| v.parse().ok()).unwrap_or(0)
+}
+
+/// Delegation settings: unconstrained, constrained (with protocol transition) and resource-based
+fn delegation(entry: &SearchEntry) -> Vec<String> {
+    let uac = uac(entry);
+    let mut delegation = Vec::new();
+    if uac & TRUSTED_FOR_DELEGATION != 0 {
+        delegation.push("unconstrained".to_string());
+    }
+    if let Some(targets) = entry.attrs.get("msDS-AllowedToDelegateTo") {
+        let kind = if uac & TRUSTED_TO_AUTH_FOR_DELEGATION != 0 { "constrained (any auth)" } else { "constrained" };
+        delegation.push(format!("{}: {}", kind, targets.join(", ")));
+    }
+    if has_attr(entry, "msDS-AllowedToActOnBehalfOfOtherIdentity") {
+        delegation.push("RBCD".to_string());
+    }
+    delegation
+}
+
+impl Command for ComputersArgs {
+    async fn execute(&self, app: &mut App) -> Result<bool, String> {
+        let dc = app.get_current_dc()?;
+        let mut ldap = app.ldap_session(self.auth).await?;
+        let base_dn = ldap.base_dn.clone();
+        let filter = if self.enabled {
+            "(&(samAccountType=805306369)(!(userAccountControl:1.2.840.113556.1.4.803:=2)))"
+        } else {
+            "(samAccountType=805306369)"
+        };
+        let mut computers = ldap
+            .paged_search(&base_dn, Scope::Subtree, filter, COMPUTER_ATTRIBUTES.to_vec(), None)
+            .await?;
+
+        if computers.is_empty() {
+            println!("No computers found");
+            return Ok(false);
+        }
+        computers.sort_by_key(|computer| hostname(computer, &dc.domain_name).to_lowercase());
+
+        let hostnames: Vec<String> = computers.iter().map(|computer| hostname(computer, &dc.domain_name)).collect();
+        let addresses = if self.ip { resolve_hosts(&dc, &hostnames).await } else { Default::default() };
+
+        let mut columns = vec!["Host Name", "Operating System", "Version", "Last Logon", "LAPS", "Delegation", "SPNs"];
+        if self.ip {
+            columns.insert(1, "IP Addresses");
+        }
+        let mut table = Table::new();
+        table
+            .set_content_arrangement(ContentArrangement::Dynamic)
+            .set_header(columns.iter().map(|c| Cell::new(c).add_attribute(Attribute::Bold).fg(Color::Cyan)));
+
+        for (computer, hostname) in computers.iter().zip(&hostnames) {
+            let delegation = delegation(computer);
+            let delegation_cell = Cell::new(delegation.join("\n"));
+            // Domain controllers are trusted for unconstrained delegation by design
+            let delegation_cell = if !delegation.is_empty() && uac(computer) & SERVER_TRUST_ACCOUNT == 0 {
+                delegation_cell.fg(Color::Red).add_attribute(Attribute::Bold)
+            } else {
+                delegation_cell
+            };
+            let laps = laps(computer);
+            let laps_cell = if laps == "readable" { Cell::new(laps).fg(Color::Green) } else { Cell::new(laps) };
+            let last_logon = first_attr(computer, "lastLogonTimestamp")
+                .and_then(|value| value.parse::<i64>().ok())
+                .map_or("never".to_string(), |value| filetime_to_string(value.max(0) as u64));
+
+            let mut row = vec![
+                Cell::new(hostname),
+                Cell::new(first_attr(computer, "operatingSystem").unwrap_or_default()),
+                Cell::new(first_attr(computer, "operatingSystemVersion").unwrap_or_default()),
+                Cell::new(last_logon),
+                laps_cell,
+                delegation_cell,
+                Cell::new(computer.attrs.get("servicePrincipalName").map_or(String::new(), |spns| spns.join("\n"))),
+            ];
+            if self.ip {
+                let ips = addresses.get(hostname).map_or(String::new(), |ips| {
+                    ips.iter().map(|ip| ip.to_string()).collect::<Vec<_>>().join("\n")
+                });
+                row.insert(1, Cell::new(ips));
+            }
+            table.add_row(row);
+        }
+        println!("{}", table);
+        println!("{} computers", computers.len());
+
+        if let Some(targets) = &self.targets {
+            let lines: Vec<String> = if self.ip {
+                let mut ips: Vec<String> = Vec::new();
+                for ip in hostnames.iter().filter_map(|hostname| addresses.get(hostname)).flatten() {
+                    if !ips.contains(&ip.to_string()) {
+                        ips.push(ip.to_string());
+                    }
+                }
+                ips
+            } else {
+                hostnames.clone()
+            };
+            std::fs::write(targets, lines.join("\n") + "\n").map_err(|e| e.to_string())?;
+            println!("Wrote {} targets to {}", lines.len(), targets.display());
+            if self.ip && addresses.len() < hostnames.len() {
+                println!("{} host names didn't resolve", hostnames.len() - addresses.len());
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn computer(attrs: &[(&str, &str)], bin_attrs: &[&str]) -> SearchEntry {
+        let mut entry = SearchEntry {
+            dn: "CN=WS01,CN=Computers,DC=voleur,DC=htb".to_string(),
+            attrs: HashMap::new(),
+            bin_attrs: HashMap::new(),
+        };
+        for (name, value) in attrs {
+            entry.attrs.entry(name.to_string()).or_insert_with(Vec::new).push(value.to_string());
+        }
+        for name in bin_attrs {
+            entry.bin_attrs.insert(name.to_string(), vec![vec![1, 0, 4, 128]]);
+        }
+        entry
+    }
+
+    #[test]
+    fn test_hostname() {
+        assert_eq!(hostname(&computer(&[("sAMAccountName", "WS01$")], &[]), "VOLEUR.HTB"), "ws01.voleur.htb");
+        let entry = computer(&[("sAMAccountName", "WS01$"), ("dNSHostName", "ws01.corp.voleur.htb")], &[]);
+        assert_eq!(hostname(&entry, "voleur.htb"), "ws01.corp.voleur.htb");
+    }
+
+    #[test]
+    fn test_laps() {
+        assert_eq!(laps(&computer(&[("ms-Mcs-AdmPwd", "x7$Kp2!q")], &[])), "readable");
+        let encrypted = computer(
+            &[("msLAPS-PasswordExpirationTime", "133700000000000000")],
+            &["msLAPS-EncryptedPassword"],
+        );
+        assert_eq!(laps(&encrypted), "encrypted");
+        assert_eq!(laps(&computer(&[("ms-Mcs-AdmPwdExpirationTime", "133700000000000000")], &[])), "managed");
+        assert_eq!(laps(&computer(&[], &[])), "");
+    }
+
+    #[test]
+    fn test_delegation() {
+        assert!(delegation(&computer(&[("userAccountControl", "4096")], &[])).is_empty());
+        assert_eq!(delegation(&computer(&[("userAccountControl", "528384")], &[])), ["unconstrained"]);
+
+        let constrained = computer(
+            &[
+                ("userAccountControl", "16781312"),
+                ("msDS-AllowedToDelegateTo", "cifs/dc.voleur.htb"),
+                ("msDS-AllowedToDelegateTo", "cifs/dc"),
+            ],
+            &[],
+        );
+        assert_eq!(delegation(&constrained), ["constrained (any auth): cifs/dc.voleur.htb, cifs/dc"]);
+
+        let rbcd = computer(
+            &[("userAccountControl", "4096"), ("msDS-AllowedToDelegateTo", "http/web01")],
+            &["msDS-AllowedToActOnBehalfOfOtherIdentity"],
+        );
+        assert_eq!(delegation(&rbcd), ["constrained: http/web01", "RBCD"]);
+    }
+}
@@ -0,0 +1,163 @@
+use clap::Args;
+use comfy_table::{Attribute, Cell, Color, ContentArrangement, Table};
+
+use crate::{
+    app::App,
+    cli::commands::Command,
+    data::{credential::AuthMethod, Credential},
+    utils::{
+        ldap_groups::{find_object, group_members, member_of, DirectoryObject},
+        ldap_operations::sam_account_name,
+    },
+};
+
+#[derive(Debug, Args)]
+pub struct GroupMembersArgs {
+    /// Group name, SID or distinguished name
+    pub group: String,
+
+    /// Include the members of nested groups
+    #[arg(short, long)]
+    pub recursive: bool,
+
+    /// Authentication protocol for LDAP
+    #[arg(long, value_enum, default_value_t = AuthMethod::Auto)]
+    pub auth: AuthMethod,
+}
+
+#[derive(Debug, Args)]
+pub struct MemberOfArgs {
+    /// Principal name, SID or distinguished name
+    pub principal: String,
+
+    /// Authentication protocol for LDAP
+    #[arg(long, value_enum, default_value_t = AuthMethod::Auto)]
+    pub auth: AuthMethod,
+}
+
+fn header(columns: &[&str]) -> Vec<Cell> {
+    columns
+        .iter()
+        .map(|c| Cell::new(c).add_attribute(Attribute::Bold).fg(Color::Cyan))
+        .collect()
+}
+
+fn membership(object: &DirectoryObject) -> &'static str {
+    if object.primary { "primary" } else { "member" }
+}
+
+/// Add privileged group names to the stored credentials of a principal of the current domain
+fn record_privileges(app: &mut App, sam: &str, privileges: &[String]) -> Result<usize, String> {
+    if privileges.is_empty() {
+        return Ok(0);
+    }
+    let domain = app.get_current_dc()?.domain_name;
+    let targets: Vec<Credential> = app
+        .credential_storage()
+        .get_all_credentials()
+        .into_iter()
+        .filter(|c| sam_account_name(&c.username).eq_ignore_ascii_case(sam))
+        .filter(|c| c.target_dc.as_ref().is_none_or(|d| d.eq_ignore_ascii_case(&domain)))
+        .collect();
+
+    for target in &targets {
+        let mut updated = target.clone();
+        for privilege in privileges {
+            updated.add_privilege(privilege.clone());
+        }
+        app.credential_storage().update_credential(&target.id, updated.clone())?;
+        if app.current_used_creds.as_ref().is_some_and(|c| c.id == target.id) {
+            app.set_current_creds(Some(updated));
+        }
+    }
+    Ok(targets.len())
+}
+
+impl Command for GroupMembersArgs {
+    async fn execute(&self, app: &mut App) -> Result<bool, String> {
+        let mut ldap = app.ldap_session(self.auth).await?;
+        let group = find_object(&mut ldap, &self.group).await?;
+        if !group.has_object_class("group") {
+            return Err(format!("{} is a {}, not a group", group.dn, group.kind()));
+        }
+        let members = group_members(&mut ldap, &group, self.recursive).await?;
+
+        let privileged = group.is_privileged_group();
+        println!("{}{}", group.dn, if privileged { " (privileged)" } else { "" });
+        if members.is_empty() {
+            println!("No members");
+            return Ok(false);
+        }
+
+        let mut table = Table::new();
+        table
+            .set_content_arrangement(ContentArrangement::Dynamic)
+            .set_header(header(&["sAMAccountName", "Type", "SID", "Membership"]));
+        for member in &members {
+            let name = Cell::new(&member.sam_account_name);
+            let name = if member.is_privileged_group() { name.fg(Color::Red).add_attribute(Attribute::Bold) } else { name };
+            table.add_row(vec![
+                name,
+                Cell::new(member.kind()),
+                Cell::new(member.sid.clone().unwrap_or_default()),
+                Cell::new(membership(member)),
+            ]);
+        }
+        println!("{}", table);
+        println!("{} members", members.len());
+
+        if privileged {
+            let mut updated = 0;
+            for member in members.iter().filter(|m| !m.has_object_class("group")) {
+                updated += record_privileges(app, &member.sam_account_name, std::slice::from_ref(&group.name))?;
+            }
+            if updated > 0 {
+                println!("Recorded {} as privilege of {} stored credentials", group.name, updated);
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl Command for MemberOfArgs {
+    async fn execute(&self, app: &mut App) -> Result<bool, String> {
+        let mut ldap = app.ldap_session(self.auth).await?;
+        let principal = find_object(&mut ldap, &self.principal).await?;
+        let groups = member_of(&mut ldap, &principal).await?;
+
+        println!("{} ({})", principal.dn, principal.kind());
+        if groups.is_empty() {
+            println!("No group memberships");
+            return Ok(false);
+        }
+
+        let mut table = Table::new();
+        table
+            .set_content_arrangement(ContentArrangement::Dynamic)
+            .set_header(header(&["Group", "SID", "Membership"]));
+        for group in &groups {
+            let name = Cell::new(&group.name);
+            let name = if group.is_privileged_group() { name.fg(Color::Red).add_attribute(Attribute::Bold) } else { name };
+            table.add_row(vec![
+                name,
+                Cell::new(group.sid.clone().unwrap_or_default()),
+                Cell::new(membership(group)),
+            ]);
+        }
+        println!("{}", table);
+
+        let privileges: Vec<String> = groups
+            .iter()
+            .filter(|g| g.is_privileged_group())
+            .map(|g| g.name.clone())
+            .collect();
+        if !privileges.is_empty() {
+            println!("Privileged groups: {}", privileges.join(", "));
+        }
+        let updated = record_privileges(app, &principal.sam_account_name, &privileges)?;
+        if updated > 0 {
+            println!("Updated the privileges of {} stored credentials", updated);
+        }
+        Ok(false)
+    }
+}
@@ -0,0 +1,56 @@
+mod computers;
+mod groups;
+mod search;
+mod users;
+mod whoami;
+
+use clap::{Parser, Subcommand};
+
+use crate::{
+    app::App,
+    cli::commands::{
+        ldap::{
+            computers::ComputersArgs,
+            groups::{GroupMembersArgs, MemberOfArgs},
+            search::SearchArgs,
+            users::UsersArgs,
+            whoami::WhoamiArgs,
+        },
+        Command,
+    },
+};
+
+#[derive(Debug, Parser)]
+pub struct LdapArgs {
+    #[command(subcommand)]
+    pub commands: LdapCommands
+}
+
+#[derive(Debug, Subcommand)]
+pub enum LdapCommands {
+    #[command(about = "Bind with the current credential and show the identity and protection of the session")]
+    Whoami(WhoamiArgs),
+    #[command(about = "Run a paged search and print the entries as a table, JSON or LDIF")]
+    Search(SearchArgs),
+    #[command(about = "List users with their account flags, logon times and SPNs")]
+    Users(UsersArgs),
+    #[command(about = "List the members of a group, with nested groups and primary group members")]
+    GroupMembers(GroupMembersArgs),
+    #[command(about = "List the groups of a principal and record its privileged groups on stored credentials")]
+    MemberOf(MemberOfArgs),
+    #[command(about = "List computers with their operating system, LAPS and delegation settings")]
+    Computers(ComputersArgs),
+}
+
+impl Command for LdapArgs {
+    async fn execute(&self, app: &mut App) -> Result<bool, String> {
+        match &self.commands {
+            LdapCommands::Whoami(cmd) => cmd.execute(app).await,
+            LdapCommands::Search(cmd) => cmd.execute(app).await,
+            LdapCommands::Users(cmd) => cmd.execute(app).await,
+            LdapCommands::GroupMembers(cmd) => cmd.execute(app).await,
+            LdapCommands::MemberOf(cmd) => cmd.execute(app).await,
+            LdapCommands::Computers(cmd) => cmd.execute(app).await,
+        }
+    }
+}
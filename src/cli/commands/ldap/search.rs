@@ -0,0 +1,202 @@
+use std::path::PathBuf;
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use clap::{Args, ValueEnum};
+use comfy_table::{Attribute, Cell, Color, ContentArrangement, Table};
+use ldap3::{Scope, SearchEntry};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    app::App,
+    cli::commands::Command,
+    data::credential::AuthMethod,
+    utils::ldap_operations::{decode_attribute, entry_attributes},
+};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum SearchScope {
+    Base,
+    One,
+    Sub,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Ldif,
+}
+
+#[derive(Debug, Args)]
+pub struct SearchArgs {
+    /// LDAP filter, e.g. "(&(objectClass=user)(adminCount=1))"
+    pub filter: String,
+
+    /// Base DN of the search (defaults to the domain's base DN)
+    #[arg(short, long)]
+    pub base: Option<String>,
+
+    /// Search scope
+    #[arg(short, long, value_enum, default_value_t = SearchScope::Sub)]
+    pub scope: SearchScope,
+
+    /// Comma separated attributes to return (all attributes when omitted)
+    #[arg(short, long, value_delimiter = ',')]
+    pub attrs: Vec<String>,
+
+    /// Maximum number of entries to return
+    #[arg(short, long)]
+    pub limit: Option<usize>,
+
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Table)]
+    pub format: OutputFormat,
+
+    /// File to write the results to instead of the screen
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Authentication protocol for LDAP
+    #[arg(long, value_enum, default_value_t = AuthMethod::Auto)]
+    pub auth: AuthMethod,
+}
+
+/// Decoded values of an attribute, binary values are base64 encoded
+fn display_values(name: &str, values: &[Vec<u8>]) -> Vec<String> {
+    values
+        .iter()
+        .map(|value| decode_attribute(name, value).unwrap_or_else(|| BASE64_STANDARD.encode(value)))
+        .collect()
+}
+
+fn format_table(entries: &[SearchEntry], attrs: &[String]) -> String {
+    // Without explicit attributes the columns are every attribute returned, in order of appearance
+    let mut columns: Vec<String> = attrs.to_vec();
+    if columns.is_empty() {
+        for entry in entries {
+            for (name, _) in entry_attributes(entry) {
+                if !columns.iter().any(|c| c.eq_ignore_ascii_case(&name)) {
+                    columns.push(name);
+                }
+            }
+        }
+    }
+
+    let mut header = vec![Cell::new("DN").add_attribute(Attribute::Bold).fg(Color::Cyan)];
+    header.extend(columns.iter().map(|c| Cell::new(c).add_attribute(Attribute::Bold).fg(Color::Cyan)));
+
+    let mut table = Table::new();
+    table.set_content_arrangement(ContentArrangement::Dynamic).set_header(header);
+    for entry in entries {
+        let attributes = entry_attributes(entry);
+        let mut row = vec![Cell::new(&entry.dn)];
+        for column in &columns {
+            let value = attributes
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(column))
+                .map_or(String::new(), |(name, values)| display_values(name, values).join("\n"));
+            row.push(Cell::new(value));
+        }
+        table.add_row(row);
+    }
+    table.to_string()
+}
+
+fn format_json(entries: &[SearchEntry]) -> Result<String, String> {
+    let entries: Vec<Value> = entries
+        .iter()
+        .map(|entry| {
+            let attributes: Map<String, Value> = entry_attributes(entry)
+                .into_iter()
+                .map(|(name, values)| {
+                    let values = display_values(&name, &values);
+                    (name, json!(values))
+                })
+                .collect();
+            json!({ "dn": entry.dn, "attributes": attributes })
+        })
+        .collect();
+    serde_json::to_string_pretty(&entries).map_err(|e| e.to_string())
+}
+
+/// LDIF line of an attribute, base64 (`name:: value`) when the value isn't a safe string (RFC 2849)
+fn ldif_line(name: &str, value: &[u8]) -> String {
+    let safe = value.iter().all(|b| b.is_ascii() && *b != b'\0' && *b != b'\n' && *b != b'\r')
+        && !value.starts_with(b" ")
+        && !value.starts_with(b":")
+        && !value.starts_with(b"<")
+        && !value.ends_with(b" ");
+    if safe {
+        format!("{}: {}", name, String::from_utf8_lossy(value))
+    } else {
+        format!("{}:: {}", name, BASE64_STANDARD.encode(value))
+    }
+}
+
+fn format_ldif(entries: &[SearchEntry]) -> String {
+    let mut ldif = String::new();
+    for entry in entries {
+        ldif.push_str(&ldif_line("dn", entry.dn.as_bytes()));
+        ldif.push('\n');
+        // Values are written as stored in the directory so the LDIF can be imported back
+        for (name, values) in entry_attributes(entry) {
+            for value in &values {
+                ldif.push_str(&ldif_line(&name, value));
+                ldif.push('\n');
+            }
+        }
+        ldif.push('\n');
+    }
+    ldif
+}
+
+impl Command for SearchArgs {
+    async fn execute(&self, app: &mut App) -> Result<bool, String> {
+        let mut ldap = app.ldap_session(self.auth).await?;
+        let base = self.base.clone().unwrap_or(ldap.base_dn.clone());
+        let scope = match self.scope {
+            SearchScope::Base => Scope::Base,
+            SearchScope::One => Scope::OneLevel,
+            SearchScope::Sub => Scope::Subtree,
+        };
+        let attrs: Vec<&str> = if self.attrs.is_empty() {
+            vec!["*"]
+        } else {
+            self.attrs.iter().map(|a| a.as_str()).collect()
+        };
+
+        let entries = ldap.paged_search(&base, scope, &self.filter, attrs, self.limit).await?;
+        let output = match self.format {
+            OutputFormat::Table => format_table(&entries, &self.attrs),
+            OutputFormat::Json => format_json(&entries)?,
+            OutputFormat::Ldif => format_ldif(&entries),
+        };
+
+        match &self.output {
+            Some(path) => {
+                std::fs::write(path, output + "\n").map_err(|e| e.to_string())?;
+                println!("Wrote {} entries to {}", entries.len(), path.display());
+            }
+            None => {
+                println!("{}", output);
+                println!("{} entries", entries.len());
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ldif_line() {
+        assert_eq!(ldif_line("cn", b"Domain Admins"), "cn: Domain Admins");
+        assert_eq!(ldif_line("description", b" leading space"), "description:: IGxlYWRpbmcgc3BhY2U=");
+        assert_eq!(ldif_line("cn", "José".as_bytes()), "cn:: Sm9zw6k=");
+        // Binary and FILETIME values are written as stored
+        assert_eq!(ldif_line("objectGUID", &[0x01, 0x00, 0xff]), "objectGUID:: AQD/");
+        assert_eq!(ldif_line("pwdLastSet", b"133000000000000000"), "pwdLastSet: 133000000000000000");
+    }
+}
@@ -0,0 +1,207 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, Duration, Utc};
+use clap::Args;
+use comfy_table::{Attribute, Cell, Color, ContentArrangement, Table};
+use ldap3::{Scope, SearchEntry};
+
+use crate::{
+    app::App,
+    cli::commands::Command,
+    data::credential::AuthMethod,
+    utils::ldap_operations::{filetime, filetime_to_string, first_attr, supported_etypes, uac_flags},
+};
+
+const USER_ATTRIBUTES: [&str; 10] = [
+    "sAMAccountName",
+    "userPrincipalName",
+    "userAccountControl",
+    "pwdLastSet",
+    "lastLogonTimestamp",
+    "badPwdCount",
+    "description",
+    "adminCount",
+    "servicePrincipalName",
+    "msDS-SupportedEncryptionTypes",
+];
+
+#[derive(Debug, Args)]
+pub struct UsersArgs {
+    /// Only enabled accounts
+    #[arg(long)]
+    pub enabled: bool,
+
+    /// Only accounts with adminCount=1 (current or former members of protected groups)
+    #[arg(long)]
+    pub admin_count: bool,
+
+    /// Only accounts with a service principal name (kerberoastable)
+    #[arg(long)]
+    pub spn: bool,
+
+    /// Only accounts without kerberos pre-authentication (AS-REP roastable)
+    #[arg(long)]
+    pub no_preauth: bool,
+
+    /// Only accounts that didn't log on for this many days
+    #[arg(long)]
+    pub stale: Option<u32>,
+
+    /// File to write the sAMAccountNames of the listed users to, one per line
+    #[arg(short, long)]
+    pub wordlist: Option<PathBuf>,
+
+    /// Authentication protocol for LDAP
+    #[arg(long, value_enum, default_value_t = AuthMethod::Auto)]
+    pub auth: AuthMethod,
+}
+
+impl UsersArgs {
+    /// Search filter of the selected accounts, `now` being the reference of --stale
+    fn filter(&self, now: DateTime<Utc>) -> String {
+        let mut filter = "(&(samAccountType=805306368)".to_string();
+        if self.enabled {
+            filter.push_str("(!(userAccountControl:1.2.840.113556.1.4.803:=2))");
+        }
+        if self.admin_count {
+            filter.push_str("(adminCount=1)");
+        }
+        if self.spn {
+            filter.push_str("(servicePrincipalName=*)");
+        }
+        if self.no_preauth {
+            filter.push_str("(userAccountControl:1.2.840.113556.1.4.803:=4194304)");
+        }
+        if let Some(days) = self.stale {
+            // lastLogonTimestamp replicates with up to 14 days of delay, unset means never logged on
+            let cutoff = filetime(now - Duration::days(days as i64));
+            filter.push_str(&format!("(|(!(lastLogonTimestamp=*))(lastLogonTimestamp<={}))", cutoff));
+        }
+        filter.push(')');
+        filter
+    }
+}
+
+fn filetime_cell(entry: &SearchEntry, name: &str) -> String {
+    first_attr(entry, name)
+        .and_then(|value| value.parse::<i64>().ok())
+        .map_or("never".to_string(), |value| filetime_to_string(value.max(0) as u64))
+}
+
+fn user_row(entry: &SearchEntry) -> Vec<Cell> {
+    let uac = first_attr(entry, "userAccountControl").and_then(|v| v.parse::<u32>().ok()).unwrap_or(0);
+    let flags = uac_flags(uac)
+        .into_iter()
+        .filter(|flag| *flag != "NORMAL_ACCOUNT")
+        .collect::<Vec<_>>()
+        .join("\n");
+    let etypes = first_attr(entry, "msDS-SupportedEncryptionTypes")
+        .and_then(|v| v.parse::<u32>().ok())
+        .map_or("default".to_string(), |etypes| supported_etypes(etypes).join(", "));
+    let spns = entry.attrs.get("servicePrincipalName").map_or(String::new(), |spns| spns.join("\n"));
+
+    let sam = Cell::new(first_attr(entry, "sAMAccountName").unwrap_or_default());
+    let sam = if uac & 0x2 != 0 {
+        sam.fg(Color::DarkGrey)
+    } else if first_attr(entry, "adminCount") == Some("1") {
+        sam.fg(Color::Red).add_attribute(Attribute::Bold)
+    } else {
+        sam
+    };
+
+    vec![
+        sam,
+        Cell::new(first_attr(entry, "userPrincipalName").unwrap_or_default()),
+        Cell::new(flags),
+        Cell::new(filetime_cell(entry, "pwdLastSet")),
+        Cell::new(filetime_cell(entry, "lastLogonTimestamp")),
+        Cell::new(first_attr(entry, "badPwdCount").unwrap_or("0")),
+        Cell::new(first_attr(entry, "adminCount").unwrap_or_default()),
+        Cell::new(spns),
+        Cell::new(etypes),
+        Cell::new(first_attr(entry, "description").unwrap_or_default()),
+    ]
+}
+
+impl Command for UsersArgs {
+    async fn execute(&self, app: &mut App) -> Result<bool, String> {
+        let mut ldap = app.ldap_session(self.auth).await?;
+        let base_dn = ldap.base_dn.clone();
+        let mut users = ldap
+            .paged_search(&base_dn, Scope::Subtree, &self.filter(Utc::now()), USER_ATTRIBUTES.to_vec(), None)
+            .await?;
+
+        if users.is_empty() {
+            println!("No users found");
+            return Ok(false);
+        }
+        users.sort_by_key(|user| first_attr(user, "sAMAccountName").unwrap_or_default().to_lowercase());
+
+        let mut table = Table::new();
+        table
+            .set_content_arrangement(ContentArrangement::Dynamic)
+            .set_header(
+                [
+                    "sAMAccountName",
+                    "UPN",
+                    "Flags",
+                    "Password Last Set",
+                    "Last Logon",
+                    "Bad Pwd",
+                    "Admin",
+                    "SPNs",
+                    "Encryption Types",
+                    "Description",
+                ]
+                .map(|h| Cell::new(h).add_attribute(Attribute::Bold).fg(Color::Cyan)),
+            );
+        for user in &users {
+            table.add_row(user_row(user));
+        }
+        println!("{}", table);
+        println!("{} users", users.len());
+
+        if let Some(wordlist) = &self.wordlist {
+            let names: Vec<&str> = users.iter().filter_map(|user| first_attr(user, "sAMAccountName")).collect();
+            std::fs::write(wordlist, names.join("\n") + "\n").map_err(|e| e.to_string())?;
+            println!("Wrote {} usernames to {}", names.len(), wordlist.display());
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args() -> UsersArgs {
+        UsersArgs {
+            enabled: false,
+            admin_count: false,
+            spn: false,
+            no_preauth: false,
+            stale: None,
+            wordlist: None,
+            auth: AuthMethod::Auto,
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        assert_eq!(args().filter(now), "(&(samAccountType=805306368))");
+
+        let filter = UsersArgs { enabled: true, spn: true, ..args() }.filter(now);
+        assert_eq!(
+            filter,
+            "(&(samAccountType=805306368)(!(userAccountControl:1.2.840.113556.1.4.803:=2))(servicePrincipalName=*))"
+        );
+
+        // 90 days before now, in 100ns intervals since 1601
+        let filter = UsersArgs { stale: Some(90), ..args() }.filter(now);
+        assert_eq!(
+            filter,
+            "(&(samAccountType=805306368)(|(!(lastLogonTimestamp=*))(lastLogonTimestamp<=133366976000000000)))"
+        );
+    }
+}
@@ -0,0 +1,20 @@
+use clap::Args;
+
+use crate::{app::App, cli::commands::Command, data::credential::AuthMethod};
+
+#[derive(Debug, Args)]
+pub struct WhoamiArgs {
+    /// Authentication protocol for LDAP
+    #[arg(long, value_enum, default_value_t = AuthMethod::Auto)]
+    pub auth: AuthMethod,
+}
+
+impl Command for WhoamiArgs {
+    async fn execute(&self, app: &mut App) -> Result<bool, String> {
+        let mut ldap = app.ldap_session(self.auth).await?;
+        let identity = ldap.whoami().await?;
+        println!("Bound as {} ({:?} bind)", identity, ldap.bind);
+        println!("Protection: {}", ldap.protection());
+        Ok(false)
+    }
+}
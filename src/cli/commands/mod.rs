@@ -4,12 +4,13 @@ mod clear;
 mod dc;
 mod creds;
 mod kerberos;
+mod ldap;
 mod smb;
 
 use std::future::Future;
 use clap::{command, Parser, Subcommand};
 
-use crate::{app::App, cli::commands::{clear::ClearArgs, creds::CredsArgs, dc::DcArgs, exit::ExitArgs, kerberos::KerberosArgs, smb::SmbArgs}};
+use crate::{app::App, cli::commands::{clear::ClearArgs, creds::CredsArgs, dc::DcArgs, exit::ExitArgs, kerberos::KerberosArgs, ldap::LdapArgs, smb::SmbArgs}};
 
 macro_rules! handle_commands {
     ($command:expr, $app:expr, $($variant:ident),*) => {
@@ -38,6 +39,7 @@ impl Cli {
             Dc,
             Creds,
             Kerberos,
+            Ldap,
             Smb
         )
     }
@@ -60,6 +62,8 @@ pub enum Commands {
 
     #[command(about = "Kerberos Operations")]
     Kerberos(KerberosArgs),
+    #[command(about = "Ldap Operations")]
+    Ldap(LdapArgs),
     #[command(about = "Smb Operations")]
     Smb(SmbArgs),
 }
\ No newline at end of file
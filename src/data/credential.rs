@@ -2,6 +2,9 @@ use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 
 use clap::ValueEnum;
+use himmelblau_kerberos_constants::etypes::RC4_HMAC;
+
+use crate::kerberos::etype_name;
 
 #[derive(Debug, Clone, ValueEnum)]
 pub enum AuthType {
@@ -11,6 +14,17 @@ pub enum AuthType {
     Tgt,
 }
 
+/// Authentication protocol used for SMB and LDAP connections
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum AuthMethod {
+    /// Kerberos, falling back to NTLM when it is unavailable
+    Auto,
+    Kerberos,
+    Ntlm,
+    /// LDAP simple bind with the password, sent in clear text unless the DC uses LDAPS
+    Simple,
+}
+
 #[derive(Debug, Clone, ValueEnum)]
 pub enum CredType {
     DomainUser,
@@ -100,6 +114,9 @@ pub enum AuthData {
     /// Kerberos ticket (base64 encoded)
     KerberosTicket(String),
     
+    /// Kerberos long term key (hex encoded), e.g. from a keytab
+    KerberosKey { etype: i32, key: String },
+    
     /// Certificate for PKINIT
     Certificate {
         cert_data: String,
@@ -113,6 +130,25 @@ pub enum AuthData {
     Custom(HashMap<String, String>),
 }
 
+/// LM hash of an empty password, set when no LM hash is stored
+pub const EMPTY_LM_HASH: &str = "aad3b435b51404eeaad3b435b51404ee";
+
+impl AuthData {
+    /// Parse a hash in NT or LM:NT format
+    pub fn from_hash_string(hash: &str) -> AuthData {
+        match hash.trim().split_once(':') {
+            Some((lm, nt)) if lm.is_empty() || lm.eq_ignore_ascii_case(EMPTY_LM_HASH) => {
+                AuthData::NtlmHash(nt.to_lowercase())
+            }
+            Some((lm, nt)) => AuthData::LmNtlm {
+                lm: lm.to_lowercase(),
+                ntlm: nt.to_lowercase(),
+            },
+            None => AuthData::NtlmHash(hash.trim().to_lowercase()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum CredentialType {
     /// Regular domain user
@@ -279,6 +315,18 @@ impl Credential {
         )
     }
     
+    /// NT hash of the credential, if it is a password or holds one
+    pub fn nt_hash(&self) -> Option<String> {
+        match &self.auth_data {
+            AuthData::Password(pass) => Some(ntlm_hash::ntlm_hash(pass.as_str()).to_lowercase()),
+            AuthData::NtlmHash(hash) => Some(hash.to_lowercase()),
+            AuthData::LmNtlm { ntlm, .. } => Some(ntlm.to_lowercase()),
+            // The RC4 key is the NT hash
+            AuthData::KerberosKey { etype: RC4_HMAC, key } => Some(key.to_lowercase()),
+            _ => None,
+        }
+    }
+    
     /// Get a safe representation of the auth data type (without sensitive data)
     pub fn auth_data_type(&self) -> String {
         match &self.auth_data {
@@ -287,6 +335,7 @@ impl Credential {
             AuthData::LmHash(l) => format!("{}", l),
             AuthData::LmNtlm { .. } => "LM/NTLM Hash".to_string(),
             AuthData::KerberosTicket(_) => "Kerberos Ticket".to_string(),
+            AuthData::KerberosKey { etype, .. } => format!("Kerberos Key ({})", etype_name(*etype)),
             AuthData::Certificate { .. } => "Certificate".to_string(),
             AuthData::Token(_) => "Token".to_string(),
             AuthData::Custom(_) => "Custom".to_string(),
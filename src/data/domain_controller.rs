@@ -1,5 +1,7 @@
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::collections::HashMap;
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -20,7 +22,64 @@ pub struct DomainController {
     /// Whether LDAPS is enabled
     pub ldaps_enabled: bool,
     
+    /// How to reach the KDCs of the domain
+    #[serde(default)]
+    pub kdc: KdcSettings,
+    
+    /// Offset of the DC clock from the local clock in seconds, applied to kerberos timestamps
+    #[serde(default)]
+    pub clock_skew_secs: i64,
 }
+
+/// KDC transport settings of a domain controller
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct KdcSettings {
+    /// Kerberos port of the domain controller, from the SRV record for located KDCs
+    pub port: u16,
+    
+    /// Other KDCs of the domain, tried in order when the domain controller doesn't answer
+    pub extra_kdcs: Vec<SocketAddr>,
+    
+    /// Timeout of a single request in seconds
+    pub timeout_secs: u64,
+    
+    /// Additional attempts per KDC before failing over
+    pub retries: u32,
+    
+    /// Send requests over UDP first
+    pub udp: bool,
+    
+    /// MS-KKDCP proxy used when none of the KDCs can be reached
+    pub proxy: Option<KdcProxySettings>,
+}
+
+/// KDC proxy (MS-KKDCP) of a domain controller
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct KdcProxySettings {
+    /// Proxy endpoint, e.g. https://gateway.corp.local/KdcProxy
+    pub url: String,
+    
+    /// PEM file with CA certificates trusted for the proxy in addition to the system roots
+    pub ca_file: Option<PathBuf>,
+    
+    /// Accept any certificate presented by the proxy
+    pub insecure: bool,
+}
+
+impl Default for KdcSettings {
+    fn default() -> Self {
+        Self {
+            port: 88,
+            extra_kdcs: Vec::new(),
+            timeout_secs: 10,
+            retries: 1,
+            udp: false,
+            proxy: None,
+        }
+    }
+}
+
 impl DomainController {
     /// Create a new DomainController instance
     pub fn new(
@@ -33,6 +92,8 @@ impl DomainController {
             ldap_port: 389,
             gc_port: 3268,
             ldaps_enabled: false,
+            kdc: KdcSettings::default(),
+            clock_skew_secs: 0,
         }
     }
     
@@ -40,12 +101,28 @@ impl DomainController {
     pub fn ldap_url(&self) -> String {
         let protocol = if self.ldaps_enabled { "ldaps" } else { "ldap" };
         let port = if self.ldaps_enabled { 636 } else { self.ldap_port };
-        format!("{}://{}:{}", protocol, self.ip_address, port)
+        format!("{}://{}", protocol, SocketAddr::new(self.ip_address, port))
     }
     
     /// Get the Global Catalog connection string
     pub fn gc_url(&self) -> String {
         let port = if self.ldaps_enabled { 3269 } else { self.gc_port };
-        format!("ldap://{}:{}", self.ip_address, port)
+        format!("ldap://{}", SocketAddr::new(self.ip_address, port))
+    }
+    
+    /// Current time on the domain controller clock
+    pub fn now(&self) -> DateTime<Utc> {
+        Utc::now() + Duration::seconds(self.clock_skew_secs)
+    }
+    
+    /// Addresses of the KDCs of the domain, the domain controller first
+    pub fn kdc_addresses(&self) -> Vec<SocketAddr> {
+        let mut kdcs = vec![SocketAddr::new(self.ip_address, self.kdc.port)];
+        for kdc in &self.kdc.extra_kdcs {
+            if !kdcs.contains(kdc) {
+                kdcs.push(*kdc);
+            }
+        }
+        kdcs
     }
 }
\ No newline at end of file
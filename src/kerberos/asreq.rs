@@ -0,0 +1,117 @@
+use chrono::{DateTime, Duration, Utc};
+use himmelblau_kerberos_asn1::{
+    AsRep, AsReq, Asn1Object, EncAsRepPart, EncryptedData, EncryptionKey, KerberosFlags, KerberosTime, KrbError, PaData,
+    PaEncTsEnc, PaPacRequest,
+};
+use himmelblau_kerberos_constants::{
+    kdc_options::{FORWARDABLE, PROXIABLE, RENEWABLE},
+    key_usages::{KEY_USAGE_AS_REP_ENC_PART, KEY_USAGE_AS_REQ_TIMESTAMP},
+    pa_data_types::{PA_ENC_TIMESTAMP, PA_PAC_REQUEST},
+    principal_names::{NT_PRINCIPAL, NT_SRV_INST},
+};
+use himmelblau_kerberos_ccache::Credential as CcacheCredential;
+use himmelblau_kerberos_crypto::new_kerberos_cipher;
+
+use crate::{
+    data::DomainController,
+    kerberos::{
+        tgs::random_nonce,
+        ticket::{ccache_credential, principal_name},
+        transport::KdcTransport,
+    },
+};
+
+/// Reply of the KDC to an AS-REQ
+pub enum AsReply {
+    AsRep(AsRep),
+    Error(KrbError),
+}
+
+/// Build an AS-REQ for a krbtgt ticket of `username`
+pub fn build_as_req(
+    realm: &str,
+    username: &str,
+    etypes: &[i32],
+    mut padata: Vec<PaData>,
+    now: DateTime<Utc>,
+) -> AsReq {
+    let realm = realm.to_uppercase();
+
+    let mut as_req = AsReq::default();
+    as_req.req_body.kdc_options = KerberosFlags::from(FORWARDABLE | RENEWABLE | PROXIABLE);
+    as_req.req_body.cname = Some(principal_name(NT_PRINCIPAL, username));
+    as_req.req_body.realm = realm.clone();
+    as_req.req_body.sname = Some(principal_name(NT_SRV_INST, &format!("krbtgt/{}", realm)));
+    as_req.req_body.till = KerberosTime::from(now + Duration::days(1));
+    as_req.req_body.rtime = Some(KerberosTime::from(now + Duration::days(1)));
+    as_req.req_body.nonce = random_nonce();
+    as_req.req_body.etypes = etypes.to_vec();
+
+    padata.push(PaData::new(PA_PAC_REQUEST, PaPacRequest::new(true).build()));
+    as_req.padata = Some(padata);
+    as_req
+}
+
+/// Send an AS-REQ to the KDC, KRB-ERROR replies are returned instead of failing
+pub async fn send_as_req(dc: &DomainController, as_req: &AsReq) -> Result<AsReply, String> {
+    send_as_req_via(&KdcTransport::for_dc(dc), as_req).await
+}
+
+/// Send an AS-REQ through a given transport, KRB-ERROR replies are returned instead of failing
+pub async fn send_as_req_via(transport: &KdcTransport, as_req: &AsReq) -> Result<AsReply, String> {
+    let response = transport.send(&as_req.build()).await?;
+    if let Ok((_, as_rep)) = AsRep::parse(&response) {
+        return Ok(AsReply::AsRep(as_rep));
+    }
+    match KrbError::parse(&response) {
+        Ok((_, krb_error)) => Ok(AsReply::Error(krb_error)),
+        Err(e) => Err(format!("Invalid KDC response: {}", e)),
+    }
+}
+
+/// Derive the long term key of a user from its password.
+/// AES keys are salted with the default user salt (REALM + username).
+pub fn password_key(etype: i32, realm: &str, username: &str, password: &str) -> Result<Vec<u8>, String> {
+    let cipher = new_kerberos_cipher(etype).map_err(|e| e.to_string())?;
+    Ok(cipher.generate_key_from_string(password, password_salt(realm, username).as_bytes()))
+}
+
+/// Salt of the AES keys: REALMusername, or REALMhostname.realm for machine accounts
+pub fn password_salt(realm: &str, username: &str) -> String {
+    match username.strip_suffix('$') {
+        Some(host) => format!("{}host{}.{}", realm.to_uppercase(), host.to_lowercase(), realm.to_lowercase()),
+        None => format!("{}{}", realm.to_uppercase(), username),
+    }
+}
+
+/// PA-ENC-TIMESTAMP pre-authentication data encrypted with the user key, `now` is the KDC time
+pub fn pa_enc_timestamp(etype: i32, key: &[u8], now: DateTime<Utc>) -> Result<PaData, String> {
+    let cipher = new_kerberos_cipher(etype).map_err(|e| e.to_string())?;
+    let timestamp = PaEncTsEnc::from(now);
+    let encrypted = cipher.encrypt(key, KEY_USAGE_AS_REQ_TIMESTAMP, &timestamp.build());
+    Ok(PaData::new(PA_ENC_TIMESTAMP, EncryptedData::new(etype, None, encrypted).build()))
+}
+
+/// Whether the encrypted part of an AS-REP was encrypted with `key`
+pub fn as_rep_matches_key(as_rep: &AsRep, etype: i32, key: &[u8]) -> bool {
+    if as_rep.enc_part.etype != etype {
+        return false;
+    }
+    match new_kerberos_cipher(etype) {
+        Ok(cipher) => cipher
+            .decrypt(key, KEY_USAGE_AS_REP_ENC_PART, &as_rep.enc_part.cipher)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Decrypt an AS-REP with the reply key and turn it into a ccache credential
+pub fn as_rep_to_credential(as_rep: &AsRep, reply_key: &EncryptionKey) -> Result<CcacheCredential, String> {
+    let cipher = new_kerberos_cipher(reply_key.keytype).map_err(|e| e.to_string())?;
+    let plain = cipher
+        .decrypt(&reply_key.keyvalue, KEY_USAGE_AS_REP_ENC_PART, &as_rep.enc_part.cipher)
+        .map_err(|e| format!("Failed to decrypt AS-REP: {}", e))?;
+    let (_, enc_part) = EncAsRepPart::parse(&plain).map_err(|e| e.to_string())?;
+
+    Ok(ccache_credential(&as_rep.crealm, &as_rep.cname, &as_rep.ticket, &enc_part))
+}
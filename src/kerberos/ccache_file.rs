@@ -0,0 +1,65 @@
+use std::path::PathBuf;
+
+use himmelblau_kerberos_ccache::{CCache, Credential as CcacheCredential, Header};
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::data::DomainController;
+
+/// Serializes GSSAPI binds, which all read the same ccache and krb5.conf
+static GSSAPI_LOCK: Mutex<()> = Mutex::const_new(());
+
+fn ccache_path() -> PathBuf {
+    std::env::temp_dir().join(format!("hydraad_{}.ccache", std::process::id()))
+}
+
+fn config_path() -> PathBuf {
+    std::env::temp_dir().join(format!("hydraad_{}.krb5.conf", std::process::id()))
+}
+
+/// Point the system GSSAPI at the ccache and krb5.conf of the process. Changes the process
+/// environment, so it must run before the runtime or any other thread is started.
+pub fn init() {
+    unsafe {
+        std::env::set_var("KRB5CCNAME", format!("FILE:{}", ccache_path().display()));
+        std::env::set_var("KRB5_CONFIG", config_path());
+    }
+}
+
+/// Ccache and krb5.conf for libraries using the system GSSAPI (ldap3).
+/// Holds the GSSAPI lock until dropped, so the files stay the ones of this bind.
+pub struct KerberosEnvironment {
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl KerberosEnvironment {
+    pub async fn acquire(dc: &DomainController, credentials: Vec<CcacheCredential>) -> Result<Self, String> {
+        let first = credentials.first().ok_or("No tickets to write".to_string())?;
+        let primary_principal = first.client.clone();
+        let ccache = CCache::new(Header::default(), primary_principal, credentials);
+
+        let guard = GSSAPI_LOCK.lock().await;
+        std::fs::write(ccache_path(), ccache.build()).map_err(|e| e.to_string())?;
+        std::fs::write(config_path(), krb5_conf(dc)).map_err(|e| e.to_string())?;
+
+        Ok(Self { _guard: guard })
+    }
+}
+
+impl Drop for KerberosEnvironment {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(ccache_path());
+        let _ = std::fs::remove_file(config_path());
+    }
+}
+
+/// krb5.conf pointing the realm at the domain controller
+pub fn krb5_conf(dc: &DomainController) -> String {
+    let realm = dc.domain_name.to_uppercase();
+    let kdcs: String = dc.kdc_addresses().iter().map(|kdc| format!("        kdc = {}\n", kdc)).collect();
+    format!(
+        "[libdefaults]\n    default_realm = {realm}\n    dns_lookup_kdc = false\n    rdns = false\n\n[realms]\n    {realm} = {{\n{kdcs}    }}\n\n[domain_realm]\n    .{domain} = {realm}\n    {domain} = {realm}\n",
+        realm = realm,
+        kdcs = kdcs,
+        domain = dc.domain_name.to_lowercase(),
+    )
+}
@@ -0,0 +1,35 @@
+//! Clock skew between the local host and the KDC. Kerberos rejects timestamps
+//! more than 5 minutes off with KRB_AP_ERR_SKEW, every KRB-ERROR carries the KDC
+//! time so it can be measured without credentials.
+
+use chrono::{DateTime, Utc};
+use himmelblau_kerberos_asn1::KrbError;
+
+use crate::{
+    data::DomainController,
+    kerberos::{
+        asreq::{build_as_req, send_as_req, AsReply},
+        tgs::DEFAULT_ETYPES,
+    },
+};
+
+/// Time of the KDC when it sent the error
+pub fn krb_error_time(krb_error: &KrbError) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(krb_error.stime.timestamp(), krb_error.susec * 1000)
+}
+
+/// Offset in seconds of the KDC clock from the local clock, from an error it just sent
+pub fn krb_error_skew(krb_error: &KrbError) -> Option<i64> {
+    krb_error_time(krb_error).map(|time| (time - Utc::now()).num_seconds())
+}
+
+/// Ask the KDC for its time with an AS-REQ for a principal that doesn't exist
+pub async fn kdc_time(dc: &DomainController) -> Result<DateTime<Utc>, String> {
+    let realm = dc.domain_name.to_uppercase();
+    let username = format!("hydraad-{}", uuid::Uuid::new_v4().simple());
+    let as_req = build_as_req(&realm, &username, DEFAULT_ETYPES, Vec::new(), dc.now());
+    match send_as_req(dc, &as_req).await? {
+        AsReply::Error(krb_error) => krb_error_time(&krb_error).ok_or("Invalid time in KDC reply".to_string()),
+        AsReply::AsRep(_) => Err("KDC answered with an AS-REP".to_string()),
+    }
+}
@@ -0,0 +1,202 @@
+//! Kerberos crypto the kerberos crypto crate doesn't expose: keyed checksums by key type,
+//! the pseudo-random functions (RFC 3961, RFC 3962, RFC 4757) and KRB-FX-CF2 (RFC 6113).
+
+use himmelblau_kerberos_asn1::EncryptionKey;
+use himmelblau_kerberos_constants::etypes::{AES128_CTS_HMAC_SHA1_96, AES256_CTS_HMAC_SHA1_96, RC4_HMAC};
+use himmelblau_kerberos_crypto::{checksum_hmac_md5, checksum_sha_aes, AesSizes};
+use openssl::{
+    hash::MessageDigest,
+    pkey::PKey,
+    sha::sha1,
+    sign::Signer,
+    symm::{Cipher, Crypter, Mode},
+};
+
+use crate::kerberos::tgs::random_bytes;
+
+pub const HMAC_MD5_CHECKSUM: i32 = -138;
+pub const HMAC_SHA1_96_AES128_CHECKSUM: i32 = 15;
+pub const HMAC_SHA1_96_AES256_CHECKSUM: i32 = 16;
+
+/// Length of the keys of an encryption type
+pub fn key_size(etype: i32) -> Result<usize, String> {
+    match etype {
+        AES256_CTS_HMAC_SHA1_96 => Ok(32),
+        AES128_CTS_HMAC_SHA1_96 | RC4_HMAC => Ok(16),
+        _ => Err(format!("Unsupported encryption type {}", etype)),
+    }
+}
+
+/// Random key of the given encryption type
+pub fn random_key(etype: i32) -> Result<EncryptionKey, String> {
+    Ok(EncryptionKey {
+        keytype: etype,
+        keyvalue: random_bytes(key_size(etype)?),
+    })
+}
+
+/// Checksum type and length of the keyed checksums made with `key`
+pub fn checksum_type(key: &EncryptionKey) -> Result<(i32, usize), String> {
+    match key.keytype {
+        RC4_HMAC => Ok((HMAC_MD5_CHECKSUM, 16)),
+        AES256_CTS_HMAC_SHA1_96 => Ok((HMAC_SHA1_96_AES256_CHECKSUM, 12)),
+        AES128_CTS_HMAC_SHA1_96 => Ok((HMAC_SHA1_96_AES128_CHECKSUM, 12)),
+        etype => Err(format!("Unsupported key type {} for checksums", etype)),
+    }
+}
+
+/// Keyed checksum of `data` with the mandatory checksum type of the key
+pub fn keyed_checksum(key: &EncryptionKey, key_usage: i32, data: &[u8]) -> Result<Vec<u8>, String> {
+    let checksum = match key.keytype {
+        RC4_HMAC => checksum_hmac_md5(&key.keyvalue, key_usage, data),
+        AES256_CTS_HMAC_SHA1_96 => checksum_sha_aes(&key.keyvalue, key_usage, data, &AesSizes::Aes256),
+        AES128_CTS_HMAC_SHA1_96 => checksum_sha_aes(&key.keyvalue, key_usage, data, &AesSizes::Aes128),
+        etype => return Err(format!("Unsupported key type {} for checksums", etype)),
+    };
+    let (_, len) = checksum_type(key)?;
+    Ok(checksum[..len].to_vec())
+}
+
+/// n-fold of RFC 3961: replicate the input rotated by 13 bits and add the blocks with end-around carry
+pub fn nfold(input: &[u8], out_len: usize) -> Vec<u8> {
+    fn gcd(a: usize, b: usize) -> usize {
+        if b == 0 { a } else { gcd(b, a % b) }
+    }
+    let in_bits = input.len() * 8;
+    let lcm = input.len() * out_len / gcd(input.len(), out_len);
+
+    let mut out = vec![0u8; out_len];
+    let mut chunk = vec![0u8; out_len];
+    for offset in (0..lcm).step_by(out_len) {
+        chunk.iter_mut().for_each(|b| *b = 0);
+        for i in 0..out_len * 8 {
+            // Bit `i` of the chunk comes from the copy rotated right by 13 bits per repetition
+            let position = offset * 8 + i;
+            let rotation = 13 * (position / in_bits);
+            let source = (position % in_bits + in_bits - rotation % in_bits) % in_bits;
+            let bit = (input[source / 8] >> (7 - source % 8)) & 1;
+            chunk[i / 8] |= bit << (7 - i % 8);
+        }
+
+        let mut carry = 0u32;
+        for i in (0..out_len).rev() {
+            let sum = out[i] as u32 + chunk[i] as u32 + carry;
+            out[i] = sum as u8;
+            carry = sum >> 8;
+        }
+        while carry != 0 {
+            for i in (0..out_len).rev() {
+                let sum = out[i] as u32 + carry;
+                out[i] = sum as u8;
+                carry = sum >> 8;
+                if carry == 0 {
+                    break;
+                }
+            }
+        }
+    }
+    out
+}
+
+fn aes_encrypt_block(key: &[u8], block: &[u8]) -> Result<Vec<u8>, String> {
+    let cipher = match key.len() {
+        16 => Cipher::aes_128_ecb(),
+        32 => Cipher::aes_256_ecb(),
+        _ => return Err("Invalid AES key length".to_string()),
+    };
+    let mut crypter = Crypter::new(cipher, Mode::Encrypt, key, None).map_err(|e| e.to_string())?;
+    crypter.pad(false);
+    let mut out = vec![0; block.len() + 16];
+    let mut len = crypter.update(block, &mut out).map_err(|e| e.to_string())?;
+    len += crypter.finalize(&mut out[len..]).map_err(|e| e.to_string())?;
+    out.truncate(len);
+    Ok(out)
+}
+
+/// DK(key, constant) of the AES encryption types, their random-to-key is the identity
+fn aes_derive_key(key: &[u8], constant: &[u8]) -> Result<Vec<u8>, String> {
+    let mut block = nfold(constant, 16);
+    let mut derived = Vec::with_capacity(key.len() + 16);
+    while derived.len() < key.len() {
+        block = aes_encrypt_block(key, &block)?;
+        derived.extend_from_slice(&block);
+    }
+    derived.truncate(key.len());
+    Ok(derived)
+}
+
+fn hmac_sha1(key: &[u8], data: &[u8]) -> Result<Vec<u8>, String> {
+    let key = PKey::hmac(key).map_err(|e| e.to_string())?;
+    let mut signer = Signer::new(MessageDigest::sha1(), &key).map_err(|e| e.to_string())?;
+    signer.update(data).map_err(|e| e.to_string())?;
+    signer.sign_to_vec().map_err(|e| e.to_string())
+}
+
+/// Pseudo-random function of the key's encryption type
+pub fn prf(key: &EncryptionKey, data: &[u8]) -> Result<Vec<u8>, String> {
+    match key.keytype {
+        AES128_CTS_HMAC_SHA1_96 | AES256_CTS_HMAC_SHA1_96 => {
+            let prf_key = aes_derive_key(&key.keyvalue, b"prf")?;
+            aes_encrypt_block(&prf_key, &sha1(data)[..16])
+        }
+        RC4_HMAC => hmac_sha1(&key.keyvalue, data),
+        etype => Err(format!("Unsupported key type {} for the PRF", etype)),
+    }
+}
+
+/// PRF+ of RFC 6113: PRF(key, 1 | pepper) | PRF(key, 2 | pepper) | ... truncated to `len`
+pub fn prf_plus(key: &EncryptionKey, pepper: &[u8], len: usize) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(len + 20);
+    let mut counter = 1u8;
+    while out.len() < len {
+        let mut data = vec![counter];
+        data.extend_from_slice(pepper);
+        out.extend(prf(key, &data)?);
+        counter += 1;
+    }
+    out.truncate(len);
+    Ok(out)
+}
+
+/// KRB-FX-CF2: combine two keys into one of the type of `key1`
+pub fn cf2(key1: &EncryptionKey, key2: &EncryptionKey, pepper1: &[u8], pepper2: &[u8]) -> Result<EncryptionKey, String> {
+    let len = key_size(key1.keytype)?;
+    let a = prf_plus(key1, pepper1, len)?;
+    let b = prf_plus(key2, pepper2, len)?;
+    Ok(EncryptionKey {
+        keytype: key1.keytype,
+        keyvalue: a.iter().zip(b).map(|(x, y)| x ^ y).collect(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use himmelblau_kerberos_crypto::new_kerberos_cipher;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_nfold() {
+        // RFC 3961 appendix A.1
+        assert_eq!(hex(&nfold(b"012345", 8)), "be072631276b1955");
+        assert_eq!(hex(&nfold(b"password", 7)), "78a07b6caf85fa");
+        assert_eq!(hex(&nfold(b"kerberos", 16)), "6b65726265726f737b9b5b2b93132b93");
+    }
+
+    #[test]
+    fn test_cf2() {
+        // RFC 6113 appendix A
+        for (etype, expected) in [
+            (AES128_CTS_HMAC_SHA1_96, "97df97e4b798b29eb31ed7280287a92a"),
+            (AES256_CTS_HMAC_SHA1_96, "4d6ca4e629785c1f01baf55e2e548566b9617ae3a96868c337cb93b5e72b1c7b"),
+        ] {
+            let cipher = new_kerberos_cipher(etype).unwrap();
+            let key1 = EncryptionKey { keytype: etype, keyvalue: cipher.generate_key_from_string("key1", b"key1") };
+            let key2 = EncryptionKey { keytype: etype, keyvalue: cipher.generate_key_from_string("key2", b"key2") };
+            assert_eq!(hex(&cf2(&key1, &key2, b"a", b"b").unwrap().keyvalue), expected);
+        }
+    }
+}
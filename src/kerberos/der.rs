@@ -0,0 +1,187 @@
+//! Minimal DER encoding and decoding for the structures the kerberos
+//! asn1 crate doesn't provide (PKINIT, CMS, authorization data).
+
+use chrono::{DateTime, Utc};
+
+pub const TAG_INTEGER: u8 = 0x02;
+pub const TAG_BIT_STRING: u8 = 0x03;
+pub const TAG_OCTET_STRING: u8 = 0x04;
+pub const TAG_OID: u8 = 0x06;
+pub const TAG_GENERALIZED_TIME: u8 = 0x18;
+pub const TAG_GENERAL_STRING: u8 = 0x1b;
+pub const TAG_SEQUENCE: u8 = 0x30;
+pub const TAG_SET: u8 = 0x31;
+
+fn length(len: usize) -> Vec<u8> {
+    if len < 0x80 {
+        return vec![len as u8];
+    }
+    let bytes: Vec<u8> = len.to_be_bytes().into_iter().skip_while(|b| *b == 0).collect();
+    let mut out = vec![0x80 | bytes.len() as u8];
+    out.extend(bytes);
+    out
+}
+
+pub fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    out.extend(length(content.len()));
+    out.extend_from_slice(content);
+    out
+}
+
+pub fn sequence(items: &[Vec<u8>]) -> Vec<u8> {
+    tlv(TAG_SEQUENCE, &items.concat())
+}
+
+pub fn set(items: &[Vec<u8>]) -> Vec<u8> {
+    tlv(TAG_SET, &items.concat())
+}
+
+/// Explicit context specific tag [n]
+pub fn explicit(n: u8, content: &[u8]) -> Vec<u8> {
+    tlv(0xa0 | n, content)
+}
+
+/// Unsigned big endian integer
+pub fn integer_bytes(bytes: &[u8]) -> Vec<u8> {
+    let mut content: Vec<u8> = bytes.iter().copied().skip_while(|b| *b == 0).collect();
+    if content.first().map_or(true, |b| b & 0x80 != 0) {
+        content.insert(0, 0);
+    }
+    tlv(TAG_INTEGER, &content)
+}
+
+pub fn integer(value: u64) -> Vec<u8> {
+    integer_bytes(&value.to_be_bytes())
+}
+
+pub fn octet_string(bytes: &[u8]) -> Vec<u8> {
+    tlv(TAG_OCTET_STRING, bytes)
+}
+
+pub fn bit_string(bytes: &[u8]) -> Vec<u8> {
+    let mut content = vec![0];
+    content.extend_from_slice(bytes);
+    tlv(TAG_BIT_STRING, &content)
+}
+
+pub fn null() -> Vec<u8> {
+    vec![0x05, 0x00]
+}
+
+/// Object identifier from its dotted representation
+pub fn oid(dotted: &str) -> Vec<u8> {
+    let arcs: Vec<u64> = dotted.split('.').filter_map(|a| a.parse().ok()).collect();
+    let mut content = Vec::new();
+    if arcs.len() >= 2 {
+        content.push((arcs[0] * 40 + arcs[1]) as u8);
+    }
+    for arc in arcs.iter().skip(2) {
+        let mut groups = vec![(*arc & 0x7f) as u8];
+        let mut value = *arc >> 7;
+        while value > 0 {
+            groups.push((value & 0x7f) as u8 | 0x80);
+            value >>= 7;
+        }
+        content.extend(groups.into_iter().rev());
+    }
+    tlv(TAG_OID, &content)
+}
+
+pub fn generalized_time(time: DateTime<Utc>) -> Vec<u8> {
+    tlv(TAG_GENERALIZED_TIME, time.format("%Y%m%d%H%M%SZ").to_string().as_bytes())
+}
+
+/// A decoded tag, length, value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tlv<'a> {
+    pub tag: u8,
+    pub content: &'a [u8],
+}
+
+impl<'a> Tlv<'a> {
+    /// Decode the content as a list of TLVs (SEQUENCE, SET or explicit tag content)
+    pub fn children(&self) -> Result<Vec<Tlv<'a>>, String> {
+        parse_all(self.content)
+    }
+
+    /// Child with the given tag
+    pub fn child(&self, tag: u8) -> Result<Tlv<'a>, String> {
+        self.children()?
+            .into_iter()
+            .find(|c| c.tag == tag)
+            .ok_or(format!("Missing DER element with tag {:#x}", tag))
+    }
+
+    /// Content of the single element wrapped in an explicit tag
+    pub fn inner(&self) -> Result<Tlv<'a>, String> {
+        parse_tlv(self.content).map(|(tlv, _)| tlv)
+    }
+
+    /// Integer value as unsigned big endian bytes
+    pub fn unsigned_bytes(&self) -> &'a [u8] {
+        match self.content {
+            [0, rest @ ..] if !rest.is_empty() => rest,
+            content => content,
+        }
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.content.iter().fold(0, |acc, b| (acc << 8) | *b as u64)
+    }
+}
+
+/// Decode one TLV and return the remaining data
+pub fn parse_tlv(data: &[u8]) -> Result<(Tlv<'_>, &[u8]), String> {
+    let truncated = || "Truncated DER data".to_string();
+    let tag = *data.first().ok_or_else(truncated)?;
+    let first = *data.get(1).ok_or_else(truncated)?;
+
+    let (len, header) = if first & 0x80 == 0 {
+        (first as usize, 2)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 {
+            return Err("Unsupported DER length".to_string());
+        }
+        let bytes = data.get(2..2 + count).ok_or_else(truncated)?;
+        (bytes.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize), 2 + count)
+    };
+
+    let content = data.get(header..header + len).ok_or_else(truncated)?;
+    Ok((Tlv { tag, content }, &data[header + len..]))
+}
+
+/// Decode consecutive TLVs
+pub fn parse_all(mut data: &[u8]) -> Result<Vec<Tlv<'_>>, String> {
+    let mut items = Vec::new();
+    while !data.is_empty() {
+        let (tlv, rest) = parse_tlv(data)?;
+        items.push(tlv);
+        data = rest;
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encoding() {
+        assert_eq!(oid("1.2.840.113549.1.7.2"), vec![0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02]);
+        assert_eq!(integer(0x80), vec![0x02, 0x02, 0x00, 0x80]);
+        assert_eq!(integer(0), vec![0x02, 0x01, 0x00]);
+        assert_eq!(length(0x1234), vec![0x82, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data = sequence(&[explicit(0, &integer(5)), explicit(1, &octet_string(&[0xaa; 200]))]);
+        let (tlv, rest) = parse_tlv(&data).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(tlv.tag, TAG_SEQUENCE);
+        assert_eq!(tlv.child(0xa0).unwrap().inner().unwrap().as_u64(), 5);
+        assert_eq!(tlv.child(0xa1).unwrap().inner().unwrap().content, &[0xaa; 200][..]);
+    }
+}
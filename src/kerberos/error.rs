@@ -0,0 +1,40 @@
+use himmelblau_kerberos_asn1::KrbError;
+
+pub const KDC_ERR_C_PRINCIPAL_UNKNOWN: i32 = 6;
+pub const KDC_ERR_S_PRINCIPAL_UNKNOWN: i32 = 7;
+pub const KDC_ERR_CLIENT_REVOKED: i32 = 18;
+pub const KDC_ERR_KEY_EXPIRED: i32 = 23;
+pub const KDC_ERR_PREAUTH_FAILED: i32 = 24;
+pub const KDC_ERR_PREAUTH_REQUIRED: i32 = 25;
+pub const KDC_ERR_ETYPE_NOSUPP: i32 = 14;
+pub const KRB_AP_ERR_SKEW: i32 = 37;
+pub const KRB_AP_ERR_MODIFIED: i32 = 41;
+
+/// Name of a kerberos error code
+pub fn error_code_name(code: i32) -> &'static str {
+    match code {
+        6 => "KDC_ERR_C_PRINCIPAL_UNKNOWN",
+        7 => "KDC_ERR_S_PRINCIPAL_UNKNOWN",
+        12 => "KDC_ERR_POLICY",
+        14 => "KDC_ERR_ETYPE_NOSUPP",
+        18 => "KDC_ERR_CLIENT_REVOKED",
+        23 => "KDC_ERR_KEY_EXPIRED",
+        24 => "KDC_ERR_PREAUTH_FAILED",
+        25 => "KDC_ERR_PREAUTH_REQUIRED",
+        31 => "KRB_AP_ERR_BAD_INTEGRITY",
+        32 => "KRB_AP_ERR_TKT_EXPIRED",
+        37 => "KRB_AP_ERR_SKEW",
+        41 => "KRB_AP_ERR_MODIFIED",
+        60 => "KRB_ERR_GENERIC",
+        68 => "KDC_ERR_WRONG_REALM",
+        _ => "UNKNOWN_ERROR",
+    }
+}
+
+pub fn krb_error_message(krb_error: &KrbError) -> String {
+    format!(
+        "Kerberos error {} ({})",
+        error_code_name(krb_error.error_code),
+        krb_error.error_code
+    )
+}
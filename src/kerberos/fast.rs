@@ -0,0 +1,316 @@
+//! FAST armoring (RFC 6113): the AS and TGS exchanges are tunnelled under a key derived from an
+//! armor ticket, and the encrypted challenge replaces the encrypted timestamp pre-authentication.
+
+use chrono::{DateTime, Utc};
+use himmelblau_kerberos_asn1::{
+    Asn1Object, Checksum, EncTgsRepPart, EncryptedData, EncryptionKey, KrbError, PaData, PaEncTsEnc, PrincipalName,
+    TgsRep,
+};
+use himmelblau_kerberos_ccache::Credential as CcacheCredential;
+use himmelblau_kerberos_constants::{
+    etypes::AES256_CTS_HMAC_SHA1_96,
+    key_usages::{KEY_USAGE_TGS_REP_ENC_PART_SUB_KEY, KEY_USAGE_TGS_REQ_AUTHEN},
+    pa_data_types::PA_TGS_REQ,
+};
+use himmelblau_kerberos_crypto::new_kerberos_cipher;
+
+use crate::{
+    data::{AuthData, Credential, DomainController},
+    kerberos::{
+        asreq::{as_rep_to_credential, build_as_req, send_as_req, AsReply},
+        crypto::{cf2, checksum_type, keyed_checksum, random_key},
+        der,
+        error::krb_error_message,
+        realm::{credential_realm, dc_for_realm},
+        tgs::{build_ap_req_for, build_tgs_req},
+        ticket::{ccache_credential, credential_key, decode_ticket, long_term_key, SessionTicket},
+        transport::send_to_kdc,
+    },
+    stores::domain_controller_store::DomainControllerStore,
+    utils::ldap_operations::sam_account_name,
+};
+
+pub const PA_FX_FAST: i32 = 136;
+pub const PA_FX_ERROR: i32 = 137;
+pub const PA_ENCRYPTED_CHALLENGE: i32 = 138;
+
+/// Armor type of an AP-REQ for the armor TGT
+const FX_FAST_ARMOR_AP_REQUEST: u64 = 1;
+
+const KEY_USAGE_AP_REQ_AUTHEN: i32 = 11;
+const KEY_USAGE_FAST_REQ_CHKSUM: i32 = 50;
+const KEY_USAGE_FAST_ENC: i32 = 51;
+const KEY_USAGE_FAST_REP: i32 = 52;
+const KEY_USAGE_ENC_CHALLENGE_CLIENT: i32 = 54;
+
+/// Decrypted KrbFastResponse
+pub struct FastResponse {
+    pub padata: Vec<PaData>,
+    pub strengthen_key: Option<EncryptionKey>,
+}
+
+impl FastResponse {
+    /// Key of the KDC reply: `key`, combined with the strengthen key when the KDC sent one
+    pub fn reply_key(&self, key: &EncryptionKey) -> Result<EncryptionKey, String> {
+        match &self.strengthen_key {
+            Some(strengthen_key) => cf2(strengthen_key, key, b"strengthenkey", b"replykey"),
+            None => Ok(key.clone()),
+        }
+    }
+}
+
+/// Key of the FAST tunnel, and the armor sent to the KDC (none for the implicit armor of TGS-REQs)
+pub struct FastArmor {
+    pub armor: Option<Vec<u8>>,
+    pub armor_key: EncryptionKey,
+}
+
+impl FastArmor {
+    /// Explicit armor of an AS exchange: an AP-REQ for the armor TGT carrying a fresh subkey
+    pub fn from_ticket(armor_tgt: &SessionTicket, now: DateTime<Utc>) -> Result<Self, String> {
+        let subkey = random_key(armor_tgt.session_key.keytype)?;
+        let ap_req = build_ap_req_for(armor_tgt, now, KEY_USAGE_AP_REQ_AUTHEN, Some(&subkey))?;
+        let armor = der::sequence(&[
+            der::explicit(0, &der::integer(FX_FAST_ARMOR_AP_REQUEST)),
+            der::explicit(1, &der::octet_string(&ap_req.build())),
+        ]);
+        Ok(Self {
+            armor: Some(armor),
+            armor_key: cf2(&subkey, &armor_tgt.session_key, b"subkeyarmor", b"ticketarmor")?,
+        })
+    }
+
+    /// Implicit armor of a TGS-REQ, from the subkey of its authenticator and the TGT session key
+    pub fn implicit(tgt: &SessionTicket, subkey: &EncryptionKey) -> Result<Self, String> {
+        Ok(Self {
+            armor: None,
+            armor_key: cf2(subkey, &tgt.session_key, b"subkeyarmor", b"ticketarmor")?,
+        })
+    }
+
+    /// PA-ENCRYPTED-CHALLENGE proving the client key inside the tunnel, `now` is the KDC time
+    pub fn encrypted_challenge(&self, client_key: &EncryptionKey, now: DateTime<Utc>) -> Result<PaData, String> {
+        let challenge_key = cf2(&self.armor_key, client_key, b"clientchallengearmor", b"challengelongterm")?;
+        let cipher = new_kerberos_cipher(challenge_key.keytype).map_err(|e| e.to_string())?;
+        let encrypted = cipher.encrypt(
+            &challenge_key.keyvalue,
+            KEY_USAGE_ENC_CHALLENGE_CLIENT,
+            &PaEncTsEnc::from(now).build(),
+        );
+        Ok(PaData::new(
+            PA_ENCRYPTED_CHALLENGE,
+            EncryptedData::new(challenge_key.keytype, None, encrypted).build(),
+        ))
+    }
+
+    /// PA-FX-FAST carrying the encrypted KrbFastReq with the request body and the inner padata.
+    /// The req-checksum covers `checksummed`: the request body of AS-REQs, the PA-TGS-REQ AP-REQ
+    /// of TGS-REQs (RFC 6113 5.4.2).
+    pub fn armored_padata(&self, req_body: &[u8], checksummed: &[u8], padata: &[PaData]) -> Result<PaData, String> {
+        let padata: Vec<Vec<u8>> = padata.iter().map(|p| p.build()).collect();
+        let fast_req = der::sequence(&[
+            der::explicit(0, &der::bit_string(&[0; 4])),
+            der::explicit(1, &der::sequence(&padata)),
+            der::explicit(2, req_body),
+        ]);
+
+        let cipher = new_kerberos_cipher(self.armor_key.keytype).map_err(|e| e.to_string())?;
+        let enc_fast_req = EncryptedData::new(
+            self.armor_key.keytype,
+            None,
+            cipher.encrypt(&self.armor_key.keyvalue, KEY_USAGE_FAST_ENC, &fast_req),
+        );
+        let (checksum_type, _) = checksum_type(&self.armor_key)?;
+        let req_checksum = Checksum::new(
+            checksum_type,
+            keyed_checksum(&self.armor_key, KEY_USAGE_FAST_REQ_CHKSUM, checksummed)?,
+        );
+
+        let mut armored_req = Vec::new();
+        if let Some(armor) = &self.armor {
+            armored_req.push(der::explicit(0, armor));
+        }
+        armored_req.push(der::explicit(1, &req_checksum.build()));
+        armored_req.push(der::explicit(2, &enc_fast_req.build()));
+        Ok(PaData::new(PA_FX_FAST, der::explicit(0, &der::sequence(&armored_req))))
+    }
+
+    /// Decrypt the KrbFastResponse of the PA-FX-FAST of a KDC reply
+    pub fn response(&self, padata: Option<&Vec<PaData>>) -> Result<FastResponse, String> {
+        let pa_fx_fast = padata
+            .and_then(|padata| padata.iter().find(|p| p.padata_type == PA_FX_FAST))
+            .ok_or("KDC reply isn't armored, the KDC may not support FAST".to_string())?;
+
+        // PA-FX-FAST-REPLY ::= CHOICE { armored-data [0] KrbFastArmoredRep { enc-fast-rep [0] EncryptedData } }
+        let (reply, _) = der::parse_tlv(&pa_fx_fast.padata_value)?;
+        let enc_fast_rep = reply.inner()?.child(0xa0)?;
+        let (_, enc_fast_rep) = EncryptedData::parse(enc_fast_rep.content).map_err(|e| e.to_string())?;
+        let cipher = new_kerberos_cipher(self.armor_key.keytype).map_err(|e| e.to_string())?;
+        let plain = cipher
+            .decrypt(&self.armor_key.keyvalue, KEY_USAGE_FAST_REP, &enc_fast_rep.cipher)
+            .map_err(|e| format!("Failed to decrypt FAST response: {}", e))?;
+
+        // KrbFastResponse ::= SEQUENCE { padata [0], strengthen-key [1] OPTIONAL, finished [2] OPTIONAL, nonce [3] }
+        let (response, _) = der::parse_tlv(&plain)?;
+        let padata = parse_method_data(response.child(0xa0)?.content)?;
+        let strengthen_key = match response.child(0xa1) {
+            Ok(key) => Some(EncryptionKey::parse(key.content).map_err(|e| e.to_string())?.1),
+            Err(_) => None,
+        };
+        Ok(FastResponse { padata, strengthen_key })
+    }
+
+    /// Message of a KRB-ERROR, taken from the PA-FX-ERROR it wraps when armored
+    pub fn error_message(&self, krb_error: &KrbError) -> String {
+        match self.unwrap_error(krb_error) {
+            Some(inner) => krb_error_message(&inner),
+            None => krb_error_message(krb_error),
+        }
+    }
+
+    fn unwrap_error(&self, krb_error: &KrbError) -> Option<KrbError> {
+        let padata = parse_method_data(krb_error.e_data.as_deref()?).ok()?;
+        let response = self.response(Some(&padata)).ok()?;
+        let fx_error = response.padata.iter().find(|p| p.padata_type == PA_FX_ERROR)?;
+        KrbError::parse(&fx_error.padata_value).ok().map(|(_, inner)| inner)
+    }
+}
+
+/// Decode a SEQUENCE OF PA-DATA (METHOD-DATA)
+fn parse_method_data(data: &[u8]) -> Result<Vec<PaData>, String> {
+    let (sequence, _) = der::parse_tlv(data)?;
+    sequence
+        .children()?
+        .iter()
+        .map(|entry| {
+            let padata_type = entry.child(0xa1)?.inner()?.as_u64() as i32;
+            let padata_value = entry.child(0xa2)?.inner()?.content.to_vec();
+            Ok(PaData::new(padata_type, padata_value))
+        })
+        .collect()
+}
+
+/// Armor TGT of a stored ticket credential
+pub fn armor_ticket(creds: &Credential) -> Result<SessionTicket, String> {
+    let AuthData::KerberosTicket(data) = &creds.auth_data else {
+        return Err(format!("Credential {} isn't a ticket, armoring needs a TGT", creds.id));
+    };
+    let ticket = decode_ticket(data)?;
+    if !ticket.is_tgt() {
+        return Err(format!("Armor credential holds a ticket for {}, not a TGT", ticket.server_principal()));
+    }
+    Ok(ticket)
+}
+
+/// Request a TGT for the password, hash or kerberos key credential with an AS exchange armored by
+/// the TGT of `armor`. Password credentials use their AES256 key.
+pub async fn armored_tgt(
+    dc_storage: &DomainControllerStore,
+    dc: &DomainController,
+    creds: &Credential,
+    armor: &Credential,
+) -> Result<CcacheCredential, String> {
+    let armor_tgt = armor_ticket(armor)?;
+    let realm_dc = dc_for_realm(dc_storage, dc, &credential_realm(creds, dc)).await?;
+    let key = long_term_key(creds, AES256_CTS_HMAC_SHA1_96, &realm_dc.domain_name).or_else(|_| credential_key(creds))?;
+    armored_initial_ticket(&realm_dc, &armor_tgt, &sam_account_name(&creds.username), &key, None).await
+}
+
+/// Request a ticket from an AS exchange armored with `armor_tgt`, pre-authenticated with the encrypted challenge
+pub async fn armored_initial_ticket(
+    dc: &DomainController,
+    armor_tgt: &SessionTicket,
+    username: &str,
+    key: &EncryptionKey,
+    sname: Option<&PrincipalName>,
+) -> Result<CcacheCredential, String> {
+    let realm = dc.domain_name.to_uppercase();
+    let armor = FastArmor::from_ticket(armor_tgt, dc.now())?;
+    let challenge = armor.encrypted_challenge(key, dc.now())?;
+
+    let mut as_req = build_as_req(&realm, username, &[key.keytype], vec![challenge], dc.now());
+    if let Some(sname) = sname {
+        as_req.req_body.sname = Some(sname.clone());
+    }
+    let inner_padata = as_req.padata.take().unwrap_or_default();
+    let req_body = as_req.req_body.build();
+    as_req.padata = Some(vec![armor.armored_padata(&req_body, &req_body, &inner_padata)?]);
+
+    match send_as_req(dc, &as_req).await? {
+        AsReply::AsRep(as_rep) => {
+            let reply_key = armor.response(as_rep.padata.as_ref())?.reply_key(key)?;
+            as_rep_to_credential(&as_rep, &reply_key)
+        }
+        AsReply::Error(krb_error) => Err(armor.error_message(&krb_error)),
+    }
+}
+
+/// Request a service ticket with a TGS-REQ armored by the subkey of its authenticator
+pub async fn armored_service_ticket(
+    dc: &DomainController,
+    tgt: &SessionTicket,
+    sname: &PrincipalName,
+    etypes: &[i32],
+) -> Result<CcacheCredential, String> {
+    let now = dc.now();
+    let subkey = random_key(tgt.session_key.keytype)?;
+    let armor = FastArmor::implicit(tgt, &subkey)?;
+
+    let mut tgs_req = build_tgs_req(tgt, &tgt.tgt_realm(), sname, etypes, now)?;
+    let ap_req = build_ap_req_for(tgt, now, KEY_USAGE_TGS_REQ_AUTHEN, Some(&subkey))?.build();
+    let fast = armor.armored_padata(&tgs_req.req_body.build(), &ap_req, &[])?;
+    tgs_req.padata = Some(vec![PaData::new(PA_TGS_REQ, ap_req), fast]);
+
+    let response = send_to_kdc(dc, &tgs_req.build()).await?;
+    let tgs_rep = match TgsRep::parse(&response) {
+        Ok((_, tgs_rep)) => tgs_rep,
+        Err(_) => {
+            let (_, krb_error) = KrbError::parse(&response).map_err(|e| format!("Invalid KDC response: {}", e))?;
+            return Err(armor.error_message(&krb_error));
+        }
+    };
+
+    // The reply is encrypted with the (strengthened) subkey instead of the TGT session key
+    let reply_key = armor.response(tgs_rep.padata.as_ref())?.reply_key(&subkey)?;
+    let cipher = new_kerberos_cipher(tgs_rep.enc_part.etype).map_err(|e| e.to_string())?;
+    let plain = cipher
+        .decrypt(&reply_key.keyvalue, KEY_USAGE_TGS_REP_ENC_PART_SUB_KEY, &tgs_rep.enc_part.cipher)
+        .map_err(|e| format!("Failed to decrypt TGS-REP: {}", e))?;
+    let (_, enc_part) = EncTgsRepPart::parse(&plain).map_err(|e| e.to_string())?;
+
+    Ok(ccache_credential(&tgs_rep.crealm, &tgs_rep.cname, &tgs_rep.ticket, &enc_part))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_method_data_roundtrip() {
+        let padata = vec![PaData::new(PA_FX_ERROR, vec![1, 2, 3]), PaData::new(PA_ENCRYPTED_CHALLENGE, vec![])];
+        let encoded: Vec<Vec<u8>> = padata.iter().map(|p| p.build()).collect();
+        let parsed = parse_method_data(&der::sequence(&encoded)).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].padata_type, PA_FX_ERROR);
+        assert_eq!(parsed[0].padata_value, vec![1, 2, 3]);
+        assert_eq!(parsed[1].padata_type, PA_ENCRYPTED_CHALLENGE);
+    }
+
+    #[test]
+    fn test_req_checksum_covers_checksummed_data() {
+        let armor = FastArmor {
+            armor: None,
+            armor_key: EncryptionKey { keytype: AES256_CTS_HMAC_SHA1_96, keyvalue: vec![7; 32] },
+        };
+        let (req_body, ap_req) = (vec![0x30, 0x01, 0x00], vec![0x6e, 0x01, 0x00]);
+        let pa_fx_fast = armor.armored_padata(&req_body, &ap_req, &[]).unwrap();
+
+        // PA-FX-FAST-REQUEST [0] KrbFastArmoredReq { req-checksum [1] Checksum, enc-fast-req [2] }
+        let (request, _) = der::parse_tlv(&pa_fx_fast.padata_value).unwrap();
+        // Checksum ::= SEQUENCE { cksumtype [0] Int32, checksum [1] OCTET STRING }
+        let checksum = request.inner().unwrap().child(0xa1).unwrap().inner().unwrap();
+        let checksum = checksum.child(0xa1).unwrap().inner().unwrap().content;
+        assert_eq!(checksum, keyed_checksum(&armor.armor_key, KEY_USAGE_FAST_REQ_CHKSUM, &ap_req).unwrap());
+        assert_ne!(checksum, keyed_checksum(&armor.armor_key, KEY_USAGE_FAST_REQ_CHKSUM, &req_body).unwrap());
+    }
+}
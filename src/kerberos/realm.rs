@@ -0,0 +1,61 @@
+use crate::{
+    data::{Credential, DomainController},
+    stores::domain_controller_store::DomainControllerStore,
+    utils::dns_operations::resolve_kdcs,
+};
+
+/// Realm of a credential: the suffix of a user@realm name, the domain of the DC otherwise
+pub fn credential_realm(creds: &Credential, dc: &DomainController) -> String {
+    match creds.username.split_once('@') {
+        Some((_, realm)) if !realm.is_empty() => realm.to_uppercase(),
+        _ => dc.domain_name.to_uppercase(),
+    }
+}
+
+/// Locate a KDC of `realm` through the DNS server of `dc`.
+/// The KDC settings and clock offset of `dc` are kept, trusted domains are usually in sync.
+pub async fn locate_realm_dc(dc: &DomainController, realm: &str) -> Result<DomainController, String> {
+    if realm.eq_ignore_ascii_case(&dc.domain_name) {
+        return Ok(dc.clone());
+    }
+
+    let kdcs = resolve_kdcs(dc, realm).await?;
+    let mut realm_dc = DomainController::new(kdcs[0].ip(), realm.to_lowercase());
+    realm_dc.kdc = dc.kdc.clone();
+    realm_dc.kdc.proxy = None;
+    realm_dc.kdc.port = kdcs[0].port();
+    realm_dc.kdc.extra_kdcs = kdcs.into_iter().skip(1).collect();
+    realm_dc.clock_skew_secs = dc.clock_skew_secs;
+    Ok(realm_dc)
+}
+
+/// Domain controller of `realm`, from the store when known, located through DNS otherwise
+pub async fn dc_for_realm(
+    dc_storage: &DomainControllerStore,
+    dc: &DomainController,
+    realm: &str,
+) -> Result<DomainController, String> {
+    if realm.eq_ignore_ascii_case(&dc.domain_name) {
+        return Ok(dc.clone());
+    }
+    if let Some(realm_dc) = dc_storage.get_domain_controller(&realm.to_lowercase()) {
+        return Ok(realm_dc.clone());
+    }
+    locate_realm_dc(dc, realm)
+        .await
+        .map_err(|e| format!("{}, add a domain controller of {} with dc add", e, realm))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_credential_realm() {
+        let dc = DomainController::new("10.10.11.76".parse().unwrap(), "voleur.htb".to_string());
+        let creds = |username: &str| Credential::new_password(username.to_string(), String::new(), "test".to_string());
+        assert_eq!(credential_realm(&creds("svc_ldap"), &dc), "VOLEUR.HTB");
+        assert_eq!(credential_realm(&creds("svc_ldap@child.voleur.htb"), &dc), "CHILD.VOLEUR.HTB");
+        assert_eq!(credential_realm(&creds("svc_ldap@"), &dc), "VOLEUR.HTB");
+    }
+}
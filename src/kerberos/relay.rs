@@ -0,0 +1,302 @@
+//! Local KDC used to pass stored tickets to sspi based clients (smb).
+//!
+//! sspi only knows how to authenticate with a password. A single relay listens for the whole
+//! process and is set as `SSPI_KDC_URL` once, before any thread starts. Each SMB identity
+//! registers its ticket with a random password: AS-REQs are answered with the registered TGT
+//! of the client principal encrypted for that password, and TGS-REQs are either forwarded to
+//! the real KDC (TGT credentials) or answered with the registered service ticket.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex, OnceLock,
+};
+
+use chrono::{Duration, Utc};
+use himmelblau_kerberos_asn1::{
+    ApReq, AsRep, AsReq, Asn1Object, Authenticator, EncAsRepPart, EncKdcRepPart, EncTgsRepPart,
+    EncryptedData, EncryptionKey, EtypeInfo2Entry, KerberosTime, LastReqEntry, PaData, Ticket,
+    TgsRep, TgsReq,
+};
+use himmelblau_kerberos_constants::{
+    etypes::{AES128_CTS_HMAC_SHA1_96, AES256_CTS_HMAC_SHA1_96, RC4_HMAC},
+    key_usages::{
+        KEY_USAGE_AS_REP_ENC_PART, KEY_USAGE_TGS_REP_ENC_PART_SESSION_KEY,
+        KEY_USAGE_TGS_REP_ENC_PART_SUB_KEY, KEY_USAGE_TGS_REQ_AUTHEN,
+    },
+    pa_data_types::{PA_ETYPE_INFO2, PA_TGS_REQ},
+    principal_names::NT_SRV_INST,
+    ticket_flags::{FORWARDABLE, INITIAL, PRE_AUTHENT, RENEWABLE},
+};
+use himmelblau_kerberos_crypto::new_kerberos_cipher;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    data::DomainController,
+    kerberos::{
+        tgs::random_bytes,
+        ticket::{principal_name, SessionTicket},
+        transport::{KdcTransport, MAX_TCP_MESSAGE},
+    },
+};
+
+/// Listener bound by `init`, moved into the runtime by the first registration
+static LISTENER: OnceLock<Mutex<Option<std::net::TcpListener>>> = OnceLock::new();
+/// Identities currently authenticating through the relay
+static REGISTRATIONS: Mutex<Vec<Arc<RelayState>>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+struct RelayState {
+    id: u64,
+    kdc: KdcTransport,
+    realm: String,
+    username: String,
+    password: String,
+    /// TGT handed out in the AS-REP, either the stored one or a placeholder
+    tgt: SessionTicket,
+    /// Stored service ticket, TGS-REQs are forwarded to the KDC when unset
+    service_ticket: Option<SessionTicket>,
+}
+
+/// Registration of a ticket with the relay, removed when dropped
+pub struct TicketRelay {
+    id: u64,
+    password: String,
+}
+
+/// Placeholder TGT used when only a service ticket is available, it never reaches a real KDC
+fn placeholder_tgt(service_ticket: &SessionTicket) -> SessionTicket {
+    let realm = service_ticket.server_realm.clone();
+    let server_name = principal_name(NT_SRV_INST, &format!("krbtgt/{}", realm));
+    let mut ticket = Ticket::default();
+    ticket.realm = realm.clone();
+    ticket.sname = server_name.clone();
+    ticket.enc_part = EncryptedData::new(AES256_CTS_HMAC_SHA1_96, Some(2), random_bytes(128));
+
+    SessionTicket {
+        client_realm: service_ticket.client_realm.clone(),
+        client_name: service_ticket.client_name.clone(),
+        server_realm: realm,
+        server_name,
+        ticket,
+        session_key: EncryptionKey {
+            keytype: AES256_CTS_HMAC_SHA1_96,
+            keyvalue: random_bytes(32),
+        },
+    }
+}
+
+/// Encrypted part of a forged KDC reply handing out `session_key` for `server`
+fn enc_kdc_rep_part(server: &SessionTicket, session_key: &EncryptionKey, nonce: u32) -> EncKdcRepPart {
+    let now = Utc::now();
+    let mut part = EncKdcRepPart::default();
+    part.key = session_key.clone();
+    part.last_req = vec![LastReqEntry::new(0, KerberosTime::from(now))];
+    part.nonce = nonce;
+    part.flags = (FORWARDABLE | RENEWABLE | INITIAL | PRE_AUTHENT).into();
+    part.authtime = KerberosTime::from(now);
+    part.starttime = Some(KerberosTime::from(now));
+    part.endtime = KerberosTime::from(now + Duration::hours(10));
+    part.renew_till = Some(KerberosTime::from(now + Duration::days(7)));
+    part.srealm = server.server_realm.clone();
+    part.sname = server.server_name.clone();
+    part
+}
+
+impl RelayState {
+    fn salt(&self) -> Vec<u8> {
+        format!("{}{}", self.realm.to_uppercase(), self.username).into_bytes()
+    }
+
+    /// Answer an AS-REQ with the TGT, encrypted with a key derived from the relay password
+    fn handle_as_req(&self, as_req: &AsReq) -> Result<Vec<u8>, String> {
+        let etype = as_req
+            .req_body
+            .etypes
+            .iter()
+            .copied()
+            .find(|e| [AES256_CTS_HMAC_SHA1_96, AES128_CTS_HMAC_SHA1_96, RC4_HMAC].contains(e))
+            .ok_or("No supported etype in AS-REQ".to_string())?;
+        let cipher = new_kerberos_cipher(etype).map_err(|e| e.to_string())?;
+        let key = cipher.generate_key_from_string(&self.password, &self.salt());
+
+        let enc_part = EncAsRepPart::from(enc_kdc_rep_part(&self.tgt, &self.tgt.session_key, as_req.req_body.nonce));
+        let encrypted = cipher.encrypt(&key, KEY_USAGE_AS_REP_ENC_PART, &enc_part.build());
+
+        let etype_info = vec![EtypeInfo2Entry::new(etype, Some(String::from_utf8_lossy(&self.salt()).to_string()), None)];
+        let mut as_rep = AsRep::default();
+        as_rep.padata = Some(vec![PaData::new(PA_ETYPE_INFO2, etype_info.build())]);
+        as_rep.crealm = self.tgt.client_realm.clone();
+        as_rep.cname = self.tgt.client_name.clone();
+        as_rep.ticket = self.tgt.ticket.clone();
+        as_rep.enc_part = EncryptedData::new(etype, None, encrypted);
+        Ok(as_rep.build())
+    }
+
+    /// Answer a TGS-REQ with the stored service ticket
+    fn handle_tgs_req(&self, tgs_req: &TgsReq, service_ticket: &SessionTicket) -> Result<Vec<u8>, String> {
+        let ap_req_data = tgs_req
+            .padata
+            .iter()
+            .flatten()
+            .find(|p| p.padata_type == PA_TGS_REQ)
+            .ok_or("TGS-REQ without PA-TGS-REQ".to_string())?;
+        let (_, ap_req) = ApReq::parse(&ap_req_data.padata_value).map_err(|e| e.to_string())?;
+
+        // The reply is encrypted with the authenticator subkey when the client sent one
+        let tgt_key = &self.tgt.session_key;
+        let cipher = new_kerberos_cipher(tgt_key.keytype).map_err(|e| e.to_string())?;
+        let plain = cipher
+            .decrypt(&tgt_key.keyvalue, KEY_USAGE_TGS_REQ_AUTHEN, &ap_req.authenticator.cipher)
+            .map_err(|e| e.to_string())?;
+        let (_, authenticator) = Authenticator::parse(&plain).map_err(|e| e.to_string())?;
+        let (reply_key, key_usage) = match authenticator.subkey {
+            Some(subkey) => (subkey, KEY_USAGE_TGS_REP_ENC_PART_SUB_KEY),
+            None => (tgt_key.clone(), KEY_USAGE_TGS_REP_ENC_PART_SESSION_KEY),
+        };
+
+        let enc_part = EncTgsRepPart::from(enc_kdc_rep_part(
+            service_ticket,
+            &service_ticket.session_key,
+            tgs_req.req_body.nonce,
+        ));
+        let cipher = new_kerberos_cipher(reply_key.keytype).map_err(|e| e.to_string())?;
+        let encrypted = cipher.encrypt(&reply_key.keyvalue, key_usage, &enc_part.build());
+
+        let mut tgs_rep = TgsRep::default();
+        tgs_rep.crealm = service_ticket.client_realm.clone();
+        tgs_rep.cname = service_ticket.client_name.clone();
+        tgs_rep.ticket = service_ticket.ticket.clone();
+        tgs_rep.enc_part = EncryptedData::new(reply_key.keytype, None, encrypted);
+        Ok(tgs_rep.build())
+    }
+
+    async fn handle_message(&self, request: &[u8]) -> Result<Vec<u8>, String> {
+        if let Ok((_, as_req)) = AsReq::parse(request) {
+            return self.handle_as_req(&as_req);
+        }
+        let (_, tgs_req) = TgsReq::parse(request).map_err(|e| format!("Unexpected kerberos message: {}", e))?;
+        match &self.service_ticket {
+            Some(service_ticket) => self.handle_tgs_req(&tgs_req, service_ticket),
+            None => self.kdc.send(request).await,
+        }
+    }
+}
+
+/// Registration a request belongs to: AS-REQs by client principal, TGS-REQs by the TGT they present
+fn registration_for(request: &[u8]) -> Result<Arc<RelayState>, String> {
+    let registrations = REGISTRATIONS.lock().map_err(|e| e.to_string())?;
+    let found = if let Ok((_, as_req)) = AsReq::parse(request) {
+        let cname = as_req.req_body.cname.map(|name| name.name_string.join("/")).unwrap_or_default();
+        registrations
+            .iter()
+            .rev()
+            .find(|r| r.username.eq_ignore_ascii_case(&cname) && r.realm.eq_ignore_ascii_case(&as_req.req_body.realm))
+    } else {
+        let (_, tgs_req) = TgsReq::parse(request).map_err(|e| format!("Unexpected kerberos message: {}", e))?;
+        let ap_req = tgs_req
+            .padata
+            .iter()
+            .flatten()
+            .find(|p| p.padata_type == PA_TGS_REQ)
+            .and_then(|p| ApReq::parse(&p.padata_value).ok())
+            .map(|(_, ap_req)| ap_req)
+            .ok_or("TGS-REQ without PA-TGS-REQ".to_string())?;
+        registrations
+            .iter()
+            .find(|r| r.tgt.ticket.enc_part.cipher == ap_req.ticket.enc_part.cipher)
+    };
+    found.cloned().ok_or("No identity registered for the request".to_string())
+}
+
+async fn handle_connection(mut stream: TcpStream) -> Result<(), String> {
+    let len = stream.read_u32().await.map_err(|e| e.to_string())? as usize;
+    if len > MAX_TCP_MESSAGE {
+        return Err(format!("Request too large ({} bytes)", len));
+    }
+    let mut request = vec![0; len];
+    stream.read_exact(&mut request).await.map_err(|e| e.to_string())?;
+
+    let response = registration_for(&request)?.handle_message(&request).await?;
+    stream
+        .write_all(&(response.len() as u32).to_be_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+    stream.write_all(&response).await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Bind the relay and point sspi at it. Changes the process environment, so it must run
+/// before the runtime or any other thread is started.
+pub fn init() -> Result<(), String> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").map_err(|e| e.to_string())?;
+    listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+    let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+    unsafe {
+        std::env::set_var("SSPI_KDC_URL", format!("tcp://127.0.0.1:{}", port));
+    }
+    LISTENER
+        .set(Mutex::new(Some(listener)))
+        .map_err(|_| "Ticket relay already initialized".to_string())
+}
+
+/// Start accepting connections on the first registration
+fn ensure_listening() -> Result<(), String> {
+    let listener = LISTENER.get().ok_or("Ticket relay not initialized".to_string())?;
+    let Some(listener) = listener.lock().map_err(|e| e.to_string())?.take() else {
+        return Ok(());
+    };
+    let listener = TcpListener::from_std(listener).map_err(|e| e.to_string())?;
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(stream).await {
+                    log::debug!("Ticket relay error: {}", e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+impl TicketRelay {
+    /// Register a ticket, which may be a TGT or a service ticket, for the client principal `username`
+    pub async fn start(dc: &DomainController, username: &str, ticket: SessionTicket) -> Result<Self, String> {
+        ensure_listening()?;
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let password = uuid::Uuid::new_v4().to_string();
+
+        let (tgt, service_ticket) = if ticket.is_tgt() {
+            (ticket, None)
+        } else {
+            (placeholder_tgt(&ticket), Some(ticket))
+        };
+        let state = Arc::new(RelayState {
+            id,
+            kdc: KdcTransport::for_dc(dc),
+            realm: tgt.client_realm.clone(),
+            username: username.to_string(),
+            password: password.clone(),
+            tgt,
+            service_ticket,
+        });
+        REGISTRATIONS.lock().map_err(|e| e.to_string())?.push(state);
+
+        Ok(Self { id, password })
+    }
+
+    /// Password the client has to authenticate with
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+}
+
+impl Drop for TicketRelay {
+    fn drop(&mut self) {
+        if let Ok(mut registrations) = REGISTRATIONS.lock() {
+            registrations.retain(|r| r.id != self.id);
+        }
+    }
+}
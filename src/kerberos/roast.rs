@@ -0,0 +1,89 @@
+use himmelblau_kerberos_constants::etypes::RC4_HMAC;
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Format an encrypted service ticket as a hashcat `$krb5tgs$` hash
+/// (mode 13100 for RC4, 19600/19700 for AES128/AES256)
+pub fn krb5tgs_hash(etype: i32, username: &str, realm: &str, spn: &str, cipher: &[u8]) -> String {
+    let spn = spn.replace(':', "~");
+    if etype == RC4_HMAC {
+        format!(
+            "$krb5tgs${}$*{}${}${}*${}${}",
+            etype,
+            username,
+            realm,
+            spn,
+            hex(&cipher[..16.min(cipher.len())]),
+            hex(&cipher[16.min(cipher.len())..])
+        )
+    } else {
+        let split = cipher.len().saturating_sub(12);
+        format!(
+            "$krb5tgs${}${}${}$*{}*${}${}",
+            etype,
+            username,
+            realm,
+            spn,
+            hex(&cipher[split..]),
+            hex(&cipher[..split])
+        )
+    }
+}
+
+/// Format an encrypted AS-REP part as a hashcat `$krb5asrep$` hash
+/// (mode 18200 for RC4, 32100/32200 for AES128/AES256)
+pub fn krb5asrep_hash(etype: i32, username: &str, realm: &str, cipher: &[u8]) -> String {
+    if etype == RC4_HMAC {
+        format!(
+            "$krb5asrep${}${}@{}:{}${}",
+            etype,
+            username,
+            realm,
+            hex(&cipher[..16.min(cipher.len())]),
+            hex(&cipher[16.min(cipher.len())..])
+        )
+    } else {
+        let split = cipher.len().saturating_sub(12);
+        format!(
+            "$krb5asrep${}${}${}${}${}",
+            etype,
+            username,
+            realm,
+            hex(&cipher[split..]),
+            hex(&cipher[..split])
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_krb5tgs_hash() {
+        let cipher: Vec<u8> = (0..20).collect();
+        assert_eq!(
+            krb5tgs_hash(23, "svc_sql", "VOLEUR.HTB", "MSSQLSvc/db.voleur.htb:1433", &cipher),
+            "$krb5tgs$23$*svc_sql$VOLEUR.HTB$MSSQLSvc/db.voleur.htb~1433*$000102030405060708090a0b0c0d0e0f$10111213"
+        );
+        assert_eq!(
+            krb5tgs_hash(18, "svc_sql", "VOLEUR.HTB", "http/web", &cipher),
+            "$krb5tgs$18$svc_sql$VOLEUR.HTB$*http/web*$08090a0b0c0d0e0f10111213$0001020304050607"
+        );
+    }
+
+    #[test]
+    fn test_krb5asrep_hash() {
+        let cipher: Vec<u8> = (0..20).collect();
+        assert_eq!(
+            krb5asrep_hash(23, "svc_backup", "VOLEUR.HTB", &cipher),
+            "$krb5asrep$23$svc_backup@VOLEUR.HTB:000102030405060708090a0b0c0d0e0f$10111213"
+        );
+        assert_eq!(
+            krb5asrep_hash(18, "svc_backup", "VOLEUR.HTB", &cipher),
+            "$krb5asrep$18$svc_backup$VOLEUR.HTB$08090a0b0c0d0e0f10111213$0001020304050607"
+        );
+    }
+}
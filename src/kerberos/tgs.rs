@@ -0,0 +1,262 @@
+use chrono::{DateTime, Duration, Utc};
+use himmelblau_kerberos_asn1::{
+    ApReq, Asn1Object, Authenticator, EncTgsRepPart, EncryptedData, EncryptionKey, KerberosFlags, KerberosTime,
+    KrbError, PaData, PrincipalName, TgsRep, TgsReq,
+};
+use himmelblau_kerberos_constants::{
+    etypes::{AES128_CTS_HMAC_SHA1_96, AES256_CTS_HMAC_SHA1_96, RC4_HMAC},
+    kdc_options::{CANONICALIZE, FORWARDABLE, RENEWABLE, RENEWABLE_OK},
+    key_usages::{KEY_USAGE_TGS_REP_ENC_PART_SESSION_KEY, KEY_USAGE_TGS_REQ_AUTHEN},
+    pa_data_types::PA_TGS_REQ,
+    principal_names::NT_SRV_INST,
+};
+use himmelblau_kerberos_ccache::Credential as CcacheCredential;
+use himmelblau_kerberos_crypto::new_kerberos_cipher;
+
+use crate::{
+    data::DomainController,
+    kerberos::{
+        error::krb_error_message,
+        realm::dc_for_realm,
+        ticket::{ccache_credential, decode_ccache, principal_name, session_ticket_from_ccache, SessionTicket},
+        transport::send_to_kdc,
+    },
+    stores::domain_controller_store::DomainControllerStore,
+};
+
+/// Encryption types requested by default, RC4 first so roastable tickets are preferred
+pub const DEFAULT_ETYPES: &[i32] = &[RC4_HMAC, AES256_CTS_HMAC_SHA1_96, AES128_CTS_HMAC_SHA1_96];
+
+pub fn random_nonce() -> u32 {
+    let bytes = random_bytes(4);
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) & 0x7fff_ffff
+}
+
+/// Bytes from the OpenSSL CSPRNG, used for session keys, subkeys and nonces
+pub fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0; len];
+    openssl::rand::rand_bytes(&mut bytes).expect("OpenSSL random generator failure");
+    bytes
+}
+
+/// Build the AP-REQ that authenticates a TGS-REQ with the given TGT, `now` is the KDC time
+pub fn build_ap_req(tgt: &SessionTicket, now: DateTime<Utc>) -> Result<ApReq, String> {
+    build_ap_req_for(tgt, now, KEY_USAGE_TGS_REQ_AUTHEN, None)
+}
+
+/// Build an AP-REQ for `ticket`, its authenticator encrypted with `key_usage` and optionally carrying a subkey
+pub fn build_ap_req_for(
+    ticket: &SessionTicket,
+    now: DateTime<Utc>,
+    key_usage: i32,
+    subkey: Option<&EncryptionKey>,
+) -> Result<ApReq, String> {
+    let mut authenticator = Authenticator::default();
+    authenticator.crealm = ticket.client_realm.clone();
+    authenticator.cname = ticket.client_name.clone();
+    authenticator.ctime = KerberosTime::from(now);
+    authenticator.cusec = now.timestamp_subsec_micros();
+    authenticator.subkey = subkey.cloned();
+
+    let cipher = new_kerberos_cipher(ticket.session_key.keytype).map_err(|e| e.to_string())?;
+    let encrypted = cipher.encrypt(&ticket.session_key.keyvalue, key_usage, &authenticator.build());
+
+    let mut ap_req = ApReq::default();
+    ap_req.ticket = ticket.ticket.clone();
+    ap_req.authenticator = EncryptedData::new(ticket.session_key.keytype, None, encrypted);
+    Ok(ap_req)
+}
+
+/// Build a TGS-REQ for the given service principal
+pub fn build_tgs_req(
+    tgt: &SessionTicket,
+    realm: &str,
+    sname: &PrincipalName,
+    etypes: &[i32],
+    now: DateTime<Utc>,
+) -> Result<TgsReq, String> {
+    let ap_req = build_ap_req(tgt, now)?;
+
+    let mut tgs_req = TgsReq::default();
+    tgs_req.req_body.kdc_options = KerberosFlags::from(FORWARDABLE | RENEWABLE | CANONICALIZE | RENEWABLE_OK);
+    tgs_req.req_body.realm = realm.to_uppercase();
+    tgs_req.req_body.sname = Some(sname.clone());
+    tgs_req.req_body.till = KerberosTime::from(now + Duration::days(1));
+    tgs_req.req_body.nonce = random_nonce();
+    tgs_req.req_body.etypes = etypes.to_vec();
+    tgs_req.padata = Some(vec![PaData::new(PA_TGS_REQ, ap_req.build())]);
+    Ok(tgs_req)
+}
+
+/// Send a TGS-REQ to the KDC and parse the reply
+pub async fn send_tgs_req(dc: &DomainController, tgs_req: &TgsReq) -> Result<TgsRep, String> {
+    let response = send_to_kdc(dc, &tgs_req.build()).await?;
+    if let Ok((_, tgs_rep)) = TgsRep::parse(&response) {
+        return Ok(tgs_rep);
+    }
+    match KrbError::parse(&response) {
+        Ok((_, krb_error)) => Err(krb_error_message(&krb_error)),
+        Err(e) => Err(format!("Invalid KDC response: {}", e)),
+    }
+}
+
+/// Request a service ticket for `sname` with the given TGT
+pub async fn request_service_ticket(
+    dc: &DomainController,
+    tgt: &SessionTicket,
+    sname: &PrincipalName,
+    etypes: &[i32],
+) -> Result<TgsRep, String> {
+    let tgs_req = build_tgs_req(tgt, &tgt.tgt_realm(), sname, etypes, dc.now())?;
+    send_tgs_req(dc, &tgs_req).await
+}
+
+/// Decrypt a TGS-REP with the TGT session key and turn it into a ccache credential
+pub fn tgs_rep_to_credential(tgt: &SessionTicket, tgs_rep: &TgsRep) -> Result<CcacheCredential, String> {
+    let cipher = new_kerberos_cipher(tgs_rep.enc_part.etype).map_err(|e| e.to_string())?;
+    let plain = cipher
+        .decrypt(
+            &tgt.session_key.keyvalue,
+            KEY_USAGE_TGS_REP_ENC_PART_SESSION_KEY,
+            &tgs_rep.enc_part.cipher,
+        )
+        .map_err(|e| format!("Failed to decrypt TGS-REP: {}", e))?;
+    let (_, enc_part) = EncTgsRepPart::parse(&plain).map_err(|e| e.to_string())?;
+
+    Ok(ccache_credential(&tgs_rep.crealm, &tgs_rep.cname, &tgs_rep.ticket, &enc_part))
+}
+
+/// Maximum number of referrals followed before giving up
+const MAX_REFERRALS: usize = 5;
+
+/// Request a service ticket, following referrals to the KDCs of other realms.
+/// Returns the service ticket credential.
+pub async fn request_service_ticket_with_referrals(
+    dc_storage: &DomainControllerStore,
+    dc: &DomainController,
+    tgt: &SessionTicket,
+    sname: &PrincipalName,
+    etypes: &[i32],
+) -> Result<CcacheCredential, String> {
+    let mut dc = dc.clone();
+    let mut tgt = tgt.clone();
+    for _ in 0..MAX_REFERRALS {
+        let tgs_rep = request_service_ticket(&dc, &tgt, sname, etypes).await?;
+        let credential = tgs_rep_to_credential(&tgt, &tgs_rep)?;
+        let ticket = session_ticket_from_ccache(&credential)?;
+
+        let is_referral = ticket.is_tgt()
+            && !sname.name_string.first().map_or(false, |s| s.eq_ignore_ascii_case("krbtgt"))
+            && !ticket.tgt_realm().eq_ignore_ascii_case(&tgt.tgt_realm());
+        if !is_referral {
+            return Ok(credential);
+        }
+
+        let realm = ticket.tgt_realm();
+        println!("Following referral to {}", realm);
+        dc = dc_for_realm(dc_storage, &dc, &realm).await?;
+        tgt = ticket;
+    }
+    Err("Too many referrals".to_string())
+}
+
+/// Realm a ticket of a trust path leads to: the REALM of krbtgt/REALM, which mustn't have been walked
+/// already (`visited`) or the KDCs would send us around in circles
+fn next_trust_hop(ticket: &SessionTicket, visited: &[String], target_realm: &str) -> Result<String, String> {
+    let next_realm = ticket.tgt_realm();
+    if !ticket.is_tgt() || visited.iter().any(|realm| realm.eq_ignore_ascii_case(&next_realm)) {
+        return Err(format!("No trust path from {} to {}", visited[0], target_realm));
+    }
+    Ok(next_realm)
+}
+
+/// Get a TGT usable at the KDC of `target_realm` by walking the trust path from the realm of `tgt`.
+/// Returns the domain controller of the target realm with the inter-realm TGT.
+pub async fn request_inter_realm_tgt(
+    dc_storage: &DomainControllerStore,
+    dc: &DomainController,
+    tgt: &SessionTicket,
+    target_realm: &str,
+) -> Result<(DomainController, CcacheCredential), String> {
+    let target_realm = target_realm.to_uppercase();
+    let sname = principal_name(NT_SRV_INST, &format!("krbtgt/{}", target_realm));
+    let mut dc = dc.clone();
+    let mut tgt = tgt.clone();
+    let mut visited = vec![tgt.tgt_realm()];
+    for _ in 0..MAX_REFERRALS {
+        let tgs_rep = request_service_ticket(&dc, &tgt, &sname, DEFAULT_ETYPES).await?;
+        let credential = tgs_rep_to_credential(&tgt, &tgs_rep)?;
+        let ticket = session_ticket_from_ccache(&credential)?;
+
+        // krbtgt/NEXT@CURRENT is used at the KDC of NEXT, either the target or a transit realm
+        let next_realm = next_trust_hop(&ticket, &visited, &target_realm)?;
+        visited.push(next_realm.clone());
+        dc = dc_for_realm(dc_storage, &dc, &next_realm).await?;
+        if next_realm.eq_ignore_ascii_case(&target_realm) {
+            return Ok((dc, credential));
+        }
+        println!("Following trust path through {}", next_realm);
+        tgt = ticket;
+    }
+    Err("Too many referrals".to_string())
+}
+
+/// Get the tickets needed to access `spn` from a stored kerberos ticket credential.
+/// A TGT is exchanged for a service ticket, a service ticket is used as is when it matches.
+pub async fn service_tickets_for(dc: &DomainController, data: &str, spn: &str) -> Result<Vec<CcacheCredential>, String> {
+    let ccache = decode_ccache(data)?;
+    let ticket = session_ticket_from_ccache(&ccache)?;
+    let spn_name = principal_name(NT_SRV_INST, spn);
+
+    if !ticket.is_tgt() {
+        let service = ticket.server_name.name_string.join("/");
+        if !service.eq_ignore_ascii_case(&spn_name.name_string.join("/")) {
+            return Err(format!("Stored ticket is for {}, not {}", service, spn));
+        }
+        return Ok(vec![ccache]);
+    }
+
+    let tgs_rep = request_service_ticket(dc, &ticket, &spn_name, DEFAULT_ETYPES).await?;
+    let service_ccache = tgs_rep_to_credential(&ticket, &tgs_rep)?;
+    Ok(vec![ccache, service_ccache])
+}
+
+#[cfg(test)]
+mod test {
+    use himmelblau_kerberos_asn1::Ticket;
+    use himmelblau_kerberos_constants::principal_names::NT_PRINCIPAL;
+
+    use super::*;
+
+    fn ticket(server: &str, realm: &str) -> SessionTicket {
+        SessionTicket {
+            client_realm: "A.LOCAL".to_string(),
+            client_name: principal_name(NT_PRINCIPAL, "user"),
+            server_realm: realm.to_string(),
+            server_name: principal_name(NT_SRV_INST, server),
+            ticket: Ticket::default(),
+            session_key: EncryptionKey {
+                keytype: AES256_CTS_HMAC_SHA1_96,
+                keyvalue: vec![0; 32],
+            },
+        }
+    }
+
+    #[test]
+    fn test_next_trust_hop() {
+        let visited = vec!["A.LOCAL".to_string()];
+        let referral = ticket("krbtgt/B.LOCAL", "A.LOCAL");
+        assert_eq!(next_trust_hop(&referral, &visited, "C.LOCAL").unwrap(), "B.LOCAL");
+
+        // The KDC of B sends us back to A
+        let visited = vec!["A.LOCAL".to_string(), "B.LOCAL".to_string()];
+        let back = ticket("krbtgt/a.local", "B.LOCAL");
+        assert_eq!(
+            next_trust_hop(&back, &visited, "C.LOCAL").unwrap_err(),
+            "No trust path from A.LOCAL to C.LOCAL"
+        );
+        // A TGT for the current realm, the KDC doesn't know the target
+        assert!(next_trust_hop(&ticket("krbtgt/B.LOCAL", "B.LOCAL"), &visited, "C.LOCAL").is_err());
+        assert!(next_trust_hop(&ticket("cifs/dc.b.local", "B.LOCAL"), &visited, "C.LOCAL").is_err());
+    }
+}
@@ -0,0 +1,335 @@
+use base64::{prelude::BASE64_STANDARD, Engine};
+use chrono::{Duration, Utc};
+use himmelblau_kerberos_asn1::{Asn1Object, EncKdcRepPart, EncryptionKey, PrincipalName, Ticket};
+use himmelblau_kerberos_ccache::{
+    CountedOctetString, Credential as CcacheCredential, KeyBlock, Principal, Times,
+};
+use himmelblau_kerberos_constants::{
+    etypes::{AES128_CTS_HMAC_SHA1_96, AES256_CTS_HMAC_SHA1_96, RC4_HMAC},
+    principal_names::NT_PRINCIPAL,
+};
+use himmelblau_kerberos_crypto::new_kerberos_cipher;
+
+use crate::{
+    data::{AuthData, Credential, DomainController},
+    kerberos::{
+        asreq::{as_rep_to_credential, build_as_req, pa_enc_timestamp, password_key, send_as_req, AsReply},
+        clock::krb_error_skew,
+        error::{krb_error_message, KRB_AP_ERR_SKEW},
+        etype_name,
+        pac::KEY_USAGE_TICKET_ENC_PART,
+        realm::{credential_realm, dc_for_realm},
+    },
+    stores::domain_controller_store::DomainControllerStore,
+    utils::ldap_operations::sam_account_name,
+};
+
+/// A ticket together with the session key needed to use it
+#[derive(Debug, Clone)]
+pub struct SessionTicket {
+    pub client_realm: String,
+    pub client_name: PrincipalName,
+    pub server_realm: String,
+    pub server_name: PrincipalName,
+    pub ticket: Ticket,
+    pub session_key: EncryptionKey,
+}
+
+impl SessionTicket {
+    /// Service principal as "service/host@REALM"
+    pub fn server_principal(&self) -> String {
+        format!("{}@{}", self.server_name.name_string.join("/"), self.server_realm)
+    }
+
+    /// Realm whose KDC accepts this TGT (REALM in krbtgt/REALM)
+    pub fn tgt_realm(&self) -> String {
+        self.server_name
+            .name_string
+            .get(1)
+            .cloned()
+            .unwrap_or(self.server_realm.clone())
+    }
+
+    pub fn is_tgt(&self) -> bool {
+        self.server_name
+            .name_string
+            .first()
+            .map_or(false, |s| s.eq_ignore_ascii_case("krbtgt"))
+    }
+}
+
+/// Names of the ticket flags set in `flags`
+pub fn ticket_flag_names(flags: u32) -> Vec<&'static str> {
+    const NAMES: [(u32, &str); 16] = [
+        (0x4000_0000, "forwardable"),
+        (0x2000_0000, "forwarded"),
+        (0x1000_0000, "proxiable"),
+        (0x0800_0000, "proxy"),
+        (0x0400_0000, "may-postdate"),
+        (0x0200_0000, "postdated"),
+        (0x0100_0000, "invalid"),
+        (0x0080_0000, "renewable"),
+        (0x0040_0000, "initial"),
+        (0x0020_0000, "pre-authent"),
+        (0x0010_0000, "hw-authent"),
+        (0x0008_0000, "transited-policy-checked"),
+        (0x0004_0000, "ok-as-delegate"),
+        (0x0000_8000, "anonymous"),
+        (0x0001_0000, "name-canonicalize"),
+        (0x8000_0000, "reserved"),
+    ];
+    NAMES
+        .iter()
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+/// Decrypt the EncTicketPart of a ticket with the long term key of its service
+pub fn decrypt_ticket(ticket: &Ticket, key: &[u8]) -> Result<Vec<u8>, String> {
+    let cipher = new_kerberos_cipher(ticket.enc_part.etype).map_err(|e| e.to_string())?;
+    cipher
+        .decrypt(key, KEY_USAGE_TICKET_ENC_PART, &ticket.enc_part.cipher)
+        .map_err(|e| format!("Failed to decrypt the ticket: {}", e))
+}
+
+/// Account owning the keys of a service principal: krbtgt, the computer account of a host service, or the user itself
+pub fn service_account_name(sname: &PrincipalName) -> String {
+    match sname.name_string.as_slice() {
+        [service, ..] if service.eq_ignore_ascii_case("krbtgt") => "krbtgt".to_string(),
+        [_, host, ..] => format!("{}$", host.split('.').next().unwrap_or(host)),
+        [name] => name.clone(),
+        [] => String::new(),
+    }
+}
+
+/// Build a principal name from "service/host" or "user" strings
+pub fn principal_name(name_type: i32, name: &str) -> PrincipalName {
+    PrincipalName {
+        name_type,
+        name_string: name.split('/').map(|s| s.to_string()).collect(),
+    }
+}
+
+pub fn hex_decode(hex: &str) -> Result<Vec<u8>, String> {
+    let hex = hex.trim();
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return Err(format!("Invalid hex string '{}'", hex));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|e| e.to_string()))
+        .collect()
+}
+
+/// Derive the RC4 key of a password or NTLM hash credential
+pub fn user_key(creds: &Credential) -> Result<EncryptionKey, String> {
+    let nt_hash = match &creds.auth_data {
+        AuthData::Password(pass) => ntlm_hash::ntlm_hash(pass.as_str()),
+        AuthData::NtlmHash(hash) => hash.clone(),
+        AuthData::LmNtlm { ntlm, .. } => ntlm.clone(),
+        _ => return Err("Unsupported authentication type".to_string()),
+    };
+    let keyvalue = hex_decode(&nt_hash)?;
+    if keyvalue.len() != 16 {
+        return Err("NT hashes are 32 hex characters".to_string());
+    }
+    Ok(EncryptionKey {
+        keytype: RC4_HMAC,
+        keyvalue,
+    })
+}
+
+/// Key of the credential to authenticate with: its stored kerberos key, or the RC4 key otherwise
+pub fn credential_key(creds: &Credential) -> Result<EncryptionKey, String> {
+    match &creds.auth_data {
+        AuthData::KerberosKey { etype, key } => Ok(EncryptionKey {
+            keytype: *etype,
+            keyvalue: hex_decode(key)?,
+        }),
+        _ => user_key(creds),
+    }
+}
+
+/// Long term key of the credential for `etype`, derived from the password when needed
+pub fn long_term_key(creds: &Credential, etype: i32, realm: &str) -> Result<EncryptionKey, String> {
+    match &creds.auth_data {
+        AuthData::KerberosKey { etype: key_etype, .. } if *key_etype == etype => credential_key(creds),
+        AuthData::Password(password) if etype != RC4_HMAC => Ok(EncryptionKey {
+            keytype: etype,
+            keyvalue: password_key(etype, realm, &sam_account_name(&creds.username), password)?,
+        }),
+        _ if etype == RC4_HMAC => user_key(creds),
+        _ => Err(format!("No {} key for {}", etype_name(etype), creds.username)),
+    }
+}
+
+/// Parse an hex encoded AES128 or AES256 key
+pub fn aes_key(hex: &str) -> Result<EncryptionKey, String> {
+    let keyvalue = hex_decode(hex)?;
+    let keytype = match keyvalue.len() {
+        16 => AES128_CTS_HMAC_SHA1_96,
+        32 => AES256_CTS_HMAC_SHA1_96,
+        _ => return Err("AES keys are 32 (AES128) or 64 (AES256) hex characters".to_string()),
+    };
+    Ok(EncryptionKey { keytype, keyvalue })
+}
+
+/// Convert a realm and principal name to a ccache principal
+pub fn principal_to_ccache(realm: &str, name: &PrincipalName) -> Principal {
+    Principal::new(
+        name.name_type,
+        CountedOctetString::new(realm.as_bytes().to_vec()),
+        name.name_string
+            .iter()
+            .map(|s| CountedOctetString::new(s.as_bytes().to_vec()))
+            .collect(),
+    )
+}
+
+/// Split a ccache principal into its realm and principal name
+pub fn principal_from_ccache(principal: &Principal) -> (String, PrincipalName) {
+    let realm = String::from_utf8_lossy(&principal.realm.data).to_string();
+    let name = PrincipalName {
+        name_type: principal.name_type,
+        name_string: principal
+            .components
+            .iter()
+            .map(|c| String::from_utf8_lossy(&c.data).to_string())
+            .collect(),
+    };
+    (realm, name)
+}
+
+/// Build a ccache credential from a KDC reply and its decrypted encrypted part
+pub fn ccache_credential(
+    client_realm: &str,
+    client_name: &PrincipalName,
+    ticket: &Ticket,
+    enc_part: &EncKdcRepPart,
+) -> CcacheCredential {
+    let authtime = enc_part.authtime.timestamp() as u32;
+    let times = Times::new(
+        authtime,
+        enc_part.starttime.as_ref().map_or(authtime, |t| t.timestamp() as u32),
+        enc_part.endtime.timestamp() as u32,
+        enc_part.renew_till.as_ref().map_or(0, |t| t.timestamp() as u32),
+    );
+
+    CcacheCredential::new(
+        principal_to_ccache(client_realm, client_name),
+        principal_to_ccache(&enc_part.srealm, &enc_part.sname),
+        KeyBlock::new(enc_part.key.keytype as u16, enc_part.key.keyvalue.clone()),
+        times,
+        enc_part.flags.flags,
+        CountedOctetString::new(ticket.build()),
+    )
+}
+
+/// Decode a stored kerberos ticket credential to its ccache form
+pub fn decode_ccache(data: &str) -> Result<CcacheCredential, String> {
+    let raw = BASE64_STANDARD.decode(data.trim()).map_err(|e| e.to_string())?;
+    let (_, credential) = CcacheCredential::parse(&raw).map_err(|e| format!("Invalid ticket: {}", e))?;
+    Ok(credential)
+}
+
+/// Decode a stored kerberos ticket credential
+pub fn decode_ticket(data: &str) -> Result<SessionTicket, String> {
+    session_ticket_from_ccache(&decode_ccache(data)?)
+}
+
+pub fn session_ticket_from_ccache(credential: &CcacheCredential) -> Result<SessionTicket, String> {
+    let (_, ticket) = Ticket::parse(&credential.ticket.data).map_err(|e| format!("Invalid ticket: {}", e))?;
+    let (client_realm, client_name) = principal_from_ccache(&credential.client);
+    let (server_realm, server_name) = principal_from_ccache(&credential.server);
+
+    Ok(SessionTicket {
+        client_realm,
+        client_name,
+        server_realm,
+        server_name,
+        ticket,
+        session_key: EncryptionKey {
+            keytype: credential.key.keytype as i32,
+            keyvalue: credential.key.keyvalue.clone(),
+        },
+    })
+}
+
+/// Encode a ccache credential for storage in `AuthData::KerberosTicket`
+pub fn encode_ticket(credential: CcacheCredential) -> String {
+    BASE64_STANDARD.encode(credential.build())
+}
+
+/// Request a TGT for the given password, hash or kerberos key credential
+/// Accounts of another realm (user@realm) are authenticated by a KDC of their realm
+pub async fn request_tgt(
+    dc_storage: &DomainControllerStore,
+    dc: &DomainController,
+    creds: &Credential,
+) -> Result<CcacheCredential, String> {
+    let realm_dc = dc_for_realm(dc_storage, dc, &credential_realm(creds, dc)).await?;
+    request_tgt_with_key(&realm_dc, &sam_account_name(&creds.username), &credential_key(creds)?).await
+}
+
+/// Request a TGT for `username` with one of its long term keys
+pub async fn request_tgt_with_key(
+    dc: &DomainController,
+    username: &str,
+    key: &EncryptionKey,
+) -> Result<CcacheCredential, String> {
+    request_initial_ticket(dc, username, key, None).await
+}
+
+/// Request a ticket from the AS exchange, for the krbtgt or another service (e.g. kadmin/changepw)
+pub async fn request_initial_ticket(
+    dc: &DomainController,
+    username: &str,
+    key: &EncryptionKey,
+    sname: Option<&PrincipalName>,
+) -> Result<CcacheCredential, String> {
+    let realm = dc.domain_name.to_uppercase();
+    let mut now = dc.now();
+    let mut skew_retried = false;
+
+    loop {
+        let padata = vec![pa_enc_timestamp(key.keytype, &key.keyvalue, now)?];
+        let mut as_req = build_as_req(&realm, username, &[key.keytype], padata, now);
+        if let Some(sname) = sname {
+            as_req.req_body.sname = Some(sname.clone());
+        }
+
+        match send_as_req(dc, &as_req).await? {
+            AsReply::AsRep(as_rep) => return as_rep_to_credential(&as_rep, key),
+            // Retry once with the KDC time from the error, `dc time` keeps the offset for later requests
+            AsReply::Error(krb_error) if krb_error.error_code == KRB_AP_ERR_SKEW && !skew_retried => {
+                let skew = krb_error_skew(&krb_error).ok_or(krb_error_message(&krb_error))?;
+                println!("Clock skew of {}s with the KDC, retrying with its time (run dc time to store it)", skew);
+                now = Utc::now() + Duration::seconds(skew);
+                skew_retried = true;
+            }
+            AsReply::Error(krb_error) => return Err(krb_error_message(&krb_error)),
+        }
+    }
+}
+
+/// Get a TGT for the credential, reusing it if the credential already is one
+pub async fn get_tgt(
+    dc_storage: &DomainControllerStore,
+    dc: &DomainController,
+    creds: &Credential,
+) -> Result<SessionTicket, String> {
+    if let AuthData::KerberosTicket(data) = &creds.auth_data {
+        let ticket = decode_ticket(data)?;
+        if !ticket.is_tgt() {
+            return Err(format!("Credential holds a ticket for {}, not a TGT", ticket.server_principal()));
+        }
+        return Ok(ticket);
+    }
+    session_ticket_from_ccache(&request_tgt(dc_storage, dc, creds).await?)
+}
+
+/// Client principal name of a credential
+pub fn client_principal(creds: &Credential) -> PrincipalName {
+    principal_name(NT_PRINCIPAL, &sam_account_name(&creds.username))
+}
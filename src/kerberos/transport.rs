@@ -0,0 +1,190 @@
+//! Async transport to the KDCs of a domain.
+//!
+//! Messages are sent over TCP, or over UDP with a TCP retry when the KDC answers
+//! KRB_ERR_RESPONSE_TOO_BIG. Each KDC is tried `retries + 1` times before failing
+//! over to the next one, and the KDC proxy of the domain is used when none answers.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use himmelblau_kerberos_asn1::{Asn1Object, KrbError};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+    time::timeout,
+};
+
+use crate::{data::DomainController, kerberos::kkdcp::KdcProxy};
+
+pub const KDC_PORT: u16 = 88;
+pub const KPASSWD_PORT: u16 = 464;
+
+/// The reply doesn't fit in a UDP datagram, the request has to be sent over TCP
+const KRB_ERR_RESPONSE_TOO_BIG: i32 = 52;
+
+const MAX_UDP_RESPONSE: usize = 0xffff;
+
+/// Largest length prefix accepted on a TCP Kerberos stream, bigger ones are
+/// refused before anything is allocated for them
+pub const MAX_TCP_MESSAGE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KdcProtocol {
+    Tcp,
+    Udp,
+}
+
+#[derive(Debug, Clone)]
+pub struct KdcTransport {
+    kdcs: Vec<SocketAddr>,
+    timeout: Duration,
+    retries: u32,
+    protocol: KdcProtocol,
+    proxy: Option<KdcProxy>,
+}
+
+impl KdcTransport {
+    pub fn new(kdcs: Vec<SocketAddr>) -> Self {
+        Self {
+            kdcs,
+            timeout: Duration::from_secs(10),
+            retries: 1,
+            protocol: KdcProtocol::Tcp,
+            proxy: None,
+        }
+    }
+
+    /// Transport to the KDCs of a domain controller, with its settings
+    pub fn for_dc(dc: &DomainController) -> Self {
+        let timeout = Duration::from_secs(dc.kdc.timeout_secs);
+        let transport = Self::new(dc.kdc_addresses())
+            .with_timeout(timeout)
+            .with_retries(dc.kdc.retries)
+            .with_protocol(if dc.kdc.udp { KdcProtocol::Udp } else { KdcProtocol::Tcp });
+        match &dc.kdc.proxy {
+            Some(proxy) => transport.with_proxy(KdcProxy::new(proxy.clone(), &dc.domain_name).with_timeout(timeout)),
+            None => transport,
+        }
+    }
+
+    /// Transport to the domain controller alone, without other KDCs or a proxy to fail over to
+    pub fn pinned_to_dc(dc: &DomainController) -> Self {
+        let mut transport = Self::for_dc(dc);
+        transport.kdcs.truncate(1);
+        transport.proxy = None;
+        transport
+    }
+
+    /// Transport to the kpasswd service of the KDCs of a domain controller
+    pub fn kpasswd_for_dc(dc: &DomainController) -> Self {
+        let mut transport = Self::for_dc(dc);
+        for kdc in &mut transport.kdcs {
+            kdc.set_port(KPASSWD_PORT);
+        }
+        transport
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub fn with_protocol(mut self, protocol: KdcProtocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    pub fn with_proxy(mut self, proxy: KdcProxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Send a raw kerberos message and return the raw response (without the TCP length prefix).
+    /// KRB-ERROR replies are responses, only network failures move on to the next attempt.
+    pub async fn send(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        let mut last_error = "No KDC configured".to_string();
+        for kdc in &self.kdcs {
+            for attempt in 0..=self.retries {
+                match self.send_to(*kdc, data).await {
+                    Ok(response) => return Ok(response),
+                    Err(e) => {
+                        log::debug!("KDC {} attempt {} failed: {}", kdc, attempt + 1, e);
+                        last_error = format!("KDC {}: {}", kdc, e);
+                    }
+                }
+            }
+        }
+
+        if let Some(proxy) = &self.proxy {
+            log::debug!("No KDC reachable, going through the KDC proxy {}", proxy.url());
+            return proxy
+                .send(data)
+                .await
+                .map_err(|e| format!("{} (direct: {})", e, last_error));
+        }
+        Err(last_error)
+    }
+
+    async fn send_to(&self, kdc: SocketAddr, data: &[u8]) -> Result<Vec<u8>, String> {
+        if self.protocol == KdcProtocol::Udp {
+            let response = timeout(self.timeout, send_udp(kdc, data))
+                .await
+                .map_err(|_| "Timed out".to_string())??;
+            if !is_response_too_big(&response) {
+                return Ok(response);
+            }
+            log::debug!("KDC {} response too big for UDP, retrying over TCP", kdc);
+        }
+        timeout(self.timeout, send_tcp(kdc, data))
+            .await
+            .map_err(|_| "Timed out".to_string())?
+    }
+}
+
+fn is_response_too_big(response: &[u8]) -> bool {
+    KrbError::parse(response).map_or(false, |(_, e)| e.error_code == KRB_ERR_RESPONSE_TOO_BIG)
+}
+
+pub async fn send_tcp(kdc: SocketAddr, data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut stream = TcpStream::connect(kdc).await.map_err(|e| format!("Failed to connect: {}", e))?;
+
+    let mut request = Vec::with_capacity(data.len() + 4);
+    request.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    request.extend_from_slice(data);
+    stream.write_all(&request).await.map_err(|e| e.to_string())?;
+
+    let len = stream.read_u32().await.map_err(|e| e.to_string())? as usize;
+    if len > MAX_TCP_MESSAGE {
+        return Err(format!("KDC response too large ({} bytes)", len));
+    }
+    let mut response = vec![0; len];
+    stream.read_exact(&mut response).await.map_err(|e| e.to_string())?;
+    Ok(response)
+}
+
+pub async fn send_udp(kdc: SocketAddr, data: &[u8]) -> Result<Vec<u8>, String> {
+    let local: IpAddr = match kdc {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(local, 0)).await.map_err(|e| e.to_string())?;
+    socket.connect(kdc).await.map_err(|e| e.to_string())?;
+    socket.send(data).await.map_err(|e| e.to_string())?;
+
+    let mut buffer = vec![0; MAX_UDP_RESPONSE];
+    let len = socket.recv(&mut buffer).await.map_err(|e| e.to_string())?;
+    buffer.truncate(len);
+    Ok(buffer)
+}
+
+/// Send a raw kerberos message to the KDCs of a domain controller
+pub async fn send_to_kdc(dc: &DomainController, data: &[u8]) -> Result<Vec<u8>, String> {
+    KdcTransport::for_dc(dc).send(data).await
+}
@@ -5,12 +5,24 @@ pub mod stores;
 pub mod data;
 pub mod sspi_client;
 pub mod rustls;
+pub mod kerberos;
 
 
 use crate::app::App;
 
-#[tokio::main]
-async fn main() {
+fn main() {
+    if let Err(e) = kerberos::init() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to build the tokio runtime");
+    runtime.block_on(run());
+}
+
+async fn run() {
     // env_logger::builder().filter_level(log::LevelFilter::Trace).init();
     // Get args
     let args: Vec<String> = std::env::args().collect();
@@ -1,74 +1,74 @@
-use std::io::{Read, Write};
-use std::net::{IpAddr, Ipv4Addr, TcpStream, UdpSocket};
+use std::net::{SocketAddr, ToSocketAddrs};
 
-use byteorder::{BigEndian, ReadBytesExt};
 use sspi::network_client::NetworkClient;
 use sspi::{Error, ErrorKind, NetworkProtocol, NetworkRequest};
 use url::Url;
 
+use crate::data::DomainController;
+use crate::kerberos::transport::{KdcProtocol, KdcTransport, KDC_PORT};
 use crate::rustls::{install_default_crypto_provider_if_necessary, load_native_certs};
 
-
-
-#[derive(Debug, Clone)]
-pub struct ReqwestNetworkClient {
-    ip_addr: Ipv4Addr,
+/// sspi network client sending KDC requests through `KdcTransport`.
+/// Requests for the domain of the configured DC go to its KDCs with its settings,
+/// other hosts are resolved and contacted directly.
+#[derive(Debug, Clone, Default)]
+pub struct KdcNetworkClient {
+    dc: Option<DomainController>,
 }
 
-impl ReqwestNetworkClient {
-    pub fn new(ip_addr: Ipv4Addr) -> Self {
-        Self { ip_addr }
+impl KdcNetworkClient {
+    pub fn new() -> Self {
+        Self { dc: None }
     }
 
-    fn send_tcp(&self, url: &Url, data: &[u8]) -> Result<Vec<u8>, Error> {
-        let addr = format!(
-            "{}:{}",
-            self.ip_addr.to_string(),
-            url.port().unwrap_or(88)
-        );
-        let mut stream = TcpStream::connect(addr)
-            .map_err(|e| Error::new(ErrorKind::NoAuthenticatingAuthority, format!("{:?}", e)))?;
-
-        stream
-            .write(data)
-            .map_err(|e| Error::new(ErrorKind::NoAuthenticatingAuthority, format!("{:?}", e)))?;
-
-        let len = stream
-            .read_u32::<BigEndian>()
-            .map_err(|e| Error::new(ErrorKind::NoAuthenticatingAuthority, format!("{:?}", e)))?;
-
-        let mut buf = vec![0; len as usize + 4];
-        buf[0..4].copy_from_slice(&(len.to_be_bytes()));
-
-        stream
-            .read_exact(&mut buf[4..])
-            .map_err(|e| Error::new(ErrorKind::NoAuthenticatingAuthority, format!("{:?}", e)))?;
-
-        Ok(buf)
+    pub fn for_dc(dc: &DomainController) -> Self {
+        Self { dc: Some(dc.clone()) }
     }
 
-    fn send_udp(&self, url: &Url, data: &[u8]) -> Result<Vec<u8>, Error> {
-        let port = portpicker::pick_unused_port()
-            .ok_or_else(|| Error::new(ErrorKind::InternalError, "No free ports"))?;
-        let udp_socket = UdpSocket::bind((IpAddr::V4(Ipv4Addr::LOCALHOST), port))?;
-
-        let addr = format!(
-            "{}:{}",
-            self.ip_addr.to_string(),
-            url.port().unwrap_or(88)
-        );
-        udp_socket.send_to(data, addr)?;
-
-        // 48 000 bytes: default maximum token len in Windows
-        let mut buf = vec![0; 0xbb80];
-
-        let n = udp_socket.recv(&mut buf)?;
+    fn transport(&self, url: &Url, protocol: KdcProtocol) -> Result<KdcTransport, Error> {
+        let host = url
+            .host_str()
+            .ok_or_else(|| Error::new(ErrorKind::NoAuthenticatingAuthority, "KDC url without host"))?
+            .trim_matches(|c| c == '[' || c == ']')
+            .to_lowercase();
+
+        if let Some(dc) = &self.dc {
+            let domain = dc.domain_name.to_lowercase();
+            if host == domain || host.ends_with(&format!(".{}", domain)) || host == dc.ip_address.to_string() {
+                return Ok(KdcTransport::for_dc(dc).with_protocol(protocol));
+            }
+        }
 
-        let mut reply_buf = Vec::with_capacity(n + 4);
-        reply_buf.extend_from_slice(&(n as u32).to_be_bytes());
-        reply_buf.extend_from_slice(&buf[0..n]);
+        let kdcs: Vec<SocketAddr> = (host.as_str(), url.port().unwrap_or(KDC_PORT))
+            .to_socket_addrs()
+            .map_err(|e| Error::new(ErrorKind::NoAuthenticatingAuthority, format!("Failed to resolve {}: {}", host, e)))?
+            .collect();
+        Ok(KdcTransport::new(kdcs).with_protocol(protocol))
+    }
 
-        Ok(reply_buf)
+    /// Run a KDC exchange on its own runtime, sspi calls the client synchronously
+    /// from inside the application runtime
+    fn send_kdc(&self, url: &Url, protocol: KdcProtocol, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let transport = self.transport(url, protocol)?;
+        let response = std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()
+                        .map_err(|e| e.to_string())?
+                        .block_on(transport.send(data))
+                })
+                .join()
+                .map_err(|_| "KDC transport thread panicked".to_string())?
+        })
+        .map_err(|e| Error::new(ErrorKind::NoAuthenticatingAuthority, e))?;
+
+        // sspi expects the TCP length prefix on every response
+        let mut reply = Vec::with_capacity(response.len() + 4);
+        reply.extend_from_slice(&(response.len() as u32).to_be_bytes());
+        reply.extend_from_slice(&response);
+        Ok(reply)
     }
 
     fn send_http(&self, url: &Url, data: &[u8]) -> Result<Vec<u8>, Error> {
@@ -127,11 +127,14 @@ impl ReqwestNetworkClient {
     }
 }
 
-impl NetworkClient for ReqwestNetworkClient {
+impl NetworkClient for KdcNetworkClient {
     fn send(&self, request: &NetworkRequest) -> Result<Vec<u8>, Error> {
         match request.protocol {
-            NetworkProtocol::Tcp => self.send_tcp(&request.url, &request.data),
-            NetworkProtocol::Udp => self.send_udp(&request.url, &request.data),
+            // sspi frames TCP requests with the length prefix, the transport adds it itself
+            NetworkProtocol::Tcp => {
+                self.send_kdc(&request.url, KdcProtocol::Tcp, request.data.get(4..).unwrap_or_default())
+            }
+            NetworkProtocol::Udp => self.send_kdc(&request.url, KdcProtocol::Udp, &request.data),
             NetworkProtocol::Http | NetworkProtocol::Https => {
                 self.send_http(&request.url, &request.data)
             }
@@ -147,7 +147,7 @@ impl CredentialsStore {
     /// Get credentials by username
     pub fn get_credentials_by_username(&self, username: &str) -> Vec<&Credential> {
         self.username_index
-            .get(username)
+            .get(&username.to_lowercase())
             .map(|ids| {
                 ids.iter()
                     .filter_map(|id| self.credentials.get(id))
@@ -156,6 +156,48 @@ impl CredentialsStore {
             .unwrap_or_default()
     }
     
+    /// Check if a credential with the same username and authentication data exists
+    pub fn contains_auth_data(&self, username: &str, auth_data: &AuthData) -> bool {
+        self.username_index
+            .get(&username.to_lowercase())
+            .map_or(false, |ids| {
+                ids.iter()
+                    .filter_map(|id| self.credentials.get(id))
+                    .any(|cred| cred.auth_data == *auth_data)
+            })
+    }
+    
+    /// Set a metadata entry on every credential of a user.
+    /// A placeholder credential is created when the user has none yet.
+    pub fn set_user_metadata(
+        &mut self,
+        username: &str,
+        key: &str,
+        value: &str,
+        source: &str,
+        credential_type: CredentialType,
+    ) -> Result<(), String> {
+        let mut credentials: Vec<Credential> = self
+            .get_credentials_by_username(username)
+            .into_iter()
+            .cloned()
+            .collect();
+
+        if credentials.is_empty() {
+            let mut credential = Credential::new_password(username.to_string(), String::new(), source.to_string());
+            credential.auth_data = AuthData::Custom(HashMap::new());
+            credential.credential_type = credential_type;
+            self.add_credential(credential.clone())?;
+            credentials.push(credential);
+        }
+
+        for mut credential in credentials {
+            credential.set_metadata(key.to_string(), value.to_string());
+            self.update_credential(&credential.id.clone(), credential)?;
+        }
+        Ok(())
+    }
+    
     /// Get credentials by type
     pub fn get_credentials_by_type(&self, credential_type: &CredentialType) -> Vec<&Credential> {
         self.type_index
@@ -1,20 +1,16 @@
-use std::{net::{IpAddr, Ipv4Addr, SocketAddr}, time::Duration};
+use std::{collections::HashMap, net::{IpAddr, Ipv4Addr, SocketAddr}, sync::Arc, time::Duration};
 
 use anyhow::anyhow;
+use tokio::{sync::Semaphore, task::JoinSet};
 use trust_dns_resolver::{config::{NameServerConfig, NameServerConfigGroup, Protocol, ResolverConfig, ResolverOpts}, Name, TokioAsyncResolver};
 
-pub async fn dig_srv_short(
-    server: String,
-    port: u16,
-    domain: String,
-) -> anyhow::Result<String> {
-    // Parse the server IP address
-    let server_ip: IpAddr = server.parse()?;
-    
-    // Create a custom resolver configuration pointing to the specified DNS server
+use crate::data::DomainController;
+
+/// Resolver querying only the given DNS server
+fn resolver_for(server: SocketAddr) -> TokioAsyncResolver {
     let mut config = ResolverConfig::new();
     let name_server = NameServerConfig {
-        socket_addr: SocketAddr::new(server_ip, port),
+        socket_addr: server,
         protocol: Protocol::Udp,
         tls_dns_name: None,
         trust_negative_responses: true,
@@ -22,12 +18,21 @@ pub async fn dig_srv_short(
     };
     config.add_name_server(name_server);
     
-    // Set resolver options
     let mut opts = ResolverOpts::default();
     opts.timeout = Duration::from_secs(5);
     
-    // Create the resolver
-    let resolver = TokioAsyncResolver::tokio(config, opts);
+    TokioAsyncResolver::tokio(config, opts)
+}
+
+pub async fn dig_srv_short(
+    server: String,
+    port: u16,
+    domain: String,
+) -> anyhow::Result<String> {
+    // Parse the server IP address
+    let server_ip: IpAddr = server.parse()?;
+    
+    let resolver = resolver_for(SocketAddr::new(server_ip, port));
     
     // Query for SRV records
     let response = resolver.srv_lookup(domain).await?;
@@ -41,6 +46,70 @@ pub async fn dig_srv_short(
     Ok(fqdn)
 }
 
+/// Resolve the fqdn of the domain controller from the _kerberos._tcp SRV record
+pub async fn dc_fqdn(dc: &DomainController) -> Result<String, String> {
+    dig_srv_short(
+        dc.ip_address.to_string(),
+        53,
+        format!("_kerberos._tcp.{}", dc.domain_name),
+    )
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// Locate the KDCs of a realm from its _kerberos._tcp SRV records, asking the DNS server of `dc`
+pub async fn resolve_kdcs(dc: &DomainController, realm: &str) -> Result<Vec<SocketAddr>, String> {
+    let resolver = resolver_for(SocketAddr::new(dc.ip_address, 53));
+    let response = resolver
+        .srv_lookup(format!("_kerberos._tcp.{}.", realm.to_lowercase()))
+        .await
+        .map_err(|e| format!("No KDC found for {}: {}", realm, e))?;
+
+    let mut kdcs = Vec::new();
+    for srv in response.iter() {
+        let ips = resolver
+            .lookup_ip(srv.target().clone())
+            .await
+            .map_err(|e| format!("Failed to resolve {}: {}", srv.target(), e))?;
+        for ip in ips.iter() {
+            let kdc = SocketAddr::new(ip, srv.port());
+            if !kdcs.contains(&kdc) {
+                kdcs.push(kdc);
+            }
+        }
+    }
+    if kdcs.is_empty() {
+        return Err(format!("No KDC found for {}", realm));
+    }
+    Ok(kdcs)
+}
+
+/// DNS queries `resolve_hosts` keeps in flight
+const MAX_CONCURRENT_LOOKUPS: usize = 32;
+
+/// Resolve host names with the DNS server of `dc`, names that don't resolve are left out
+pub async fn resolve_hosts(dc: &DomainController, hostnames: &[String]) -> HashMap<String, Vec<IpAddr>> {
+    let resolver = resolver_for(SocketAddr::new(dc.ip_address, 53));
+    let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_LOOKUPS));
+    let mut lookups = JoinSet::new();
+    for hostname in hostnames {
+        let (resolver, semaphore, hostname) = (resolver.clone(), semaphore.clone(), hostname.clone());
+        lookups.spawn(async move {
+            let _permit = semaphore.acquire_owned().await.ok()?;
+            let ips = resolver.lookup_ip(format!("{}.", hostname.trim_end_matches('.'))).await.ok()?;
+            Some((hostname, ips.iter().collect::<Vec<IpAddr>>()))
+        });
+    }
+
+    let mut addresses = HashMap::new();
+    while let Some(lookup) = lookups.join_next().await {
+        if let Ok(Some((hostname, ips))) = lookup {
+            addresses.insert(hostname, ips);
+        }
+    }
+    addresses
+}
+
 #[cfg(test)]
 mod test {
     #[tokio::test]
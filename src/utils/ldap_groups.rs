@@ -0,0 +1,205 @@
+//! Group memberships resolved by the domain controller. Nested memberships use
+//! LDAP_MATCHING_RULE_IN_CHAIN; the primary group (primaryGroupID) isn't in `member` /
+//! `memberOf` and is resolved from the RID.
+
+use ldap3::{ldap_escape, Scope, SearchEntry};
+
+use crate::utils::{
+    ldap_operations::{first_attr, sam_account_name, sid_rid, sid_to_string},
+    ldap_session::LdapSession,
+};
+
+/// Matching rule walking the chain of nested memberships
+pub const MATCHING_RULE_IN_CHAIN: &str = "1.2.840.113556.1.4.1941";
+
+/// Well known RIDs of privileged groups
+pub const PRIVILEGED_GROUP_RIDS: &[(u32, &str)] = &[
+    (512, "Domain Admins"),
+    (519, "Enterprise Admins"),
+    (548, "Account Operators"),
+    (551, "Backup Operators"),
+];
+
+/// Privileged groups which don't have a well known RID
+pub const PRIVILEGED_GROUP_NAMES: &[&str] = &["DnsAdmins"];
+
+const OBJECT_ATTRIBUTES: [&str; 6] =
+    ["sAMAccountName", "cn", "objectSid", "objectClass", "primaryGroupID", "servicePrincipalName"];
+
+/// Whether a group grants control over the domain or its controllers
+pub fn is_privileged_group(name: &str, rid: Option<u32>) -> bool {
+    rid.is_some_and(|rid| PRIVILEGED_GROUP_RIDS.iter().any(|(r, _)| *r == rid))
+        || PRIVILEGED_GROUP_NAMES.iter().any(|n| n.eq_ignore_ascii_case(name))
+}
+
+/// A user, computer or group of the directory
+#[derive(Debug, Clone, Default)]
+pub struct DirectoryObject {
+    pub dn: String,
+    pub sam_account_name: String,
+    pub name: String,
+    pub sid: Option<String>,
+    pub object_classes: Vec<String>,
+    pub primary_group_id: Option<u32>,
+    pub service_principal_names: Vec<String>,
+    /// Membership through primaryGroupID rather than `member`
+    pub primary: bool,
+}
+
+impl DirectoryObject {
+    pub fn from_entry(entry: &SearchEntry) -> Self {
+        Self {
+            dn: entry.dn.clone(),
+            sam_account_name: first_attr(entry, "sAMAccountName").unwrap_or_default().to_string(),
+            name: first_attr(entry, "cn").unwrap_or_default().to_string(),
+            sid: entry
+                .bin_attrs
+                .get("objectSid")
+                .and_then(|values| values.first())
+                .and_then(|sid| sid_to_string(sid)),
+            object_classes: entry.attrs.get("objectClass").cloned().unwrap_or_default(),
+            primary_group_id: first_attr(entry, "primaryGroupID").and_then(|id| id.parse().ok()),
+            service_principal_names: entry.attrs.get("servicePrincipalName").cloned().unwrap_or_default(),
+            primary: false,
+        }
+    }
+
+    pub fn rid(&self) -> Option<u32> {
+        self.sid.as_deref().and_then(sid_rid)
+    }
+
+    pub fn has_object_class(&self, class: &str) -> bool {
+        self.object_classes.iter().any(|c| c.eq_ignore_ascii_case(class))
+    }
+
+    /// Most specific object class, for display
+    pub fn kind(&self) -> &'static str {
+        if self.has_object_class("computer") {
+            "computer"
+        } else if self.has_object_class("group") {
+            "group"
+        } else if self.has_object_class("user") {
+            "user"
+        } else {
+            "object"
+        }
+    }
+
+    pub fn is_privileged_group(&self) -> bool {
+        self.has_object_class("group") && is_privileged_group(&self.name, self.rid())
+    }
+
+    /// SID of the primary group: the domain part of the object SID with the primaryGroupID
+    fn primary_group_sid(&self) -> Option<String> {
+        let (domain_sid, _) = self.sid.as_deref()?.rsplit_once('-')?;
+        Some(format!("{}-{}", domain_sid, self.primary_group_id?))
+    }
+}
+
+async fn search_objects(ldap: &mut LdapSession, filter: &str) -> Result<Vec<DirectoryObject>, String> {
+    let base_dn = ldap.base_dn.clone();
+    let entries = ldap
+        .paged_search(&base_dn, Scope::Subtree, filter, OBJECT_ATTRIBUTES.to_vec(), None)
+        .await?;
+    Ok(entries.iter().map(DirectoryObject::from_entry).collect())
+}
+
+fn push_unique(objects: &mut Vec<DirectoryObject>, object: DirectoryObject) {
+    if !objects.iter().any(|o| o.dn.eq_ignore_ascii_case(&object.dn)) {
+        objects.push(object);
+    }
+}
+
+/// Find a principal by distinguished name, SID or sAMAccountName (with or without domain)
+pub async fn find_object(ldap: &mut LdapSession, name: &str) -> Result<DirectoryObject, String> {
+    let sam = sam_account_name(name);
+    let filter = if name.contains('=') {
+        format!("(distinguishedName={})", ldap_escape(name))
+    } else if name.starts_with("S-1-") {
+        format!("(objectSid={})", ldap_escape(name))
+    } else {
+        format!("(sAMAccountName={})", ldap_escape(&sam))
+    };
+    search_objects(ldap, &filter)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or(format!("'{}' not found in the directory", name))
+}
+
+/// Groups the principal is a member of, directly, through nesting or as primary group
+pub async fn member_of(ldap: &mut LdapSession, principal: &DirectoryObject) -> Result<Vec<DirectoryObject>, String> {
+    let filter = format!("(member:{}:={})", MATCHING_RULE_IN_CHAIN, ldap_escape(&principal.dn));
+    let mut groups = search_objects(ldap, &filter).await?;
+
+    if let Some(primary_sid) = principal.primary_group_sid() {
+        let filter = format!("(objectSid={})", primary_sid);
+        if let Some(mut primary) = search_objects(ldap, &filter).await?.into_iter().next() {
+            let filter = format!("(member:{}:={})", MATCHING_RULE_IN_CHAIN, ldap_escape(&primary.dn));
+            let nested = search_objects(ldap, &filter).await?;
+            primary.primary = true;
+            groups.retain(|g| !g.dn.eq_ignore_ascii_case(&primary.dn));
+            groups.push(primary);
+            for group in nested {
+                push_unique(&mut groups, group);
+            }
+        }
+    }
+
+    groups.sort_by_key(|g| g.name.to_lowercase());
+    Ok(groups)
+}
+
+/// Members of a group, with the nested groups' members when `recursive`
+pub async fn group_members(
+    ldap: &mut LdapSession,
+    group: &DirectoryObject,
+    recursive: bool,
+) -> Result<Vec<DirectoryObject>, String> {
+    let filter = if recursive {
+        format!("(memberOf:{}:={})", MATCHING_RULE_IN_CHAIN, ldap_escape(&group.dn))
+    } else {
+        format!("(memberOf={})", ldap_escape(&group.dn))
+    };
+    let mut members = search_objects(ldap, &filter).await?;
+
+    // Accounts whose primary group is the group, or one of its nested groups
+    let mut rids: Vec<u32> = group.rid().into_iter().collect();
+    if recursive {
+        rids.extend(members.iter().filter(|m| m.has_object_class("group")).filter_map(|m| m.rid()));
+    }
+    if !rids.is_empty() {
+        let filter = format!(
+            "(|{})",
+            rids.iter().map(|rid| format!("(primaryGroupID={})", rid)).collect::<String>()
+        );
+        for mut member in search_objects(ldap, &filter).await? {
+            member.primary = true;
+            push_unique(&mut members, member);
+        }
+    }
+
+    members.sort_by_key(|m| m.sam_account_name.to_lowercase());
+    Ok(members)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_privileged_groups() {
+        assert!(is_privileged_group("Domain Admins", Some(512)));
+        assert!(is_privileged_group("Backup Operators", Some(551)));
+        assert!(is_privileged_group("DnsAdmins", Some(1101)));
+        assert!(!is_privileged_group("Domain Users", Some(513)));
+        assert!(!is_privileged_group("Remote Management Users", Some(580)));
+
+        let user = DirectoryObject {
+            sid: Some("S-1-5-21-1-2-3-1104".to_string()),
+            primary_group_id: Some(513),
+            ..Default::default()
+        };
+        assert_eq!(user.primary_group_sid().unwrap(), "S-1-5-21-1-2-3-513");
+    }
+}
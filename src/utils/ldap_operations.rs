@@ -0,0 +1,280 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use ldap3::SearchEntry;
+
+use crate::{data::DomainController, utils::ldap_session::LdapSession};
+
+/// FILETIME of 1970-01-01, FILETIMEs count 100ns intervals since 1601
+const UNIX_EPOCH_FILETIME: u64 = 116_444_736_000_000_000;
+
+/// Convert a date to a FILETIME, as used by the PAC and timestamp attributes (pwdLastSet, lastLogonTimestamp)
+pub fn filetime(time: DateTime<Utc>) -> u64 {
+    (time.timestamp().max(0) as u64) * 10_000_000 + UNIX_EPOCH_FILETIME
+}
+
+/// Date of a FILETIME, `None` for the unset and infinite values
+pub fn filetime_to_datetime(filetime: u64) -> Option<DateTime<Utc>> {
+    if filetime == 0 || filetime >= 0x7fff_ffff_ffff_ffff || filetime < UNIX_EPOCH_FILETIME {
+        return None;
+    }
+    DateTime::from_timestamp(((filetime - UNIX_EPOCH_FILETIME) / 10_000_000) as i64, 0)
+}
+
+/// FILETIME to a readable date, "never" for the unset and infinite values
+pub fn filetime_to_string(filetime: u64) -> String {
+    filetime_to_datetime(filetime).map_or("never".to_string(), |t| t.to_rfc3339())
+}
+
+/// userAccountControl flags
+const UAC_FLAGS: [(u32, &str); 22] = [
+    (0x0000_0001, "SCRIPT"),
+    (0x0000_0002, "ACCOUNTDISABLE"),
+    (0x0000_0008, "HOMEDIR_REQUIRED"),
+    (0x0000_0010, "LOCKOUT"),
+    (0x0000_0020, "PASSWD_NOTREQD"),
+    (0x0000_0040, "PASSWD_CANT_CHANGE"),
+    (0x0000_0080, "ENCRYPTED_TEXT_PWD_ALLOWED"),
+    (0x0000_0100, "TEMP_DUPLICATE_ACCOUNT"),
+    (0x0000_0200, "NORMAL_ACCOUNT"),
+    (0x0000_0800, "INTERDOMAIN_TRUST_ACCOUNT"),
+    (0x0000_1000, "WORKSTATION_TRUST_ACCOUNT"),
+    (0x0000_2000, "SERVER_TRUST_ACCOUNT"),
+    (0x0001_0000, "DONT_EXPIRE_PASSWORD"),
+    (0x0002_0000, "MNS_LOGON_ACCOUNT"),
+    (0x0004_0000, "SMARTCARD_REQUIRED"),
+    (0x0008_0000, "TRUSTED_FOR_DELEGATION"),
+    (0x0010_0000, "NOT_DELEGATED"),
+    (0x0020_0000, "USE_DES_KEY_ONLY"),
+    (0x0040_0000, "DONT_REQ_PREAUTH"),
+    (0x0080_0000, "PASSWORD_EXPIRED"),
+    (0x0100_0000, "TRUSTED_TO_AUTH_FOR_DELEGATION"),
+    (0x0400_0000, "PARTIAL_SECRETS_ACCOUNT"),
+];
+
+/// msDS-SupportedEncryptionTypes flags
+const SUPPORTED_ETYPES: [(u32, &str); 6] = [
+    (0x01, "DES-CBC-CRC"),
+    (0x02, "DES-CBC-MD5"),
+    (0x04, "RC4-HMAC"),
+    (0x08, "AES128"),
+    (0x10, "AES256"),
+    (0x20, "AES256-SK"),
+];
+
+/// Convert a dns domain name to its base DN (voleur.htb -> DC=voleur,DC=htb)
+pub fn domain_to_base_dn(domain: &str) -> String {
+    domain
+        .split('.')
+        .filter(|part| !part.is_empty())
+        .map(|part| format!("DC={}", part))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Strip the domain part from UPN (user@domain) and down-level (DOMAIN\user) usernames
+pub fn sam_account_name(username: &str) -> String {
+    let username = username.rsplit('\\').next().unwrap_or(username);
+    username.split('@').next().unwrap_or(username).to_string()
+}
+
+/// Convert a binary objectSid to its string representation (S-1-5-21-...)
+pub fn sid_to_string(sid: &[u8]) -> Option<String> {
+    if sid.len() < 8 {
+        return None;
+    }
+    let revision = sid[0];
+    let sub_authority_count = sid[1] as usize;
+    if sid.len() < 8 + sub_authority_count * 4 {
+        return None;
+    }
+
+    let mut authority: u64 = 0;
+    for byte in &sid[2..8] {
+        authority = (authority << 8) | *byte as u64;
+    }
+
+    let mut result = format!("S-{}-{}", revision, authority);
+    for i in 0..sub_authority_count {
+        let offset = 8 + i * 4;
+        let sub_authority = u32::from_le_bytes([sid[offset], sid[offset + 1], sid[offset + 2], sid[offset + 3]]);
+        result.push_str(&format!("-{}", sub_authority));
+    }
+    Some(result)
+}
+
+/// Convert a string SID (S-1-5-21-...) to its binary representation
+pub fn sid_from_string(sid: &str) -> Option<Vec<u8>> {
+    let mut parts = sid.strip_prefix("S-")?.split('-');
+    let revision: u8 = parts.next()?.parse().ok()?;
+    let authority: u64 = parts.next()?.parse().ok()?;
+    let sub_authorities = parts.map(|p| p.parse::<u32>().ok()).collect::<Option<Vec<_>>>()?;
+    if sub_authorities.len() > 15 {
+        return None;
+    }
+
+    let mut result = vec![revision, sub_authorities.len() as u8];
+    result.extend_from_slice(&authority.to_be_bytes()[2..]);
+    for sub_authority in sub_authorities {
+        result.extend_from_slice(&sub_authority.to_le_bytes());
+    }
+    Some(result)
+}
+
+/// Get the relative identifier (last sub authority) of a string SID
+pub fn sid_rid(sid: &str) -> Option<u32> {
+    sid.rsplit('-').next()?.parse().ok()
+}
+
+/// Parse an LDAP GeneralizedTime (20251018143000.0Z)
+pub fn parse_generalized_time(value: &str) -> Option<DateTime<Utc>> {
+    let naive = NaiveDateTime::parse_from_str(value.get(..14)?, "%Y%m%d%H%M%S").ok()?;
+    Some(naive.and_utc())
+}
+
+/// Convert a binary objectGUID to its string representation (the first three fields are little endian)
+pub fn guid_to_string(guid: &[u8]) -> Option<String> {
+    let guid: &[u8; 16] = guid.try_into().ok()?;
+    let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    Some(format!(
+        "{}-{}-{}-{}-{}",
+        hex(&[guid[3], guid[2], guid[1], guid[0]]),
+        hex(&[guid[5], guid[4]]),
+        hex(&[guid[7], guid[6]]),
+        hex(&guid[8..10]),
+        hex(&guid[10..])
+    ))
+}
+
+/// Names of the flags set in a userAccountControl value
+pub fn uac_flags(uac: u32) -> Vec<&'static str> {
+    UAC_FLAGS
+        .iter()
+        .filter(|(flag, _)| uac & flag != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+/// Names of the encryption types set in a msDS-SupportedEncryptionTypes value
+pub fn supported_etypes(value: u32) -> Vec<&'static str> {
+    SUPPORTED_ETYPES
+        .iter()
+        .filter(|(flag, _)| value & flag != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+/// First value of a text attribute
+pub fn first_attr<'a>(entry: &'a SearchEntry, name: &str) -> Option<&'a str> {
+    entry.attrs.get(name).and_then(|values| values.first()).map(|value| value.as_str())
+}
+
+/// Human readable form of a well-known attribute value: SIDs, GUIDs, timestamps and
+/// userAccountControl flags. Other values are returned as text, or `None` when they're binary.
+pub fn decode_attribute(name: &str, value: &[u8]) -> Option<String> {
+    let text = || std::str::from_utf8(value).ok();
+    match name.to_ascii_lowercase().as_str() {
+        "objectsid" | "sidhistory" | "securityidentifier" | "tokengroups" => sid_to_string(value),
+        "objectguid" | "schemaidguid" | "attributesecurityguid" | "ms-ds-consistencyguid" => guid_to_string(value),
+        "lastlogon" | "lastlogontimestamp" | "lastlogoff" | "pwdlastset" | "accountexpires" | "badpasswordtime"
+        | "lockouttime" => {
+            let filetime = text()?.parse::<i64>().ok()?;
+            Some(format!("{} ({})", filetime_to_string(filetime.max(0) as u64), filetime))
+        }
+        "whencreated" | "whenchanged" | "dscorepropagationdata" => {
+            let time = text()?;
+            Some(parse_generalized_time(time).map_or(time.to_string(), |t| t.to_rfc3339()))
+        }
+        "useraccountcontrol" | "msds-user-account-control-computed" => {
+            let uac = text()?.parse::<u32>().ok()?;
+            Some(format!("{} ({})", uac, uac_flags(uac).join(" | ")))
+        }
+        "msds-supportedencryptiontypes" => {
+            let etypes = text()?.parse::<u32>().ok()?;
+            Some(format!("{} ({})", etypes, supported_etypes(etypes).join(" | ")))
+        }
+        _ => text().map(str::to_string),
+    }
+}
+
+/// Attributes of a search entry with their raw values, text and binary ones merged and sorted by name
+pub fn entry_attributes(entry: &SearchEntry) -> Vec<(String, Vec<Vec<u8>>)> {
+    let mut attributes: Vec<(String, Vec<Vec<u8>>)> = entry
+        .attrs
+        .iter()
+        .map(|(name, values)| (name.clone(), values.iter().map(|v| v.as_bytes().to_vec()).collect()))
+        .collect();
+    for (name, values) in &entry.bin_attrs {
+        match attributes.iter_mut().find(|(existing, _)| existing == name) {
+            Some((_, existing)) => existing.extend(values.iter().cloned()),
+            None => attributes.push((name.clone(), values.clone())),
+        }
+    }
+    attributes.sort_by_key(|(name, _)| name.to_ascii_lowercase());
+    attributes
+}
+
+/// Current time of the domain controller from the rootDSE, readable without binding
+pub async fn ldap_current_time(dc: &DomainController) -> Result<DateTime<Utc>, String> {
+    let mut session = LdapSession::anonymous(dc).await?;
+    let root_dse = session.root_dse(vec!["currentTime"]).await?;
+    let _ = session.unbind().await;
+
+    root_dse
+        .attrs
+        .get("currentTime")
+        .and_then(|values| values.first())
+        .and_then(|time| parse_generalized_time(time))
+        .ok_or("No currentTime in the rootDSE".to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sid_to_string() {
+        // S-1-5-21-1-2-3-512
+        let sid = vec![
+            1, 5, 0, 0, 0, 0, 0, 5, 21, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 0, 2, 0, 0,
+        ];
+        let string_sid = sid_to_string(&sid).unwrap();
+        assert_eq!(string_sid, "S-1-5-21-1-2-3-512");
+        assert_eq!(sid_rid(&string_sid), Some(512));
+        assert_eq!(sid_from_string(&string_sid), Some(sid));
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(domain_to_base_dn("voleur.htb"), "DC=voleur,DC=htb");
+        assert_eq!(sam_account_name("ryan.naylor@voleur.htb"), "ryan.naylor");
+        assert_eq!(sam_account_name("VOLEUR\\ryan.naylor"), "ryan.naylor");
+    }
+
+    #[test]
+    fn test_filetime() {
+        let time = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        assert_eq!(filetime(time), 133_444_736_000_000_000);
+        assert_eq!(filetime_to_string(filetime(time)), "2023-11-14T22:13:20+00:00");
+        assert_eq!(filetime_to_string(0), "never");
+        assert_eq!(filetime_to_string(0x7fff_ffff_ffff_ffff), "never");
+    }
+
+    #[test]
+    fn test_decode_attribute() {
+        let guid = [
+            0x3f, 0x2a, 0x1b, 0x0c, 0x5e, 0x4d, 0x70, 0x6f, 0x81, 0x92, 0xa3, 0xb4, 0xc5, 0xd6, 0xe7, 0xf8,
+        ];
+        assert_eq!(decode_attribute("objectGUID", &guid).unwrap(), "0c1b2a3f-4d5e-6f70-8192-a3b4c5d6e7f8");
+        assert_eq!(
+            decode_attribute("userAccountControl", b"66048").unwrap(),
+            "66048 (NORMAL_ACCOUNT | DONT_EXPIRE_PASSWORD)"
+        );
+        assert_eq!(decode_attribute("sAMAccountName", b"svc_ldap").unwrap(), "svc_ldap");
+        assert_eq!(decode_attribute("thumbnailPhoto", &[0xff, 0xd8, 0xff]), None);
+    }
+
+    #[test]
+    fn test_parse_generalized_time() {
+        let time = parse_generalized_time("20251018143005.0Z").unwrap();
+        assert_eq!(time.to_rfc3339(), "2025-10-18T14:30:05+00:00");
+    }
+}
@@ -0,0 +1,319 @@
+//! Authenticated LDAP sessions with the domain controllers.
+//!
+//! SASL binds (Kerberos GSSAPI and NTLM) install a sealing security layer on clear connections
+//! and send the tls-server-end-point channel binding token over LDAPS / StartTLS, so they work
+//! with LDAP signing and channel binding enforced. Certificate binds present the certificate
+//! in the TLS handshake (Schannel) and bind with SASL EXTERNAL.
+
+use ldap3::{
+    adapters::{Adapter, EntriesOnly, PagedResults},
+    exop::{WhoAmI, WhoAmIResp},
+    Ldap, LdapConnAsync, LdapConnSettings, LdapResult, Scope, SearchEntry, SearchOptions,
+};
+use native_tls::{Identity, TlsConnector};
+use openssl::pkcs12::Pkcs12;
+
+use crate::{
+    data::{credential::AuthMethod, AuthData, Credential, DomainController},
+    kerberos::{
+        ccache_file::KerberosEnvironment,
+        pkinit::PkinitCertificate,
+        tgs::service_tickets_for,
+        ticket::{encode_ticket, request_tgt},
+    },
+    stores::domain_controller_store::DomainControllerStore,
+    utils::{
+        dns_operations::dc_fqdn,
+        ldap_operations::domain_to_base_dn,
+        smb_operations::ntlm_secret,
+    },
+};
+
+/// Result code of binds rejected because the DC requires signing or TLS
+const LDAP_STRONGER_AUTH_REQUIRED: u32 = 8;
+/// Result code of searches stopped by the size limit
+const LDAP_SIZE_LIMIT_EXCEEDED: u32 = 4;
+/// Entries per page of paged searches, the default MaxPageSize of domain controllers
+const PAGE_SIZE: i32 = 1000;
+
+/// How a session is bound
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LdapBind {
+    Anonymous,
+    Simple,
+    Ntlm,
+    Kerberos,
+    Certificate,
+}
+
+/// An open LDAP connection bound to the domain controller. Clones share the connection.
+#[derive(Clone)]
+pub struct LdapSession {
+    ldap: Ldap,
+    pub base_dn: String,
+    pub bind: LdapBind,
+    pub tls: bool,
+}
+
+fn bind_result(result: Result<LdapResult, ldap3::LdapError>) -> Result<(), String> {
+    result
+        .map_err(|e| e.to_string())?
+        .success()
+        .map_err(|e| format!("LDAP bind failed: {}", e))?;
+    Ok(())
+}
+
+/// Connection settings: domain controllers mostly serve self-signed certificates
+fn connection_settings() -> LdapConnSettings {
+    LdapConnSettings::new().set_no_tls_verify(true)
+}
+
+async fn ldap_open(dc: &DomainController, settings: LdapConnSettings) -> Result<Ldap, String> {
+    let (conn, ldap) = LdapConnAsync::with_settings(settings, dc.ldap_url().as_str())
+        .await
+        .map_err(|e| e.to_string())?;
+    ldap3::drive!(conn);
+    Ok(ldap)
+}
+
+async fn ldap_bind_kerberos(
+    ldap: &mut Ldap,
+    dc_storage: &DomainControllerStore,
+    dc: &DomainController,
+    creds: &Credential,
+) -> Result<(), String> {
+    let ticket_data = match &creds.auth_data {
+        AuthData::KerberosTicket(data) => data.clone(),
+        _ => encode_ticket(request_tgt(dc_storage, dc, creds).await?),
+    };
+    let fqdn = dc_fqdn(dc).await?;
+    let tickets = service_tickets_for(dc, &ticket_data, &format!("ldap/{}", fqdn)).await?;
+    let _environment = KerberosEnvironment::acquire(dc, tickets).await?;
+    bind_result(ldap.sasl_gssapi_bind(&fqdn).await)
+}
+
+async fn ldap_bind_ntlm(ldap: &mut Ldap, dc: &DomainController, creds: &Credential) -> Result<(), String> {
+    let secret = ntlm_secret(creds)?;
+    bind_result(
+        ldap.sasl_ntlm_bind(creds.domain_username(&dc.domain_name).as_str(), secret.as_str())
+            .await,
+    )
+}
+
+/// TLS connector presenting the certificate as client certificate
+fn certificate_connector(certificate: &PkinitCertificate) -> Result<TlsConnector, String> {
+    let pkcs12 = Pkcs12::builder()
+        .name("hydraad")
+        .pkey(&certificate.key)
+        .cert(&certificate.certificate)
+        .build2("")
+        .and_then(|pkcs12| pkcs12.to_der())
+        .map_err(|e| e.to_string())?;
+    let identity = Identity::from_pkcs12(&pkcs12, "").map_err(|e| e.to_string())?;
+    TlsConnector::builder()
+        .identity(identity)
+        .danger_accept_invalid_certs(true)
+        .danger_accept_invalid_hostnames(true)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+impl LdapSession {
+    /// Unauthenticated session, enough to read the rootDSE
+    pub async fn anonymous(dc: &DomainController) -> Result<Self, String> {
+        Ok(Self {
+            ldap: ldap_open(dc, connection_settings()).await?,
+            base_dn: domain_to_base_dn(&dc.domain_name),
+            bind: LdapBind::Anonymous,
+            tls: dc.ldaps_enabled,
+        })
+    }
+
+    /// Open a connection to the domain controller and bind with the given credential and method.
+    /// With `AuthMethod::Auto` certificates are bound over TLS, tickets use kerberos and other
+    /// credentials try kerberos before falling back to NTLM, both sealing the connection.
+    /// Simple binds are only made when requested with `AuthMethod::Simple`.
+    pub async fn connect(
+        dc_storage: &DomainControllerStore,
+        dc: &DomainController,
+        creds: &Credential,
+        auth: AuthMethod,
+    ) -> Result<Self, String> {
+        if let AuthData::Certificate { .. } = &creds.auth_data {
+            return Self::connect_certificate(dc, &PkinitCertificate::from_auth_data(&creds.auth_data)?).await;
+        }
+
+        let mut ldap = ldap_open(dc, connection_settings()).await?;
+        let bind = match (&creds.auth_data, auth) {
+            (AuthData::Password(pass), AuthMethod::Simple) => {
+                if !dc.ldaps_enabled {
+                    println!("Warning: simple bind without LDAPS, the password is sent in clear text");
+                }
+                match ldap.simple_bind(creds.upn(&dc.domain_name).as_str(), pass.as_str()).await {
+                    Ok(result) if result.rc == LDAP_STRONGER_AUTH_REQUIRED => {
+                        return Err("The DC requires LDAP signing, use --auth ntlm or kerberos".to_string());
+                    }
+                    result => {
+                        bind_result(result)?;
+                        LdapBind::Simple
+                    }
+                }
+            }
+            (_, AuthMethod::Simple) => {
+                return Err("Simple binds require a password credential".to_string());
+            }
+            (AuthData::KerberosTicket(_), AuthMethod::Ntlm) => {
+                return Err("NTLM authentication can't use a kerberos ticket".to_string());
+            }
+            (AuthData::KerberosTicket(_), _) | (_, AuthMethod::Kerberos) => {
+                ldap_bind_kerberos(&mut ldap, dc_storage, dc, creds).await?;
+                LdapBind::Kerberos
+            }
+            (_, AuthMethod::Ntlm) => {
+                ldap_bind_ntlm(&mut ldap, dc, creds).await?;
+                LdapBind::Ntlm
+            }
+            (_, AuthMethod::Auto) => match ldap_bind_kerberos(&mut ldap, dc_storage, dc, creds).await {
+                Ok(()) => LdapBind::Kerberos,
+                Err(e) => {
+                    println!("Kerberos authentication failed ({}), falling back to NTLM", e);
+                    ldap = ldap_open(dc, connection_settings()).await?;
+                    ldap_bind_ntlm(&mut ldap, dc, creds).await?;
+                    LdapBind::Ntlm
+                }
+            },
+        };
+
+        Ok(Self {
+            ldap,
+            base_dn: domain_to_base_dn(&dc.domain_name),
+            bind,
+            tls: dc.ldaps_enabled,
+        })
+    }
+
+    /// Bind with a client certificate over LDAPS, or StartTLS when the DC isn't set up for LDAPS
+    pub async fn connect_certificate(dc: &DomainController, certificate: &PkinitCertificate) -> Result<Self, String> {
+        let settings = connection_settings()
+            .set_connector(certificate_connector(certificate)?)
+            .set_starttls(!dc.ldaps_enabled);
+        let mut ldap = ldap_open(dc, settings).await?;
+        bind_result(ldap.sasl_external_bind().await)?;
+        Ok(Self {
+            ldap,
+            base_dn: domain_to_base_dn(&dc.domain_name),
+            bind: LdapBind::Certificate,
+            tls: true,
+        })
+    }
+
+    /// How the traffic of the session is protected
+    pub fn protection(&self) -> &'static str {
+        match (self.bind, self.tls) {
+            (LdapBind::Kerberos | LdapBind::Ntlm, true) => "TLS with channel binding",
+            (LdapBind::Kerberos | LdapBind::Ntlm, false) => "SASL signing and sealing",
+            (_, true) => "TLS",
+            (_, false) => "none",
+        }
+    }
+
+    /// Whether the connection was closed by either side
+    pub fn is_closed(&mut self) -> bool {
+        self.ldap.is_closed()
+    }
+
+    /// The underlying connection, for operations the session doesn't wrap
+    pub fn ldap(&mut self) -> &mut Ldap {
+        &mut self.ldap
+    }
+
+    /// Run a search under `base`, paged so it returns every matching entry
+    pub async fn search_in(
+        &mut self,
+        base: &str,
+        scope: Scope,
+        filter: &str,
+        attributes: Vec<&str>,
+    ) -> Result<Vec<SearchEntry>, String> {
+        self.paged_search(base, scope, filter, attributes, None).await
+    }
+
+    /// Run a subtree search under the domain's base DN
+    pub async fn search(&mut self, filter: &str, attributes: Vec<&str>) -> Result<Vec<SearchEntry>, String> {
+        let base_dn = self.base_dn.clone();
+        self.search_in(&base_dn, Scope::Subtree, filter, attributes).await
+    }
+
+    /// Run a search with the Simple Paged Results control, so results aren't cut at the
+    /// MaxPageSize of the DC. `limit` caps the number of returned entries.
+    pub async fn paged_search(
+        &mut self,
+        base: &str,
+        scope: Scope,
+        filter: &str,
+        attributes: Vec<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<SearchEntry>, String> {
+        let page_size = limit.map_or(PAGE_SIZE, |limit| PAGE_SIZE.min(limit.max(1) as i32));
+        let adapters: Vec<Box<dyn Adapter<_, _>>> = vec![
+            Box::new(EntriesOnly::new()),
+            Box::new(PagedResults::new(page_size)),
+        ];
+
+        let mut ldap = self.ldap.clone();
+        if let Some(limit) = limit {
+            ldap.with_search_options(SearchOptions::new().sizelimit(limit as i32));
+        }
+        let mut search = ldap
+            .streaming_search_with(adapters, base, scope, filter, attributes)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let mut entries = Vec::new();
+        while let Some(entry) = search.next().await.map_err(|e| e.to_string())? {
+            entries.push(SearchEntry::construct(entry));
+        }
+        let result = search.finish().await;
+        if result.rc != LDAP_SIZE_LIMIT_EXCEEDED {
+            result.success().map_err(|e| e.to_string())?;
+        }
+
+        if let Some(limit) = limit {
+            entries.truncate(limit);
+        }
+        Ok(entries)
+    }
+
+    /// Read attributes of the rootDSE, without the paged results control which not every server
+    /// accepts there
+    pub async fn root_dse(&mut self, attributes: Vec<&str>) -> Result<SearchEntry, String> {
+        let (rs, _res) = self
+            .ldap
+            .search("", Scope::Base, "(objectClass=*)", attributes)
+            .await
+            .map_err(|e| e.to_string())?
+            .success()
+            .map_err(|e| e.to_string())?;
+        rs.into_iter()
+            .next()
+            .map(SearchEntry::construct)
+            .ok_or("Empty rootDSE".to_string())
+    }
+
+    /// Identity the DC sees for this session (Who Am I extended operation)
+    pub async fn whoami(&mut self) -> Result<String, String> {
+        let (exop, _res) = self
+            .ldap
+            .extended(WhoAmI)
+            .await
+            .map_err(|e| e.to_string())?
+            .success()
+            .map_err(|e| e.to_string())?;
+        let response: WhoAmIResp = exop.parse();
+        Ok(response.authzid)
+    }
+
+    pub async fn unbind(&mut self) -> Result<(), String> {
+        self.ldap.unbind().await.map_err(|e| e.to_string())
+    }
+}
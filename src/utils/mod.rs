@@ -1,2 +1,7 @@
 pub mod cli_utils;
-pub mod dns_operations;
\ No newline at end of file
+pub mod dns_operations;
+pub mod ldap_groups;
+pub mod ldap_operations;
+pub mod ldap_session;
+pub mod nxc_workspace;
+pub mod smb_operations;
\ No newline at end of file
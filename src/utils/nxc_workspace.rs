@@ -0,0 +1,234 @@
+use std::{collections::HashMap, path::Path};
+
+use rusqlite::{types::Value, Connection, OpenFlags};
+
+/// Protocol databases of a NetExec workspace we know how to import
+pub const NXC_PROTOCOLS: &[&str] = &["smb", "ldap", "mssql"];
+
+#[derive(Debug, Clone)]
+pub struct NxcUser {
+    pub id: i64,
+    pub domain: String,
+    pub username: String,
+    pub password: String,
+    /// "plaintext" or "hash"
+    pub credtype: String,
+    pub pillaged_from_hostid: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NxcHost {
+    pub id: i64,
+    pub ip: String,
+    pub hostname: String,
+    pub domain: String,
+    pub os: String,
+    pub is_dc: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NxcDatabase {
+    pub protocol: String,
+    pub users: Vec<NxcUser>,
+    pub hosts: Vec<NxcHost>,
+    /// (user id, host id) pairs where the user is a local admin
+    pub admin_relations: Vec<(i64, i64)>,
+}
+
+impl NxcDatabase {
+    pub fn host(&self, id: i64) -> Option<&NxcHost> {
+        self.hosts.iter().find(|h| h.id == id)
+    }
+
+    /// Hosts the given user has admin rights on
+    pub fn admin_hosts(&self, user_id: i64) -> Vec<&NxcHost> {
+        self.admin_relations
+            .iter()
+            .filter(|(uid, _)| *uid == user_id)
+            .filter_map(|(_, hid)| self.host(*hid))
+            .collect()
+    }
+}
+
+/// DNS domain of a NetBIOS domain name, from known DNS domains whose first label matches it.
+/// Names which already are DNS domains are returned as is.
+pub fn dns_domain(domain: &str, dns_domains: &[String]) -> Option<String> {
+    if domain.contains('.') {
+        return Some(domain.to_lowercase());
+    }
+    dns_domains
+        .iter()
+        .filter(|dns| dns.contains('.'))
+        .find(|dns| dns.split('.').next().is_some_and(|label| label.eq_ignore_ascii_case(domain)))
+        .map(|dns| dns.to_lowercase())
+}
+
+fn value_to_string(value: Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::Integer(i) => Some(i.to_string()),
+        Value::Real(r) => Some(r.to_string()),
+        Value::Text(t) => Some(t),
+        Value::Blob(b) => Some(String::from_utf8_lossy(&b).to_string()),
+    }
+}
+
+fn table_columns(conn: &Connection, table: &str) -> Result<Vec<String>, String> {
+    let mut stmt = conn
+        .prepare(&format!("PRAGMA table_info({})", table))
+        .map_err(|e| e.to_string())?;
+    let columns = stmt
+        .query_map([], |row| row.get::<_, String>(1))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(columns)
+}
+
+/// Read all rows of a table as column name -> value maps.
+/// NetExec changes its schemas between versions, so only the columns present are read.
+fn read_table(conn: &Connection, table: &str) -> Result<Vec<HashMap<String, String>>, String> {
+    let columns = table_columns(conn, table)?;
+    if columns.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut stmt = conn
+        .prepare(&format!("SELECT {} FROM {}", columns.join(", "), table))
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map([], |row| {
+            let mut map = HashMap::new();
+            for (i, column) in columns.iter().enumerate() {
+                if let Some(value) = value_to_string(row.get::<_, Value>(i)?) {
+                    map.insert(column.clone(), value);
+                }
+            }
+            Ok(map)
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(rows)
+}
+
+fn get_string(row: &HashMap<String, String>, key: &str) -> String {
+    row.get(key).cloned().unwrap_or_default()
+}
+
+fn get_id(row: &HashMap<String, String>, key: &str) -> Option<i64> {
+    row.get(key).and_then(|v| v.parse().ok())
+}
+
+/// Load a single NetExec protocol database (e.g. ~/.nxc/workspaces/default/smb.db)
+pub fn load_nxc_database<P: AsRef<Path>>(path: P) -> Result<NxcDatabase, String> {
+    let path = path.as_ref();
+    let protocol = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+
+    // The LDAP protocol stores its users in a "credentials" table
+    let user_table = if table_columns(&conn, "users")?.is_empty() { "credentials" } else { "users" };
+    let users = read_table(&conn, user_table)?
+        .iter()
+        .filter_map(|row| {
+            Some(NxcUser {
+                id: get_id(row, "id")?,
+                domain: get_string(row, "domain"),
+                username: get_string(row, "username"),
+                password: get_string(row, "password"),
+                credtype: row.get("credtype").cloned().unwrap_or("plaintext".to_string()),
+                pillaged_from_hostid: get_id(row, "pillaged_from_hostid"),
+            })
+        })
+        .filter(|u| !u.username.is_empty() && !u.password.is_empty())
+        .collect();
+
+    let hosts = read_table(&conn, "hosts")?
+        .iter()
+        .filter_map(|row| {
+            Some(NxcHost {
+                id: get_id(row, "id")?,
+                ip: get_string(row, "ip"),
+                hostname: get_string(row, "hostname"),
+                domain: get_string(row, "domain"),
+                os: get_string(row, "os"),
+                // Every host in the LDAP database is a domain controller
+                is_dc: protocol == "ldap" || matches!(row.get("dc").map(|s| s.as_str()), Some("1") | Some("True") | Some("true")),
+            })
+        })
+        .collect();
+
+    let admin_relations = read_table(&conn, "admin_relations")?
+        .iter()
+        .filter_map(|row| Some((get_id(row, "userid")?, get_id(row, "hostid")?)))
+        .collect();
+
+    Ok(NxcDatabase {
+        protocol,
+        users,
+        hosts,
+        admin_relations,
+    })
+}
+
+/// Load every known protocol database from a workspace directory, or a single database file
+pub fn load_nxc_workspace<P: AsRef<Path>>(path: P) -> Result<Vec<NxcDatabase>, String> {
+    let path = path.as_ref();
+    if path.is_file() {
+        return Ok(vec![load_nxc_database(path)?]);
+    }
+
+    let databases = NXC_PROTOCOLS
+        .iter()
+        .map(|protocol| path.join(format!("{}.db", protocol)))
+        .filter(|db| db.is_file())
+        .map(load_nxc_database)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if databases.is_empty() {
+        return Err(format!("No NetExec databases found in {}", path.display()));
+    }
+    Ok(databases)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_load_nxc_workspace() {
+        let workspace = std::env::temp_dir().join(format!("hydraad_nxc_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&workspace).unwrap();
+        let conn = Connection::open(workspace.join("smb.db")).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE users (id INTEGER, domain TEXT, username TEXT, password TEXT, credtype TEXT, pillaged_from_hostid INTEGER);
+             CREATE TABLE hosts (id INTEGER, ip TEXT, hostname TEXT, domain TEXT, os TEXT, dc BOOLEAN);
+             CREATE TABLE admin_relations (id INTEGER, userid INTEGER, hostid INTEGER);
+             INSERT INTO users VALUES (1, 'VOLEUR', 'svc_ldap', 'M1XyC9pW7qT5Vn', 'plaintext', NULL);
+             INSERT INTO users VALUES (2, 'VOLEUR', 'Administrator', 'e656e07c56d831611b577b160b259ad2', 'hash', 1);
+             INSERT INTO hosts VALUES (1, '10.10.11.76', 'DC', 'voleur.htb', 'Windows Server 2022', 1);
+             INSERT INTO admin_relations VALUES (1, 2, 1);",
+        )
+        .unwrap();
+        drop(conn);
+
+        let databases = load_nxc_workspace(&workspace).unwrap();
+        std::fs::remove_dir_all(&workspace).unwrap();
+        assert_eq!(databases.len(), 1);
+        let db = &databases[0];
+        assert_eq!(db.protocol, "smb");
+        assert_eq!(db.users.len(), 2);
+        assert_eq!(db.users[1].credtype, "hash");
+        assert!(db.hosts[0].is_dc);
+        assert_eq!(db.admin_hosts(2)[0].hostname, "DC");
+
+        let dns_domains: Vec<String> = db.hosts.iter().map(|h| h.domain.clone()).collect();
+        assert_eq!(dns_domain(&db.users[0].domain, &dns_domains).as_deref(), Some("voleur.htb"));
+        assert_eq!(dns_domain("VOLEUR.HTB", &[]).as_deref(), Some("voleur.htb"));
+        assert_eq!(dns_domain("CORP", &dns_domains), None);
+    }
+}
@@ -0,0 +1,177 @@
+use std::net::{IpAddr, SocketAddr};
+
+use smb::{connection::AuthMethodsConfig, Client, ClientConfig, ConnectionConfig};
+use sspi::{AuthIdentity, Username};
+
+use crate::{
+    data::{credential::AuthMethod, AuthData, Credential, DomainController},
+    kerberos::{
+        relay::TicketRelay,
+        ticket::{decode_ticket, request_tgt, session_ticket_from_ccache, SessionTicket},
+    },
+    stores::domain_controller_store::DomainControllerStore,
+    utils::dns_operations::dc_fqdn,
+};
+
+/// Password length from which sspi (like WinPR) treats the password as an NT hash
+const SSPI_CREDENTIALS_HASH_LENGTH_OFFSET: usize = 512;
+
+/// Identity to authenticate SMB sessions with.
+/// Keeps the ticket relay alive for as long as the identity is used.
+pub struct SmbIdentity {
+    pub username: String,
+    pub password: String,
+    pub identity: AuthIdentity,
+    _relay: Option<TicketRelay>,
+}
+
+/// An authenticated SMB connection to the domain controller
+pub struct SmbSession {
+    pub client: Client,
+    pub identity: SmbIdentity,
+    /// "ip:port" path of the server
+    pub server_path: String,
+    pub method: AuthMethod,
+}
+
+/// Secret to give sspi for NTLM: the password, or the NT hash padded past
+/// SSPI_CREDENTIALS_HASH_LENGTH_OFFSET so it is used as is (pass-the-hash)
+pub fn ntlm_secret(creds: &Credential) -> Result<String, String> {
+    match &creds.auth_data {
+        AuthData::Password(pass) => Ok(pass.clone()),
+        AuthData::NtlmHash(_) | AuthData::LmNtlm { .. } | AuthData::KerberosKey { .. } => {
+            let hash = creds.nt_hash().ok_or("NTLM authentication requires an NT hash or RC4 key".to_string())?;
+            Ok(format!("{:\0<width$}", hash, width = SSPI_CREDENTIALS_HASH_LENGTH_OFFSET + 1))
+        }
+        _ => Err("NTLM authentication requires a password or NTLM hash credential".to_string()),
+    }
+}
+
+fn check_cifs_ticket(ticket: &SessionTicket) -> Result<(), String> {
+    if !ticket.is_tgt() && !ticket.server_name.name_string.first().map_or(false, |s| s.eq_ignore_ascii_case("cifs")) {
+        return Err(format!("Stored ticket is for {}, not cifs", ticket.server_principal()));
+    }
+    Ok(())
+}
+
+/// Build the sspi identity of a credential for the given method, pointing sspi at the ticket relay for kerberos
+pub async fn smb_identity(
+    dc_storage: &DomainControllerStore,
+    dc: &DomainController,
+    creds: &Credential,
+    method: AuthMethod,
+) -> Result<SmbIdentity, String> {
+    let ticket = match (&creds.auth_data, method) {
+        (AuthData::KerberosTicket(_), AuthMethod::Ntlm) => {
+            return Err("NTLM authentication can't use a kerberos ticket".to_string());
+        }
+        (AuthData::KerberosTicket(data), _) => Some(decode_ticket(data)?),
+        // The TGT is requested through the KDC transport (failover, UDP, proxy) and handed to sspi
+        // by the relay, which also forwards its TGS-REQs. This covers overpass-the-hash too, sspi
+        // can't derive kerberos keys from a hash.
+        (
+            AuthData::Password(_) | AuthData::NtlmHash(_) | AuthData::LmNtlm { .. } | AuthData::KerberosKey { .. },
+            AuthMethod::Kerberos | AuthMethod::Auto,
+        ) => {
+            Some(session_ticket_from_ccache(&request_tgt(dc_storage, dc, creds).await?)?)
+        }
+        _ => None,
+    };
+
+    let (username, realm, password, relay) = match ticket {
+        Some(ticket) => {
+            check_cifs_ticket(&ticket)?;
+            let username = ticket.client_name.name_string.join("/");
+            let realm = ticket.client_realm.clone();
+            let relay = TicketRelay::start(dc, &username, ticket).await?;
+            (username, realm, relay.password().to_string(), Some(relay))
+        }
+        None => (creds.username.clone(), dc.domain_name.clone(), ntlm_secret(creds)?, None),
+    };
+
+    let identity = AuthIdentity {
+        username: Username::new(username.as_str(), Some(realm.as_str())).map_err(|e| e.to_string())?,
+        password: password.clone().into(),
+    };
+
+    Ok(SmbIdentity {
+        username,
+        password,
+        identity,
+        _relay: relay,
+    })
+}
+
+async fn smb_connect_with(
+    dc_storage: &DomainControllerStore,
+    dc: &DomainController,
+    creds: &Credential,
+    method: AuthMethod,
+) -> Result<SmbSession, String> {
+    let client = Client::new(ClientConfig {
+        connection: ConnectionConfig {
+            auth_methods: AuthMethodsConfig {
+                ntlm: method == AuthMethod::Ntlm,
+                kerberos: method != AuthMethod::Ntlm,
+            },
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+
+    let identity = smb_identity(dc_storage, dc, creds, method).await?;
+
+    let socket_addr = match dc.ip_address {
+        IpAddr::V4(_) => SocketAddr::new(dc.ip_address, 445),
+        IpAddr::V6(_) => return Err("Unsupported IP address type".to_string()),
+    };
+
+    // Kerberos needs the host name for the cifs/ SPN, NTLM can target the IP address
+    let server_name = match method {
+        AuthMethod::Ntlm => dc.ip_address.to_string(),
+        _ => dc_fqdn(dc).await?,
+    };
+
+    let connection = client
+        .connect_to_address(&server_name, socket_addr)
+        .await
+        .map_err(|e| e.to_string())?;
+    println!("Connection established");
+
+    connection
+        .authenticate(identity.identity.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(SmbSession {
+        client,
+        identity,
+        server_path: format!("{}:445", dc.ip_address),
+        method,
+    })
+}
+
+/// Connect and authenticate to the domain controller over SMB.
+/// With `AuthMethod::Auto` kerberos is tried first and NTLM is used when it fails.
+pub async fn smb_connect(
+    dc_storage: &DomainControllerStore,
+    dc: &DomainController,
+    creds: &Credential,
+    auth: AuthMethod,
+) -> Result<SmbSession, String> {
+    if auth == AuthMethod::Simple {
+        return Err("Simple authentication is only available for LDAP".to_string());
+    }
+    if auth != AuthMethod::Auto {
+        return smb_connect_with(dc_storage, dc, creds, auth).await;
+    }
+
+    match smb_connect_with(dc_storage, dc, creds, AuthMethod::Kerberos).await {
+        Ok(session) => Ok(session),
+        Err(e) if !matches!(creds.auth_data, AuthData::KerberosTicket(_)) => {
+            println!("Kerberos authentication failed ({}), falling back to NTLM", e);
+            smb_connect_with(dc_storage, dc, creds, AuthMethod::Ntlm).await
+        }
+        Err(e) => Err(e),
+    }
+}